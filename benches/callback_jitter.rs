@@ -0,0 +1,139 @@
+// how long the audio callback takes (and how much that varies) while another
+// thread keeps changing what's playing, with the source list shared behind a
+// Mutex (as the mixer used to be) vs. owned by the audio thread and changed
+// through a channel drained at the top of each callback (as it is now).
+//
+// the crate is only a binary, so this can't reach the real Mixer; instead it
+// mixes plain sine voices the same way, which is enough to show the effect of
+// the handoff. run with `cargo bench --bench callback_jitter`.
+
+use std::{
+    f64::consts::PI,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// about what cpal asks for at a time, at 44.1 kHz
+const FRAMES_PER_CALLBACK: usize = 512;
+const CALLBACKS: usize = 2000;
+const VOICES: usize = 32;
+
+struct Voice {
+    phase: f64,
+    step: f64,
+    gain: f64,
+}
+
+impl Voice {
+    fn new(i: usize) -> Self {
+        Self {
+            phase: 0.0,
+            step: 2.0 * PI * (220.0 + 10.0 * i as f64) / 44100.0,
+            gain: 1.0 / VOICES as f64,
+        }
+    }
+}
+
+enum Command {
+    Gain(usize, f64),
+}
+
+fn mix(voices: &mut [Voice], buffer: &mut [f64]) {
+    for sample in buffer.iter_mut() {
+        *sample = voices
+            .iter_mut()
+            .map(|voice| {
+                voice.phase += voice.step;
+                voice.phase.sin() * voice.gain
+            })
+            .sum();
+    }
+}
+
+// stands in for the game changing gains every chance it gets, which is worse
+// than any real game would do, but makes contention easy to see
+fn keep_busy<F: FnMut(usize) + Send + 'static>(running: Arc<AtomicBool>, mut f: F) {
+    thread::spawn(move || {
+        let mut i = 0;
+        while running.load(Ordering::Relaxed) {
+            f(i % VOICES);
+            i += 1;
+            thread::yield_now();
+        }
+    });
+}
+
+fn shared(running: Arc<AtomicBool>) -> Vec<Duration> {
+    let voices = Arc::new(Mutex::new((0..VOICES).map(Voice::new).collect::<Vec<_>>()));
+
+    let game_voices = voices.clone();
+    keep_busy(running, move |i| {
+        game_voices.lock().unwrap()[i].gain = 1.0 / VOICES as f64;
+    });
+
+    let mut buffer = vec![0.0; FRAMES_PER_CALLBACK];
+    time_callbacks(|| mix(&mut voices.lock().unwrap(), &mut buffer))
+}
+
+fn owned(running: Arc<AtomicBool>) -> Vec<Duration> {
+    let mut voices: Vec<_> = (0..VOICES).map(Voice::new).collect();
+    // bounded, so the game blocks once it's a few changes ahead (much like it
+    // waits on the lock in shared()) instead of piling up a backlog that
+    // every callback would then have to drain
+    let (sender, receiver): (SyncSender<Command>, Receiver<Command>) = mpsc::sync_channel(VOICES);
+
+    keep_busy(running, move |i| {
+        let _ = sender.send(Command::Gain(i, 1.0 / VOICES as f64));
+    });
+
+    let mut buffer = vec![0.0; FRAMES_PER_CALLBACK];
+    time_callbacks(|| {
+        while let Ok(Command::Gain(i, gain)) = receiver.try_recv() {
+            voices[i].gain = gain;
+        }
+        mix(&mut voices, &mut buffer);
+    })
+}
+
+fn time_callbacks<F: FnMut()>(mut callback: F) -> Vec<Duration> {
+    let mut times: Vec<_> = (0..CALLBACKS)
+        .map(|_| {
+            let start = Instant::now();
+            callback();
+            start.elapsed()
+        })
+        .collect();
+
+    times.sort();
+    times
+}
+
+fn report(name: &str, times: &[Duration]) {
+    let percentile = |p: usize| times[(times.len() - 1) * p / 100];
+    println!(
+        "{:>6}: median {:?}, p99 {:?}, max {:?} (jitter: {:?})",
+        name,
+        percentile(50),
+        percentile(99),
+        percentile(100),
+        percentile(99) - percentile(50),
+    );
+}
+
+fn main() {
+    for &(name, bench) in &[
+        ("mutex", shared as fn(Arc<AtomicBool>) -> Vec<Duration>),
+        ("owned", owned),
+    ] {
+        let running = Arc::new(AtomicBool::new(true));
+        let times = bench(running.clone());
+        running.store(false, Ordering::Relaxed);
+
+        report(name, &times);
+    }
+}
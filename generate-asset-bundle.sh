@@ -0,0 +1,36 @@
+#!/bin/sh
+# usage: ./generate-asset-bundle.sh bundle.bin asset1.ogg asset2.wav ...
+# (see src/assets/bundle.rs for the format)
+
+set -e
+
+[ $# -ge 1 ] || { echo "usage: $0 OUTPUT [ASSET]..." >&2; exit 1; }
+OUTPUT="$1"
+shift
+
+# little-endian integer of the given width in bytes
+le() {
+    N="$1"
+    for _ in $(seq "$2"); do
+        printf "\\$(printf %03o $((N & 255)))"
+        N=$((N >> 8))
+    done
+}
+
+(
+    printf 'PLNTBNDL'
+    le $# 4
+
+    OFFSET=0
+    for FILE in "$@"; do
+        NAME="$(basename "${FILE}")"
+        LEN="$(wc -c < "${FILE}")"
+        le "$(printf %s "${NAME}" | wc -c)" 2
+        printf %s "${NAME}"
+        le "${OFFSET}" 8
+        le "${LEN}" 8
+        OFFSET=$((OFFSET + LEN))
+    done
+
+    cat "$@"
+) > "${OUTPUT}"
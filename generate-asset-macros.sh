@@ -9,12 +9,28 @@ find_cmd() {
     echo '#![allow(non_upper_case_globals)]'
     echo '#![allow(dead_code)]'
     echo
+    echo 'mod bundle;'
+    echo 'mod load;'
+    echo
+    echo '#[cfg(feature = "engine-api")]'
+    echo 'pub use bundle::{Bundle, BundleError};'
+    echo '#[cfg(feature = "engine-api")]'
+    echo 'pub use load::AssetError;'
+    echo
     echo 'pub enum Asset {'
     find_cmd | rev | cut -d. -f1 | sort | uniq | rev \
     | sed "s/^\(.\)\(.*\)$/    \u\1\L\2\E(\&'static [u8]),/"
     echo '}'
     echo
     echo 'impl Asset {'
+    echo '    pub fn from_extension(extension: &str, data: &'"'"'static [u8]) -> Option<Self> {'
+    echo '        match extension {'
+    find_cmd | rev | cut -d. -f1 | sort | uniq | rev \
+    | sed "s/^\(.\)\(.*\)$/            \"\1\2\" => Some(Asset::\u\1\L\2\E(data)),/"
+    echo '            _ => None,'
+    echo '        }'
+    echo '    }'
+    echo
     find_cmd | rev | cut -d. -f1 | sort | uniq | rev | while read -r EXT; do
         [ -z "${EXT}" ] && continue
         EXT_TITLE_CASE="$(echo "${EXT}" | sed 's/^\(.\)\(.*\)$/\u\1\L\2/')"
@@ -1,26 +1,54 @@
 #version 450
 
+// see PointShape in src/render/particles.rs
+layout(constant_id = 0) const int point_shape = 0;
+// see Render::set_particle_alpha
+layout(constant_id = 1) const float particle_alpha = 1.0;
+
+layout(location = 0) in float p_hue;
+
 layout(location = 0) out vec4 f_color;
 
-void main() {
-    f_color = vec4(1);
+vec4 hue_color() {
+    // negative hues mean "no color"
+    if (p_hue < 0.0) {
+        return vec4(1);
+    }
 
-    /*float hue = mod((p_hue * 6.0), 6.0);
+    float hue = mod((p_hue * 6.0), 6.0);
     float interp = 1.0 - abs(mod(hue, 2.0) - 1.0);
 
     if (0.0 <= hue && hue < 1.0) {
-        f_color = vec4(1, interp, 0, 1);
+        return vec4(1, interp, 0, 1);
     } else if (1.0 <= hue && hue < 2.0) {
-        f_color = vec4(interp, 1, 0, 1);
+        return vec4(interp, 1, 0, 1);
     } else if (2.0 <= hue && hue < 3.0) {
-        f_color = vec4(0, 1, interp, 1);
+        return vec4(0, 1, interp, 1);
     } else if (3.0 <= hue && hue < 4.0) {
-        f_color = vec4(0, interp, 1, 1);
+        return vec4(0, interp, 1, 1);
     } else if (4.0 <= hue && hue < 5.0) {
-        f_color = vec4(interp, 0, 1, 1);
+        return vec4(interp, 0, 1, 1);
     } else if (5.0 <= hue && hue < 6.0) {
-        f_color = vec4(1, 0, interp, 1);
+        return vec4(1, 0, interp, 1);
     } else {
-        f_color = vec4(1, 1, 1, 1);
-    }*/
+        return vec4(1, 1, 1, 1);
+    }
+}
+
+void main() {
+    f_color = hue_color();
+
+    if (point_shape != 0) {
+        // 0.0 at the center of the point, 1.0 at the middle of its edges
+        float r = length(gl_PointCoord - 0.5) * 2.0;
+        if (r > 1.0) {
+            discard;
+        }
+
+        if (point_shape == 2) {
+            f_color.a *= 1.0 - smoothstep(0.5, 1.0, r);
+        }
+    }
+
+    f_color.a *= particle_alpha;
 }
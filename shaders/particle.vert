@@ -2,8 +2,12 @@
 
 layout(location = 0) in vec2 position;
 layout(location = 1) in vec2 velocity;
+layout(location = 2) in float hue;
+
+layout(location = 0) out float p_hue;
 
 void main() {
     gl_PointSize = 5;
     gl_Position = vec4(position, 0.0, 1.0);
+    p_hue = hue;
 }
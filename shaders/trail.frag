@@ -0,0 +1,10 @@
+#version 450
+
+layout(location = 0) in float t_alpha;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    // straight alpha, like particle.frag (see create_graphics_pipeline)
+    f_color = vec4(1, 1, 1, t_alpha);
+}
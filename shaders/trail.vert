@@ -0,0 +1,11 @@
+#version 450
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in float alpha;
+
+layout(location = 0) out float t_alpha;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+    t_alpha = alpha;
+}
@@ -2,6 +2,14 @@
 #![allow(non_upper_case_globals)]
 #![allow(dead_code)]
 
+mod bundle;
+mod load;
+
+#[cfg(feature = "engine-api")]
+pub use bundle::{Bundle, BundleError};
+#[cfg(feature = "engine-api")]
+pub use load::AssetError;
+
 pub enum Asset {
     Ogg(&'static [u8]),
     Txt(&'static [u8]),
@@ -9,6 +17,15 @@ pub enum Asset {
 }
 
 impl Asset {
+    pub fn from_extension(extension: &str, data: &'static [u8]) -> Option<Self> {
+        match extension {
+            "ogg" => Some(Asset::Ogg(data)),
+            "txt" => Some(Asset::Txt(data)),
+            "wav" => Some(Asset::Wav(data)),
+            _ => None,
+        }
+    }
+
     pub fn ogg_data(&self) -> &'static [u8] {
         if let Asset::Ogg(data) = self {
             data
@@ -0,0 +1,241 @@
+use std::{collections::HashMap, convert::TryInto, fmt, fs, io, ops::Range, path::Path, str};
+
+use super::Asset;
+
+// see generate-asset-bundle.sh for how these are made. the layout is:
+//   magic: b"PLNTBNDL"
+//   count: u32
+//   count entries of:
+//     name length: u16
+//     name: UTF-8, e.g. "vlem0.ogg"
+//     offset: u64, from the end of the entry table
+//     length: u64
+//   the assets' contents, back to back
+// all integers are little-endian.
+const MAGIC: &[u8] = b"PLNTBNDL";
+
+#[derive(Debug)]
+pub enum BundleError {
+    Io(io::Error),
+    NotABundle,
+    Truncated,
+    BadName,
+    UnknownType(String),
+}
+
+impl fmt::Display for BundleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BundleError::Io(e) => write!(f, "failed to read asset bundle: {}", e),
+            BundleError::NotABundle => write!(f, "not an asset bundle"),
+            BundleError::Truncated => write!(f, "asset bundle is truncated"),
+            BundleError::BadName => write!(f, "asset bundle has a non-UTF-8 asset name"),
+            BundleError::UnknownType(name) => write!(f, "unknown asset type for {}", name),
+        }
+    }
+}
+
+impl From<io::Error> for BundleError {
+    fn from(e: io::Error) -> Self {
+        BundleError::Io(e)
+    }
+}
+
+// a set of assets loaded at runtime from a single archive, for assets too big
+// to bake into the binary (or that should be swappable without recompiling)
+pub struct Bundle {
+    assets: HashMap<String, Asset>,
+}
+
+impl Bundle {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, BundleError> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    // NOTE: the archive is leaked so its assets can be &'static like the
+    // built-in ones. bundles are meant to be loaded once and kept around for
+    // the life of the program anyway, but it's only leaked once it's known
+    // to be good, so failing to open one doesn't cost its size in memory.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, BundleError> {
+        let entries = parse(&data)?;
+
+        let data: &'static [u8] = Box::leak(data.into_boxed_slice());
+        let assets = entries
+            .into_iter()
+            .map(|(name, range)| {
+                let asset = Asset::from_extension(extension(&name), &data[range])
+                    .expect("parse() checked the asset type");
+                (name, asset)
+            })
+            .collect();
+
+        Ok(Self { assets })
+    }
+
+    // names include the extension, e.g. "vlem0.ogg"
+    pub fn get(&self, name: &str) -> Option<&Asset> {
+        self.assets.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.assets.keys().map(String::as_str)
+    }
+}
+
+// the name and where in `data` the contents are of each asset in the bundle,
+// after checking they're all there and of a known type
+fn parse(data: &[u8]) -> Result<Vec<(String, Range<usize>)>, BundleError> {
+    let mut reader = Reader(data);
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(BundleError::NotABundle);
+    }
+
+    let count = u32::from_le_bytes(reader.array()?);
+
+    let entries = (0..count)
+        .map(|_| {
+            let name_len = u16::from_le_bytes(reader.array()?);
+            let name =
+                str::from_utf8(reader.take(name_len.into())?).map_err(|_| BundleError::BadName)?;
+            let offset = u64::from_le_bytes(reader.array()?);
+            let len = u64::from_le_bytes(reader.array()?);
+
+            Ok((name, offset, len))
+        })
+        .collect::<Result<Vec<_>, BundleError>>()?;
+
+    let contents_start = data.len() - reader.0.len();
+    entries
+        .into_iter()
+        .map(|(name, offset, len)| {
+            let start: usize = offset.try_into().map_err(|_| BundleError::Truncated)?;
+            let len: usize = len.try_into().map_err(|_| BundleError::Truncated)?;
+            let end = start.checked_add(len).ok_or(BundleError::Truncated)?;
+            if end > reader.0.len() {
+                return Err(BundleError::Truncated);
+            }
+
+            // there's nothing 'static to wrap yet, but the type is all that
+            // matters here
+            if Asset::from_extension(extension(name), &[]).is_none() {
+                return Err(BundleError::UnknownType(name.to_owned()));
+            }
+
+            Ok((
+                name.to_owned(),
+                contents_start + start..contents_start + end,
+            ))
+        })
+        .collect()
+}
+
+fn extension(name: &str) -> &str {
+    name.rsplit('.').next().unwrap_or_default()
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BundleError> {
+        if self.0.len() < len {
+            return Err(BundleError::Truncated);
+        }
+
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn array<T>(&mut self) -> Result<T, BundleError>
+    where
+        T: Default + AsMut<[u8]>,
+    {
+        let mut array = T::default();
+        let len = array.as_mut().len();
+        array.as_mut().copy_from_slice(self.take(len)?);
+        Ok(array)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, process::Command};
+
+    const ASSETS: &[&str] = &["credits.txt", "menu1.wav", "vlem0.ogg"];
+
+    // a bundle of ASSETS, made the same way as a real one
+    fn generate(name: &str) -> Vec<u8> {
+        let output = env::temp_dir().join(format!("planets-{}-{}.bin", name, std::process::id()));
+        let status = Command::new("sh")
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .arg("generate-asset-bundle.sh")
+            .arg(&output)
+            .args(ASSETS.iter().map(|name| Path::new("assets").join(name)))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let bundle = fs::read(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+        bundle
+    }
+
+    fn read_asset(name: &str) -> Vec<u8> {
+        fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("assets")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    fn is_truncated(result: Result<Bundle, BundleError>) -> bool {
+        matches!(result, Err(BundleError::Truncated))
+    }
+
+    #[test]
+    fn generated_bundles_round_trip() {
+        let bundle = Bundle::from_bytes(generate("round-trip")).unwrap();
+
+        let mut names: Vec<_> = bundle.names().collect();
+        names.sort();
+        assert_eq!(names, ASSETS);
+
+        let credits = bundle.get("credits.txt").unwrap().txt_data();
+        assert_eq!(credits, &read_asset("credits.txt")[..]);
+        let menu1 = bundle.get("menu1.wav").unwrap().wav_data();
+        assert_eq!(menu1, &read_asset("menu1.wav")[..]);
+        let vlem0 = bundle.get("vlem0.ogg").unwrap().ogg_data();
+        assert_eq!(vlem0, &read_asset("vlem0.ogg")[..]);
+    }
+
+    #[test]
+    fn truncated_bundles_are_rejected() {
+        let data = generate("truncated");
+        // the header, the middle of the entry table, and the last asset
+        for &len in &[MAGIC.len() + 2, MAGIC.len() + 4 + 10, data.len() - 1] {
+            assert!(is_truncated(Bundle::from_bytes(data[..len].to_vec())));
+        }
+    }
+
+    #[test]
+    fn bad_offsets_are_rejected() {
+        let data = generate("bad-offsets");
+        // the first entry's offset comes right after its name
+        let offset_at = MAGIC.len() + 4 + 2 + ASSETS[0].len();
+
+        for &offset in &[data.len() as u64, u64::MAX] {
+            let mut data = data.clone();
+            data[offset_at..offset_at + 8].copy_from_slice(&offset.to_le_bytes());
+            assert!(is_truncated(Bundle::from_bytes(data)));
+        }
+    }
+
+    #[test]
+    fn other_files_are_not_bundles() {
+        let result = Bundle::from_bytes(read_asset("credits.txt"));
+        assert!(matches!(result, Err(BundleError::NotABundle)));
+    }
+}
@@ -0,0 +1,102 @@
+use std::{fmt, str};
+
+use super::Asset;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AssetError {
+    UnknownFormat,
+    WrongType,
+    NotUtf8(str::Utf8Error),
+    // checksums are CRC-32 (the same one zip and PNG use)
+    Corrupt { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssetError::UnknownFormat => write!(f, "unrecognized asset format"),
+            AssetError::WrongType => write!(f, "asset is the wrong type"),
+            AssetError::NotUtf8(e) => write!(f, "text asset is not valid UTF-8: {}", e),
+            AssetError::Corrupt { expected, actual } => write!(
+                f,
+                "asset is corrupt (expected checksum {:08x}, got {:08x})",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl Asset {
+    // figures out what kind of asset the data is from its first few bytes
+    pub fn load(data: &'static [u8]) -> Result<Self, AssetError> {
+        if data.starts_with(b"OggS") {
+            Ok(Asset::Ogg(data))
+        } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(&b"WAVE"[..]) {
+            Ok(Asset::Wav(data))
+        } else if str::from_utf8(data).is_ok() {
+            Ok(Asset::Txt(data))
+        } else {
+            Err(AssetError::UnknownFormat)
+        }
+    }
+
+    // like load(), but first checks the data against a checksum (e.g. from a
+    // downloaded asset pack's manifest), so corruption is caught here instead
+    // of somewhere deep inside the decoder
+    pub fn load_verified(data: &'static [u8], expected_crc: u32) -> Result<Self, AssetError> {
+        let actual = crc32(data);
+        if actual != expected_crc {
+            return Err(AssetError::Corrupt {
+                expected: expected_crc,
+                actual,
+            });
+        }
+
+        Self::load(data)
+    }
+
+    // the contents of a text asset (e.g. assets::credits)
+    pub fn as_str(&self) -> Result<&'static str, AssetError> {
+        match self {
+            Asset::Txt(data) => str::from_utf8(data).map_err(AssetError::NotUtf8),
+            _ => Err(AssetError::WrongType),
+        }
+    }
+}
+
+// bitwise CRC-32 (IEEE). slow compared to a table-driven one, but it only
+// runs once per asset load
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            (crc >> 1) ^ (0xedb8_8320 & (!(crc & 1)).wrapping_add(1))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGITS: &[u8] = b"123456789";
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(crc32(DIGITS), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn load_verified_catches_corruption() {
+        assert_eq!(
+            Asset::load_verified(DIGITS, 0x1234_5678).err(),
+            Some(AssetError::Corrupt {
+                expected: 0x1234_5678,
+                actual: 0xcbf4_3926,
+            })
+        );
+
+        let asset = Asset::load_verified(DIGITS, 0xcbf4_3926).unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(asset.as_str(), Ok("123456789"));
+    }
+}
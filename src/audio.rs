@@ -1,11 +1,22 @@
 use std::convert::TryFrom;
 
+mod decoder;
 mod mixer;
 pub mod music;
 mod sink;
 mod source;
 
-pub use sink::AudioThread;
+#[cfg(feature = "engine-api")]
+pub use decoder::AudioDecoder;
+#[cfg(feature = "engine-api")]
+pub use mixer::VoiceStealing;
+#[cfg(feature = "engine-api")]
+pub use sink::AudioHandle;
+#[cfg(feature = "recording-sink")]
+pub use sink::RecordingSink;
+pub use sink::{AudioThread, Sink};
+#[cfg(feature = "engine-api")]
+pub use source::{SeekError, SourceReader};
 
 // this probably would be i16 were it not for Interpolators requiring f64 frames
 pub type SampleFormat = f64;
@@ -18,6 +29,15 @@ pub enum Channels {
     Mono,
 }
 
+impl Channels {
+    pub fn count(self) -> usize {
+        match self {
+            Channels::Mono => 1,
+            Channels::Stereo => 2,
+        }
+    }
+}
+
 impl TryFrom<u32> for Channels {
     type Error = ();
 
@@ -0,0 +1,62 @@
+use lazy_static::lazy_static;
+
+use std::sync::Mutex;
+
+use super::source::{Source, SourceReader};
+
+// lets other crates add audio formats (e.g. FLAC or MP3) without forking
+pub trait AudioDecoder: Send + Sync {
+    // whether the data looks like this decoder's format (e.g. by magic bytes)
+    fn probe(&self, data: &[u8]) -> bool;
+    // only called if probe() returned true
+    fn open<'a>(&self, data: &'a [u8]) -> Box<dyn SourceReader + 'a>;
+}
+
+struct WavDecoder;
+
+impl AudioDecoder for WavDecoder {
+    fn probe(&self, data: &[u8]) -> bool {
+        data.starts_with(b"RIFF") && data.get(8..12) == Some(&b"WAVE"[..])
+    }
+
+    fn open<'a>(&self, data: &'a [u8]) -> Box<dyn SourceReader + 'a> {
+        Box::new(Source::from_wav(data))
+    }
+}
+
+struct OggDecoder;
+
+impl AudioDecoder for OggDecoder {
+    fn probe(&self, data: &[u8]) -> bool {
+        data.starts_with(b"OggS")
+    }
+
+    fn open<'a>(&self, data: &'a [u8]) -> Box<dyn SourceReader + 'a> {
+        Box::new(Source::from_ogg(data))
+    }
+}
+
+static BUILTIN_DECODERS: [&dyn AudioDecoder; 2] = [&WavDecoder, &OggDecoder];
+lazy_static! {
+    static ref DECODERS: Mutex<Vec<Box<dyn AudioDecoder>>> = Mutex::new(Vec::new());
+}
+
+#[cfg(feature = "engine-api")]
+pub fn register(decoder: Box<dyn AudioDecoder>) {
+    DECODERS.lock().unwrap().push(decoder);
+}
+
+// the built-in decoders get the first try, then the registered ones in the
+// order they were registered
+pub fn open(data: &[u8]) -> Option<Source<'_>> {
+    if let Some(decoder) = BUILTIN_DECODERS.iter().find(|d| d.probe(data)) {
+        return Some(Source::from_reader(decoder.open(data)));
+    }
+
+    DECODERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|d| d.probe(data))
+        .map(|d| Source::from_reader(d.open(data)))
+}
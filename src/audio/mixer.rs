@@ -2,69 +2,453 @@ use sample::Sample;
 
 use std::{
     borrow::BorrowMut,
+    cmp,
+    collections::HashMap,
     iter::Peekable,
     ops::DerefMut,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
 };
 
 use super::{source::Source, SampleFormat};
 
+// the source list is owned solely by the audio thread. everyone else talks to
+// it through a MixerHandle, which queues up commands that are only applied at
+// the top of each callback, so the hot path never has to take a lock.
+enum Command<'a> {
+    Add(Option<&'static str>, Source<'a>, Arc<AtomicU64>),
+    Remove(&'static str),
+    RemoveMatching(Box<dyn Fn(&'static str) -> bool + Send + 'a>),
+    Gain(&'static str, SampleFormat),
+    Mute(&'static str, bool),
+    Solo(Option<&'static str>),
+    Headroom(SampleFormat),
+    MaxVoices(Option<usize>, VoiceStealing),
+}
+
+// which voice makes way for a new one once the most that may play at once
+// are already playing (see Sink::set_max_voices)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VoiceStealing {
+    // the one that started playing first
+    Oldest,
+    // the one that's been the quietest lately, after its gain. muted voices
+    // (and those silenced by another being soloed) count as silent, so they
+    // go first
+    Quietest,
+}
+
+impl Default for VoiceStealing {
+    fn default() -> Self {
+        VoiceStealing::Oldest
+    }
+}
+
+// how much of a voice's loudness is left after each sample, for
+// VoiceStealing::Quietest. samples are interleaved, so it applies once per
+// channel of every frame: at 44.1 kHz stereo (e.g. once canonicalize()d for
+// the sink), a sound that stops is half as loud (as far as stealing goes)
+// about 0.08 s later
+const LOUDNESS_DECAY: SampleFormat = 0.9999;
+
+struct Voice<'a> {
+    name: Option<&'static str>,
+    gain: SampleFormat,
+    // muted voices keep playing (silently), so they're still in sync when
+    // they're unmuted
+    muted: bool,
+    source: Peekable<Source<'a>>,
+    // how many samples have been mixed so far (see MixerHandle::position)
+    played: Arc<AtomicU64>,
+    // when it was added, counted in voices added before it
+    added: u64,
+    // the loudest recent sample (after gain), decaying by LOUDNESS_DECAY
+    loudness: SampleFormat,
+}
+
+impl<'a> Voice<'a> {
+    fn audible(&self, solo: Option<&'static str>) -> bool {
+        !self.muted && solo.map_or(true, |solo| self.name == Some(solo))
+    }
+
+    fn audible_loudness(&self, solo: Option<&'static str>) -> SampleFormat {
+        if self.audible(solo) {
+            self.loudness
+        } else {
+            0.0
+        }
+    }
+}
+
 // it's important to note that even though we are using a vector (for cache
 // locality reasons), order of our elements doesn't matter (A + B = B + A),
 // so we can make optimizations like using swap_remove() instead of remove().
-type Sources<'a> = Arc<Mutex<Vec<(Option<&'static str>, Peekable<Source<'a>>)>>>;
+//
+// NOTE: that's only true up to rounding: float addition isn't associative, so
+// reordering the voices can change the last bit of the mix. a deterministic
+// Mixer keeps voices in the order they were added (at the cost of an O(n)
+// remove()) so the same input always mixes to bit-for-bit the same output,
+// e.g. for comparing offline renders against golden files.
+pub struct Mixer<'a> {
+    voices: Vec<Voice<'a>>,
+    deterministic: bool,
+    // if set, every other voice is treated as muted
+    solo: Option<&'static str>,
+    // applied to the whole mix, after each voice's own gain. summing a few
+    // loud voices clips otherwise
+    headroom: SampleFormat,
+    // None plays every voice, however many there are
+    max_voices: Option<usize>,
+    stealing: VoiceStealing,
+    voices_added: u64,
+    commands: Receiver<Command<'a>>,
+}
 
 #[derive(Clone)]
-pub struct Mixer<'a>(Sources<'a>);
+pub struct MixerHandle<'a> {
+    sender: Sender<Command<'a>>,
+    // the latest voice added under each name, shared by every clone of the
+    // handle. the Mixer holds the only strong reference to each counter, so
+    // once a voice is gone, so is its position.
+    positions: Arc<Mutex<HashMap<&'static str, VoicePosition>>>,
+}
+
+struct VoicePosition {
+    played: Weak<AtomicU64>,
+    sample_rate: u32,
+    channels: usize,
+}
 
 impl<'a> Mixer<'a> {
-    pub fn new() -> Self {
-        Mixer(Arc::new(Mutex::new(Vec::new())))
+    pub fn new() -> (Self, MixerHandle<'a>) {
+        Self::with_determinism(false)
+    }
+
+    pub fn deterministic() -> (Self, MixerHandle<'a>) {
+        Self::with_determinism(true)
     }
 
+    fn with_determinism(deterministic: bool) -> (Self, MixerHandle<'a>) {
+        let (sender, receiver) = mpsc::channel();
+
+        let mixer = Mixer {
+            voices: Vec::new(),
+            deterministic,
+            solo: None,
+            headroom: 1.0,
+            max_voices: None,
+            stealing: Default::default(),
+            voices_added: 0,
+            commands: receiver,
+        };
+
+        let handle = MixerHandle {
+            sender,
+            positions: Arc::new(Mutex::new(HashMap::new())),
+        };
+
+        (mixer, handle)
+    }
+
+    pub fn apply_commands(&mut self) {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                Command::Add(name, source, played) => {
+                    if self.steal_voices(1) {
+                        self.voices.push(Voice {
+                            name,
+                            gain: 1.0,
+                            muted: false,
+                            source: source.peekable(),
+                            played,
+                            added: self.voices_added,
+                            loudness: 0.0,
+                        });
+                    }
+                    self.voices_added += 1;
+                }
+                Command::Remove(name) => {
+                    let name = Some(name);
+                    self.retain_voices(|v| v.name != name);
+                }
+                Command::RemoveMatching(predicate) => {
+                    // unnamed voices can't be matched (or removed) by name
+                    self.retain_voices(|v| !v.name.map_or(false, |name| predicate(name)));
+                }
+                Command::Gain(name, gain) => {
+                    let name = Some(name);
+                    self.voices
+                        .iter_mut()
+                        .filter(|v| v.name == name)
+                        .for_each(|v| v.gain = gain);
+                }
+                Command::Mute(name, muted) => {
+                    let name = Some(name);
+                    self.voices
+                        .iter_mut()
+                        .filter(|v| v.name == name)
+                        .for_each(|v| v.muted = muted);
+                }
+                Command::Solo(name) => self.solo = name,
+                Command::Headroom(gain) => self.headroom = gain,
+                Command::MaxVoices(max, stealing) => {
+                    self.max_voices = max;
+                    self.stealing = stealing;
+                    self.steal_voices(0);
+                }
+            }
+        }
+    }
+
+    // removes voices (chosen by self.stealing) until there's room for
+    // `room` more. returns false if there can't be, i.e. the limit is 0
+    fn steal_voices(&mut self, room: usize) -> bool {
+        let max = match self.max_voices {
+            Some(max) => max,
+            None => return true,
+        };
+
+        while !self.voices.is_empty() && self.voices.len() + room > max {
+            let stealing = self.stealing;
+            let solo = self.solo;
+            let victim = self
+                .voices
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| match stealing {
+                    VoiceStealing::Oldest => a.added.cmp(&b.added),
+                    VoiceStealing::Quietest => a
+                        .audible_loudness(solo)
+                        .partial_cmp(&b.audible_loudness(solo))
+                        .unwrap_or(cmp::Ordering::Equal)
+                        .then(a.added.cmp(&b.added)),
+                })
+                .map(|(i, _)| i)
+                .unwrap();
+
+            if self.deterministic {
+                self.voices.remove(victim);
+            } else {
+                self.voices.swap_remove(victim);
+            }
+        }
+
+        self.voices.len() + room <= max
+    }
+
+    fn retain_voices<F: FnMut(&mut Voice<'a>) -> bool>(&mut self, f: F) {
+        if self.deterministic {
+            ordered_retain(&mut self.voices, f);
+        } else {
+            swap_retain(&mut self.voices, f);
+        }
+    }
+}
+
+impl<'a> MixerHandle<'a> {
+    // sending only fails if the audio thread (and with it the Mixer) is gone,
+    // in which case nobody would hear the result anyway
     pub fn add(&mut self, name: Option<&'static str>, input: Source<'a>) {
-        self.0.lock().unwrap().push((name, input.peekable()))
+        let played = Arc::new(AtomicU64::new(0));
+
+        let mut positions = self.positions.lock().unwrap();
+        // forget about voices that have finished since
+        positions.retain(|_, position| position.played.upgrade().is_some());
+        if let Some(name) = name {
+            positions.insert(
+                name,
+                VoicePosition {
+                    played: Arc::downgrade(&played),
+                    sample_rate: input.sample_rate(),
+                    channels: input.channels().count(),
+                },
+            );
+        }
+        drop(positions);
+
+        let _ = self.sender.send(Command::Add(name, input, played));
+    }
+
+    // how much of the voice last added with this name has been mixed so far,
+    // or None if it has finished (or been removed)
+    pub fn position(&self, name: &'static str) -> Option<Duration> {
+        let mut positions = self.positions.lock().unwrap();
+        let position = positions.get(name)?;
+        let (sample_rate, channels) = (position.sample_rate, position.channels);
+
+        let samples = match position.played.upgrade() {
+            Some(played) => played.load(Ordering::Relaxed),
+            None => {
+                positions.remove(name);
+                return None;
+            }
+        };
+        let frames = samples / channels as u64;
+
+        Some(Duration::from_secs_f64(
+            frames as f64 / f64::from(sample_rate),
+        ))
     }
 
     pub fn remove(&mut self, name: &'static str) {
-        let name = Some(name);
-        swap_retain(self.0.lock().unwrap(), |(n, _)| n != &name);
+        let _ = self.sender.send(Command::Remove(name));
     }
-}
 
-/*pub struct MixerIterator<'a>(Sources<'a>);
+    pub fn remove_matching(&mut self, predicate: Box<dyn Fn(&'static str) -> bool + Send + 'a>) {
+        let _ = self.sender.send(Command::RemoveMatching(predicate));
+    }
 
-impl<'a> IntoIterator for Mixer<'a> {
-    type Item = SampleFormat;
-    type IntoIter = MixerIterator<'a>;
+    pub fn set_gain(&mut self, name: &'static str, gain: SampleFormat) {
+        let _ = self.sender.send(Command::Gain(name, gain));
+    }
+
+    pub fn set_muted(&mut self, name: &'static str, muted: bool) {
+        let _ = self.sender.send(Command::Mute(name, muted));
+    }
+
+    pub fn solo(&mut self, name: Option<&'static str>) {
+        let _ = self.sender.send(Command::Solo(name));
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        MixerIterator(self.0)
+    pub fn set_headroom(&mut self, gain: SampleFormat) {
+        let _ = self.sender.send(Command::Headroom(gain));
     }
-}*/
+
+    pub fn set_max_voices(&mut self, max: Option<usize>, stealing: VoiceStealing) {
+        let _ = self.sender.send(Command::MaxVoices(max, stealing));
+    }
+}
 
 impl<'a> Iterator for Mixer<'a> {
     type Item = SampleFormat;
 
     fn next(&mut self) -> Option<Self::Item> {
-        swap_retain(self.0.lock().unwrap(), |(_, i)| i.peek().is_some());
+        self.retain_voices(|v| v.source.peek().is_some());
 
-        let mut i = 0;
-        let mut accum = <Self::Item as Sample>::Signed::equilibrium();
-        while let Some((_, input)) = self.0.lock().unwrap().get_mut(i) {
-            accum = accum.add_amp(input.next().unwrap());
-            i += 1;
-        }
+        let solo = self.solo;
+        let accum = self.voices.iter_mut().fold(
+            <Self::Item as Sample>::Signed::equilibrium(),
+            |accum, voice| {
+                let sample = voice.source.next().unwrap();
+                voice.played.fetch_add(1, Ordering::Relaxed);
+                voice.loudness = (voice.loudness * LOUDNESS_DECAY).max(sample.abs() * voice.gain);
+                if voice.audible(solo) {
+                    accum.add_amp(sample.mul_amp(voice.gain))
+                } else {
+                    accum
+                }
+            },
+        );
 
-        Some(accum.to_sample())
+        Some(accum.mul_amp(self.headroom).to_sample())
     }
 }
 
 fn swap_retain<T, F: FnMut(&mut T) -> bool>(mut vec: impl DerefMut<Target = Vec<T>>, mut f: F) {
     let vec = vec.borrow_mut();
-    for i in 0..vec.len() {
-        if !f(&mut vec[i]) {
+    let mut i = 0;
+    while i < vec.len() {
+        if f(&mut vec[i]) {
+            i += 1;
+        } else {
+            // the last element was swapped into slot i, so check it next
             vec.swap_remove(i);
         }
     }
 }
+
+// like Vec::retain(), but f gets a &mut (which Peekable::peek() needs)
+fn ordered_retain<T, F: FnMut(&mut T) -> bool>(vec: &mut Vec<T>, mut f: F) {
+    let mut i = 0;
+    while i < vec.len() {
+        if f(&mut vec[i]) {
+            i += 1;
+        } else {
+            vec.remove(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::Channels;
+    use std::iter;
+
+    fn constant(amplitude: SampleFormat) -> Source<'static> {
+        Source::from_iterator(iter::repeat(amplitude), 44100, Channels::Mono)
+    }
+
+    fn names(mixer: &Mixer) -> Vec<&'static str> {
+        mixer.voices.iter().filter_map(|v| v.name).collect()
+    }
+
+    #[test]
+    fn positions_are_shared_between_handles() {
+        let (mut mixer, mut handle) = Mixer::new();
+        let other = handle.clone();
+        handle.add(
+            Some("a"),
+            Source::silence(Duration::from_secs(1), 10, Channels::Mono),
+        );
+        mixer.apply_commands();
+        assert_eq!(other.position("a"), Some(Duration::from_secs(0)));
+
+        for _ in 0..5 {
+            mixer.next();
+        }
+        assert_eq!(other.position("a"), Some(Duration::from_millis(500)));
+
+        while mixer.next().is_some() && !mixer.voices.is_empty() {}
+        assert_eq!(other.position("a"), None);
+        assert!(handle.positions.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn max_voices_steals_the_oldest() {
+        let (mut mixer, mut handle) = Mixer::deterministic();
+        handle.set_max_voices(Some(2), VoiceStealing::Oldest);
+        handle.add(Some("a"), constant(0.9));
+        handle.add(Some("b"), constant(0.1));
+        handle.add(Some("c"), constant(0.5));
+        mixer.apply_commands();
+
+        assert_eq!(names(&mixer), ["b", "c"]);
+    }
+
+    #[test]
+    fn max_voices_steals_the_quietest() {
+        let (mut mixer, mut handle) = Mixer::deterministic();
+        handle.add(Some("a"), constant(0.9));
+        handle.add(Some("b"), constant(0.1));
+        mixer.apply_commands();
+        mixer.next();
+
+        handle.set_max_voices(Some(2), VoiceStealing::Quietest);
+        handle.add(Some("c"), constant(0.5));
+        mixer.apply_commands();
+
+        assert_eq!(names(&mixer), ["a", "c"]);
+    }
+
+    #[test]
+    fn voices_silenced_by_solo_count_as_quiet() {
+        let (mut mixer, mut handle) = Mixer::deterministic();
+        handle.add(Some("a"), constant(0.9));
+        handle.add(Some("b"), constant(0.1));
+        handle.solo(Some("b"));
+        mixer.apply_commands();
+        assert_eq!(mixer.next(), Some(0.1));
+
+        handle.set_max_voices(Some(2), VoiceStealing::Quietest);
+        handle.add(Some("c"), constant(0.5));
+        mixer.apply_commands();
+
+        // a is the loudest, but nobody can hear it
+        assert_eq!(names(&mixer), ["b", "c"]);
+    }
+}
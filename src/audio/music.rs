@@ -1,7 +1,44 @@
+use std::time::Duration;
+
 use super::{sink::Sink, source::Source};
 use crate::assets::{self, Asset};
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MusicOptions {
+    pub gain: f64,
+    // how long to ramp up from silence for (zero starts at full volume)
+    pub fade_in: Duration,
+    pub looping: bool,
+}
+
+impl Default for MusicOptions {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            fade_in: Duration::from_secs(0),
+            looping: false,
+        }
+    }
+}
+
 pub fn vlem<'a>(sink: &dyn Sink) -> Source<'a> {
+    vlem_with(sink, Default::default())
+}
+
+pub fn vlem_with<'a>(sink: &dyn Sink, options: MusicOptions) -> Source<'a> {
+    let source = if options.looping {
+        Source::repeat_with(vlem_parts)
+    } else {
+        vlem_parts()
+    };
+
+    source
+        .canonicalize(sink)
+        .with_gain(options.gain)
+        .fade_in(options.fade_in)
+}
+
+fn vlem_parts<'a>() -> Source<'a> {
     const VLEM: [&Asset; 8] = [
         &assets::vlem0,
         &assets::vlem1,
@@ -13,9 +50,31 @@ pub fn vlem<'a>(sink: &dyn Sink) -> Source<'a> {
         &assets::vlem7,
     ];
 
+    let first = Source::new(VLEM[0]);
+    let sample_rate = first.sample_rate();
+    let channels = first.channels();
+
+    // chain() panics if the formats differ, which (with no hint as to which
+    // track was at fault) is a rough way to find out one was re-encoded
     VLEM[1..]
         .iter()
-        .map(|&a| Source::new(a))
-        .fold(Source::new(VLEM[0]), Source::chain)
-        .canonicalize(sink)
+        .enumerate()
+        .map(|(i, &asset)| {
+            let part = Source::new(asset);
+            if part.sample_rate() == sample_rate && part.channels() == channels {
+                return part;
+            }
+
+            log::warn!(
+                "vlem{} is {} Hz with {} channel(s), unlike vlem0 ({} Hz with {}); converting it",
+                i + 1,
+                part.sample_rate(),
+                part.channels().count(),
+                sample_rate,
+                channels.count(),
+            );
+            part.with_sample_rate(Some(sample_rate))
+                .with_channels(Some(channels))
+        })
+        .fold(first, Source::chain)
 }
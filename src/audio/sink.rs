@@ -3,27 +3,84 @@ use cpal::{
     traits::{DeviceTrait, EventLoopTrait, HostTrait},
     Format, SampleRate, StreamData, StreamDataResult, SupportedFormat, UnknownTypeOutputBuffer,
 };
-use crossbeam_utils::thread::{scope, Scope};
-use sample::{conv::ToSample, Sample};
+#[cfg(any(not(feature = "graphics"), feature = "engine-api"))]
+use crossbeam_utils::thread::scope;
+use sample::{
+    conv::ToSample,
+    frame::{Frame, Mono, Stereo},
+    interpolate::{self, Converter},
+    ring_buffer,
+    signal::Signal,
+    Sample,
+};
 
 use std::{
     convert::TryInto,
+    marker::PhantomData,
     num::NonZeroU32,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU32, Ordering},
         Arc,
     },
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
-use super::{mixer::Mixer, source::Source, Channels, SampleFormat};
-use crate::util::IntentionalPanic;
+use super::{
+    mixer::{Mixer, MixerHandle, VoiceStealing},
+    source::{Source, SINC_BUFFER_SIZE},
+    Channels, SampleFormat,
+};
+use crate::util::{IntentionalPanic, PanicHookGuard};
 
 pub trait Sink<'a> {
     fn play(&mut self, name: Option<&'static str>, source: Source<'a>);
     fn play_singleton(&mut self, name: &'static str, source: Source<'a>);
 
+    // stops every source whose name matches, e.g. to tear down all of a
+    // screen's sound effects at once
+    fn remove_matching(&mut self, predicate: Box<dyn Fn(&'static str) -> bool + Send + 'a>);
+    fn remove_prefixed(&mut self, prefix: &'static str) {
+        self.remove_matching(Box::new(move |name| name.starts_with(prefix)));
+    }
+
+    fn set_gain(&mut self, name: &'static str, gain: SampleFormat);
+
+    // for debugging: muting and soloing don't touch the sources' gains, so
+    // everything sounds the same as before once they're undone
+    fn mute(&mut self, name: &'static str, muted: bool);
+    fn solo(&mut self, name: &'static str);
+    fn unsolo(&mut self);
+
+    // attenuates the whole mix by `db` decibels (e.g. 6.0), so many sources
+    // playing at once don't clip. it's applied on top of each source's gain,
+    // so a source at gain 1.0 with 6 dB of headroom plays at about 0.5. there
+    // is no separate master volume; this is it.
+    fn set_headroom(&mut self, db: SampleFormat);
+
+    // caps how many sources play at once, so lots of overlapping sound
+    // effects don't turn to mush (or eat the audio thread). past the limit,
+    // each new source stops an old one, chosen by `stealing`. None (the
+    // default) lifts the limit.
+    fn set_max_voices(&mut self, max: Option<usize>, stealing: VoiceStealing);
+
+    // how far the source last played with this name has gotten, or None if
+    // it's done playing (or there never was one)
+    fn position(&self, name: &'static str) -> Option<Duration>;
+
     fn channels(&self) -> Option<Channels>;
+    // the rate the mixer runs at, which sources are canonicalized to. this is
+    // the device's rate unless AudioThread::with_internal_rate was used.
     fn sample_rate(&self) -> Option<NonZeroU32>;
+    // what the device was actually opened with (e.g. for showing "48000 Hz /
+    // 2ch / F32" in a debug overlay). the mixer always runs in SampleFormat
+    // (and possibly a different sample rate) and converts to this at the very
+    // end.
+    fn format(&self) -> Option<Format>;
+
+    // the loudest (absolute) sample on each of the mix's channels since the
+    // last call, e.g. for a VU meter
+    fn master_peaks(&self) -> Vec<f32>;
 }
 
 struct DummySink;
@@ -31,6 +88,17 @@ struct DummySink;
 impl<'a> Sink<'a> for DummySink {
     fn play(&mut self, _name: Option<&'static str>, _source: Source<'a>) {}
     fn play_singleton(&mut self, _name: &'static str, _source: Source<'a>) {}
+    fn remove_matching(&mut self, _predicate: Box<dyn Fn(&'static str) -> bool + Send + 'a>) {}
+    fn set_gain(&mut self, _name: &'static str, _gain: SampleFormat) {}
+    fn mute(&mut self, _name: &'static str, _muted: bool) {}
+    fn solo(&mut self, _name: &'static str) {}
+    fn unsolo(&mut self) {}
+    fn set_headroom(&mut self, _db: SampleFormat) {}
+    fn set_max_voices(&mut self, _max: Option<usize>, _stealing: VoiceStealing) {}
+
+    fn position(&self, _name: &'static str) -> Option<Duration> {
+        None
+    }
 
     fn channels(&self) -> Option<Channels> {
         None
@@ -38,12 +106,171 @@ impl<'a> Sink<'a> for DummySink {
     fn sample_rate(&self) -> Option<NonZeroU32> {
         None
     }
+    fn format(&self) -> Option<Format> {
+        None
+    }
+    fn master_peaks(&self) -> Vec<f32> {
+        Vec::new()
+    }
 }
 
-#[derive(Clone)]
-pub struct AudioThread<'a> {
+// a sink with no device behind it: nothing is mixed until render() is
+// called, and everything mixed is kept, so tests can check what was played
+// (and how long, and how loud) without any audio hardware. the mixer is
+// deterministic, so the same calls always record the same samples.
+#[cfg(any(test, feature = "recording-sink"))]
+pub struct RecordingSink<'a> {
     mixer: Mixer<'a>,
+    handle: MixerHandle<'a>,
+    sample_rate: NonZeroU32,
+    channels: Channels,
+    recorded: Vec<SampleFormat>,
+    peaks: Peaks,
+}
+
+#[cfg(any(test, feature = "recording-sink"))]
+impl<'a> RecordingSink<'a> {
+    pub fn new(sample_rate: NonZeroU32, channels: Channels) -> Self {
+        let (mixer, handle) = Mixer::deterministic();
+
+        Self {
+            mixer,
+            handle,
+            sample_rate,
+            channels,
+            recorded: Vec::new(),
+            peaks: Peaks::default(),
+        }
+    }
+
+    // mixes the next `frames` frames (silence, if nothing is playing)
+    pub fn render(&mut self, frames: usize) {
+        self.mixer.apply_commands();
+
+        let channels = self.channels.count();
+        self.recorded.reserve(frames * channels);
+        for i in 0..frames * channels {
+            let sample = self.mixer.next().unwrap_or_else(SampleFormat::equilibrium);
+            self.peaks.record(i % channels, sample.abs() as f32);
+            self.recorded.push(sample);
+        }
+    }
+
+    // everything render() has mixed so far, interleaved
+    pub fn recorded(&self) -> &[SampleFormat] {
+        &self.recorded
+    }
+
+    pub fn take_recorded(&mut self) -> Vec<SampleFormat> {
+        std::mem::replace(&mut self.recorded, Vec::new())
+    }
+}
+
+#[cfg(any(test, feature = "recording-sink"))]
+impl<'a> Sink<'a> for RecordingSink<'a> {
+    fn play(&mut self, name: Option<&'static str>, source: Source<'a>) {
+        self.handle.add(name, source);
+    }
+
+    fn play_singleton(&mut self, name: &'static str, source: Source<'a>) {
+        self.handle.remove(name);
+        self.handle.add(Some(name), source);
+    }
+
+    fn remove_matching(&mut self, predicate: Box<dyn Fn(&'static str) -> bool + Send + 'a>) {
+        self.handle.remove_matching(predicate);
+    }
+
+    fn set_gain(&mut self, name: &'static str, gain: SampleFormat) {
+        self.handle.set_gain(name, gain);
+    }
+
+    fn mute(&mut self, name: &'static str, muted: bool) {
+        self.handle.set_muted(name, muted);
+    }
+
+    fn solo(&mut self, name: &'static str) {
+        self.handle.solo(Some(name));
+    }
+
+    fn unsolo(&mut self) {
+        self.handle.solo(None);
+    }
+
+    fn set_headroom(&mut self, db: SampleFormat) {
+        self.handle
+            .set_headroom(SampleFormat::powf(10.0, -db / 20.0));
+    }
+
+    fn set_max_voices(&mut self, max: Option<usize>, stealing: VoiceStealing) {
+        self.handle.set_max_voices(max, stealing);
+    }
+
+    fn position(&self, name: &'static str) -> Option<Duration> {
+        self.handle.position(name)
+    }
+
+    fn channels(&self) -> Option<Channels> {
+        Some(self.channels)
+    }
+
+    fn sample_rate(&self) -> Option<NonZeroU32> {
+        Some(self.sample_rate)
+    }
+
+    // there's no device, so no device format either
+    fn format(&self) -> Option<Format> {
+        None
+    }
+
+    fn master_peaks(&self) -> Vec<f32> {
+        self.peaks.take(self.channels.count())
+    }
+}
+
+// the peaks are stored as f32 bits: non-negative floats sort the same as their
+// bits do, so they can be compared (and swapped) atomically as integers. the
+// mix is never wider than stereo, so two are enough.
+#[derive(Default)]
+struct Peaks([AtomicU32; 2]);
+
+impl Peaks {
+    fn record(&self, channel: usize, peak: f32) {
+        let peak = peak.to_bits();
+        let slot = &self.0[channel];
+
+        let mut current = slot.load(Ordering::Acquire);
+        while peak > current {
+            match slot.compare_exchange_weak(current, peak, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    fn take(&self, channels: usize) -> Vec<f32> {
+        self.0[..channels]
+            .iter()
+            .map(|peak| f32::from_bits(peak.swap(0, Ordering::AcqRel)))
+            .collect()
+    }
+}
+
+pub struct AudioThread<'a> {
+    mixer: MixerHandle<'a>,
     format: Format,
+    // the mixer's, which may differ from format.sample_rate
+    sample_rate: NonZeroU32,
+    peaks: Arc<Peaks>,
+    stopping: Arc<AtomicBool>,
+}
+
+// the half of the audio thread that actually lives on the audio thread
+struct AudioCallback<'a> {
+    output: MixerOutput<'a>,
+    // may be more than the mixer's (see fill_stream_buffer)
+    device_channels: usize,
+    peaks: Arc<Peaks>,
     stopping: Arc<AtomicBool>,
 }
 
@@ -57,12 +284,55 @@ impl<'a> Sink<'a> for AudioThread<'a> {
         self.mixer.add(Some(name), source);
     }
 
+    fn remove_matching(&mut self, predicate: Box<dyn Fn(&'static str) -> bool + Send + 'a>) {
+        self.mixer.remove_matching(predicate);
+    }
+
+    fn set_gain(&mut self, name: &'static str, gain: SampleFormat) {
+        self.mixer.set_gain(name, gain);
+    }
+
+    fn mute(&mut self, name: &'static str, muted: bool) {
+        self.mixer.set_muted(name, muted);
+    }
+
+    fn solo(&mut self, name: &'static str) {
+        self.mixer.solo(Some(name));
+    }
+
+    fn unsolo(&mut self) {
+        self.mixer.solo(None);
+    }
+
+    fn set_headroom(&mut self, db: SampleFormat) {
+        self.mixer
+            .set_headroom(SampleFormat::powf(10.0, -db / 20.0));
+    }
+
+    fn set_max_voices(&mut self, max: Option<usize>, stealing: VoiceStealing) {
+        self.mixer.set_max_voices(max, stealing);
+    }
+
+    fn position(&self, name: &'static str) -> Option<Duration> {
+        self.mixer.position(name)
+    }
+
     fn channels(&self) -> Option<Channels> {
-        self.format.channels.try_into().ok()
+        // everything is mixed in (at most) stereo; any extra channels the
+        // device has are left silent
+        self.format.channels.min(2).try_into().ok()
     }
 
     fn sample_rate(&self) -> Option<NonZeroU32> {
-        Some(NonZeroU32::new(self.format.sample_rate.0).unwrap())
+        Some(self.sample_rate)
+    }
+
+    fn format(&self) -> Option<Format> {
+        Some(self.format.clone())
+    }
+
+    fn master_peaks(&self) -> Vec<f32> {
+        self.peaks.take(self.channels().map_or(0, Channels::count))
     }
 }
 
@@ -73,72 +343,213 @@ impl<'a> Drop for AudioThread<'a> {
 }
 
 impl<'a> AudioThread<'a> {
+    // with graphics, the engine starts audio (with start()) instead
+    #[cfg(any(not(feature = "graphics"), feature = "engine-api"))]
     pub fn with<F: FnOnce(Box<dyn Sink<'a> + 'a>) + 'a>(f: F) {
+        Self::with_mixer_rate(None, f)
+    }
+
+    // like with(), but the mixer runs at `hz` no matter what the device does,
+    // and only the finished mix is resampled to the device's rate. sources
+    // are canonicalized to `hz` instead, so if most of them are already at
+    // that rate, they aren't resampled at all, which is cheaper than one
+    // resampler per source. it also makes effects on the whole mix (like
+    // pitch-bending it) independent of the device. 0 means the device's rate.
+    //
+    // the catches: the output resampler adds about SINC_BUFFER_SIZE / 2
+    // frames of latency (around a millisecond at 44.1 kHz), sources at some
+    // third rate are resampled twice (softening their highs a little more),
+    // and nothing above half of `hz` survives, even if the device could play
+    // it. if the rates already match, the mix is passed through untouched.
+    #[cfg(feature = "engine-api")]
+    pub fn with_internal_rate<F: FnOnce(Box<dyn Sink<'a> + 'a>) + 'a>(hz: u32, f: F) {
+        Self::with_mixer_rate(NonZeroU32::new(hz), f)
+    }
+
+    #[cfg(any(not(feature = "graphics"), feature = "engine-api"))]
+    fn with_mixer_rate<F: FnOnce(Box<dyn Sink<'a> + 'a>) + 'a>(
+        mixer_rate: Option<NonZeroU32>,
+        f: F,
+    ) {
         // TODO: allow cpal::EventLoop::run() to terminate
         // here we have to write a custom panic hander(!) because the audio thread has to panic in
         // order to exit at all from event_loop.run().
-        IntentionalPanic::setup_hook();
-        scope(|s| f(Self::new(s))).unwrap_err();
+        let _panic_hook = IntentionalPanic::setup_hook();
+        scope(|s| {
+            f(Self::new(mixer_rate, |run| {
+                s.spawn(move |_| run());
+            }))
+        })
+        .unwrap_err();
     }
 
-    fn new(scope: &Scope<'a>) -> Box<dyn Sink<'a> + 'a> {
-        match Self::spawn(scope) {
+    // like with(), but returns instead of taking over the calling thread.
+    // the audio thread is stopped when the AudioHandle is dropped.
+    pub fn start() -> AudioHandle {
+        let _panic_hook = IntentionalPanic::setup_hook();
+
+        let mut audio_thread = None;
+        let sink = AudioThread::new(None, |run| audio_thread = Some(thread::spawn(run)));
+
+        AudioHandle {
+            sink,
+            thread: audio_thread,
+            _panic_hook,
+        }
+    }
+
+    // like start(), but without any audio thread: the sink just drops
+    // whatever it's given, as if there were no audio device
+    pub fn start_silent() -> AudioHandle {
+        AudioHandle {
+            sink: Box::new(DummySink),
+            thread: None,
+            _panic_hook: IntentionalPanic::setup_hook(),
+        }
+    }
+
+    // TODO: cpal only lets us pick the buffer size (BufferSize) as of 0.13.
+    // once upgraded, add a with_latency(frames, f) that asks for a buffer size
+    // clamped to the device's supported range
+
+    // spawn_thread starts the thread the audio callback runs on. the mixer
+    // runs at mixer_rate, or the device's rate if that's None.
+    fn new<S>(mixer_rate: Option<NonZeroU32>, spawn_thread: S) -> Box<dyn Sink<'a> + Send + 'a>
+    where
+        S: FnOnce(Box<dyn FnOnce() + Send + 'a>),
+    {
+        match Self::spawn(mixer_rate, spawn_thread) {
             Ok(real) => Box::new(real),
-            Err(_) => Box::new(DummySink {}),
+            Err(e) => {
+                log::warn!("audio disabled: {}", e);
+                Box::new(DummySink {})
+            }
         }
     }
 
-    fn spawn(scope: &Scope<'a>) -> Result<Self, ()> {
+    fn spawn<S>(mixer_rate: Option<NonZeroU32>, spawn_thread: S) -> Result<Self, String>
+    where
+        S: FnOnce(Box<dyn FnOnce() + Send + 'a>),
+    {
         let host = cpal::default_host();
         // TODO: sound device selection menu
         // see issue #2
-        let device = host.default_output_device().ok_or(())?;
-        let format = Self::get_output_format(&device)?;
+        let device = host
+            .default_output_device()
+            .ok_or_else(|| "no audio output device found".to_owned())?;
+        // unlike having no device at all, this is usually a driver or
+        // configuration problem, so say what the device itself said
+        let format = Self::get_output_format(&device).map_err(|e| {
+            format!(
+                "audio device {} has no usable output formats ({})",
+                device.name().unwrap_or_else(|_| "(unknown)".to_owned()),
+                e
+            )
+        })?;
 
         let event_loop = host.event_loop();
         let stream_id = event_loop
             .build_output_stream(&device, &format)
-            .or(Err(()))?;
-        event_loop.play_stream(stream_id).or(Err(()))?;
+            .map_err(|e| format!("failed to open audio stream: {}", e))?;
+        event_loop
+            .play_stream(stream_id)
+            .map_err(|e| format!("failed to start audio stream: {}", e))?;
 
-        let sink = Self {
-            mixer: Mixer::new(),
-            stopping: Arc::new(AtomicBool::new(false)),
-            format,
-        };
+        let device_rate = NonZeroU32::new(format.sample_rate.0)
+            .ok_or_else(|| "audio device has a sample rate of 0 Hz".to_owned())?;
+        let sample_rate = mixer_rate.unwrap_or(device_rate);
+        let device_channels = usize::from(format.channels);
+
+        let (mixer, handle) = Mixer::new();
+        let peaks = Arc::new(Peaks::default());
+        let stopping = Arc::new(AtomicBool::new(false));
 
-        let mut audio_thread = sink.clone();
-        scope.spawn(move |_| event_loop.run(move |id, data| audio_thread.callback(id, data)));
+        let mut audio_callback = AudioCallback {
+            output: MixerOutput::new(mixer, device_channels.min(2), sample_rate, device_rate),
+            device_channels,
+            peaks: peaks.clone(),
+            stopping: stopping.clone(),
+        };
+        spawn_thread(Box::new(move || {
+            event_loop.run(move |id, data| audio_callback.callback(id, data))
+        }));
 
-        Ok(sink)
+        Ok(Self {
+            mixer: handle,
+            format,
+            sample_rate,
+            peaks,
+            stopping,
+        })
     }
 
-    fn get_output_format(device: &Device) -> Result<Format, ()> {
+    fn get_output_format(device: &Device) -> Result<Format, String> {
         const HZ_44100: Option<SampleRate> = Some(SampleRate(44100));
 
-        match device
-            .supported_output_formats()
-            .ok()
-            .and_then(|s| s.max_by(SupportedFormat::cmp_default_heuristics))
-        {
+        // if listing the formats fails, the default one might still work
+        let supported = match device.supported_output_formats() {
+            Ok(formats) => formats.max_by(SupportedFormat::cmp_default_heuristics),
+            Err(e) => {
+                log::warn!("failed to list audio output formats: {}", e);
+                None
+            }
+        };
+
+        let format = match supported {
             Some(SupportedFormat {
                 channels,
                 min_sample_rate,
                 max_sample_rate,
                 data_type,
-            }) => Some(Format {
+            }) => Format {
                 channels,
                 sample_rate: HZ_44100
                     .filter(|r| (min_sample_rate..=max_sample_rate).contains(r))
                     .unwrap_or(max_sample_rate),
                 data_type,
-            }),
-            None => device.default_output_format().ok(),
+            },
+            None => device
+                .default_output_format()
+                .map_err(|e| format!("no default format: {}", e))?,
+        };
+
+        if format.channels > 0 {
+            Ok(format)
+        } else {
+            Err("it has no output channels".to_owned())
         }
-        .filter(|f| f.channels <= 2)
-        .ok_or(())
     }
+}
 
+// unlike the sink with() lends out, this can be moved between threads, and
+// outlive whatever started it (e.g. so music keeps playing while the window
+// is recreated)
+pub struct AudioHandle {
+    sink: Box<dyn Sink<'static> + Send>,
+    thread: Option<JoinHandle<()>>,
+    // the audio thread still has to panic to stop (see AudioThread::with)
+    _panic_hook: PanicHookGuard,
+}
+
+impl AudioHandle {
+    pub fn sink(&mut self) -> &mut dyn Sink<'static> {
+        self.sink.as_mut()
+    }
+}
+
+impl Drop for AudioHandle {
+    fn drop(&mut self) {
+        // dropping the AudioThread tells the audio thread to stop
+        self.sink = Box::new(DummySink);
+
+        if let Some(thread) = self.thread.take() {
+            // it always "fails", since panicking is the only way out
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<'a> AudioCallback<'a> {
     fn callback(&mut self, _id: StreamId, data: StreamDataResult) {
         match data.unwrap() {
             StreamData::Output {
@@ -163,16 +574,176 @@ impl<'a> AudioThread<'a> {
         O: Sample,
         SampleFormat: Sample + ToSample<O>,
     {
+        self.output.mixer().apply_commands();
+
         // NOTE: it would not be correct to directly copy interleaved samples
         // instead of doing it on a frame-by-frame basis were it not for the
         // implementation of source::new, which dynamically ensures the frame
         // width is the same as the sink's (by doubling mono or mixing stereo).
-        for sample in buffer {
-            *sample = self
-                .mixer
-                .next()
-                .map(Sample::to_sample)
-                .unwrap_or_else(O::equilibrium);
+        //
+        // devices with more than two channels (e.g. 5.1 or 7.1 interfaces)
+        // get the stereo mix on their first two (front left & right)
+        let mixer_channels = self.device_channels.min(2);
+        let mut peaks = [0.0f32; 2];
+        let mut mixed_frame = [SampleFormat::equilibrium(); 2];
+
+        for frame in buffer.chunks_mut(self.device_channels) {
+            let (mixed, extra) = frame.split_at_mut(mixer_channels.min(frame.len()));
+
+            self.output.next_frame(&mut mixed_frame[..mixer_channels]);
+            for (channel, (sample, &mixed_sample)) in mixed.iter_mut().zip(&mixed_frame).enumerate()
+            {
+                peaks[channel] = peaks[channel].max(mixed_sample.abs() as f32);
+                *sample = mixed_sample.to_sample();
+            }
+
+            for sample in extra {
+                *sample = O::equilibrium();
+            }
+        }
+
+        for (channel, &peak) in peaks[..mixer_channels].iter().enumerate() {
+            self.peaks.record(channel, peak);
+        }
+    }
+}
+
+// the mix, one frame at a time, at the device's sample rate
+enum MixerOutput<'a> {
+    Direct(Mixer<'a>),
+    // (boxed, since the resamplers' buffers are much bigger than a Mixer)
+    MonoResampled(Box<OutputResampler<'a, Mono<SampleFormat>>>),
+    StereoResampled(Box<OutputResampler<'a, Stereo<SampleFormat>>>),
+}
+
+type OutputResampler<'a, F> =
+    Converter<MixerSignal<'a, F>, interpolate::Sinc<[F; SINC_BUFFER_SIZE]>>;
+
+// the mixer's interleaved samples, grouped back up into frames so the whole
+// mix can be resampled at once (see AudioThread::with_internal_rate)
+struct MixerSignal<'a, F> {
+    mixer: Mixer<'a>,
+    frame: PhantomData<F>,
+}
+
+impl<'a, F: Frame<Sample = SampleFormat>> Signal for MixerSignal<'a, F> {
+    type Frame = F;
+
+    fn next(&mut self) -> F {
+        let mixer = &mut self.mixer;
+        F::from_fn(|_| mixer.next().unwrap_or_else(SampleFormat::equilibrium))
+    }
+}
+
+impl<'a> MixerOutput<'a> {
+    fn new(
+        mixer: Mixer<'a>,
+        channels: usize,
+        mixer_rate: NonZeroU32,
+        device_rate: NonZeroU32,
+    ) -> Self {
+        if mixer_rate == device_rate {
+            return MixerOutput::Direct(mixer);
+        }
+
+        match channels {
+            1 => MixerOutput::MonoResampled(Self::resampler(mixer, mixer_rate, device_rate)),
+            _ => MixerOutput::StereoResampled(Self::resampler(mixer, mixer_rate, device_rate)),
+        }
+    }
+
+    fn resampler<F: Frame<Sample = SampleFormat>>(
+        mixer: Mixer<'a>,
+        mixer_rate: NonZeroU32,
+        device_rate: NonZeroU32,
+    ) -> Box<OutputResampler<'a, F>> {
+        let signal = MixerSignal {
+            mixer,
+            frame: PhantomData,
+        };
+        let buffer = ring_buffer::Fixed::from([F::equilibrium(); SINC_BUFFER_SIZE]);
+
+        Box::new(Converter::from_hz_to_hz(
+            signal,
+            interpolate::Sinc::new(buffer),
+            f64::from(mixer_rate.get()),
+            f64::from(device_rate.get()),
+        ))
+    }
+
+    fn mixer(&mut self) -> &mut Mixer<'a> {
+        match self {
+            MixerOutput::Direct(mixer) => mixer,
+            MixerOutput::MonoResampled(resampler) => &mut resampler.source_mut().mixer,
+            MixerOutput::StereoResampled(resampler) => &mut resampler.source_mut().mixer,
+        }
+    }
+
+    // frame is as wide as the mix (one or two channels)
+    fn next_frame(&mut self, frame: &mut [SampleFormat]) {
+        match self {
+            MixerOutput::Direct(mixer) => {
+                for sample in frame {
+                    *sample = mixer.next().unwrap_or_else(SampleFormat::equilibrium);
+                }
+            }
+            MixerOutput::MonoResampled(resampler) => copy_frame(resampler.next(), frame),
+            MixerOutput::StereoResampled(resampler) => copy_frame(resampler.next(), frame),
         }
     }
 }
+
+fn copy_frame<F: Frame<Sample = SampleFormat>>(from: F, to: &mut [SampleFormat]) {
+    for (to, from) in to.iter_mut().zip(from.channels()) {
+        *to = from;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dummy_sinks_leave_sources_alone() {
+        let sine = || Source::sine(440.0, Duration::from_millis(10), 22050, Channels::Mono);
+        let source = sine().canonicalize(&DummySink);
+
+        assert_eq!(source.sample_rate(), 22050);
+        assert!(source.channels() == Channels::Mono);
+        assert_eq!(source.duration(), sine().duration());
+        assert!(source.eq(sine()));
+    }
+
+    #[test]
+    fn remove_prefixed_only_stops_matching_sources() {
+        let mut sink = RecordingSink::new(NonZeroU32::new(44100).unwrap(), Channels::Mono);
+        for &name in &["ui.click", "ui.hover", "game.boom"] {
+            let source = Source::silence(Duration::from_secs(1), 44100, Channels::Mono);
+            sink.play(Some(name), source);
+        }
+        sink.render(100);
+
+        sink.remove_prefixed("ui.");
+        sink.render(100);
+
+        assert_eq!(sink.position("ui.click"), None);
+        assert_eq!(sink.position("ui.hover"), None);
+        assert!(sink.position("game.boom").is_some());
+    }
+
+    #[test]
+    fn recording_sinks_keep_everything_rendered() {
+        let mut sink = RecordingSink::new(NonZeroU32::new(44100).unwrap(), Channels::Stereo);
+        let sine = Source::sine(440.0, Duration::from_millis(10), 44100, Channels::Stereo);
+        sink.play(None, sine);
+        sink.render(100);
+        sink.render(100);
+
+        assert_eq!(sink.recorded().len(), 400);
+        assert!(sink.recorded().iter().any(|&sample| sample.abs() > 0.1));
+
+        let recorded = sink.take_recorded();
+        assert_eq!(recorded.len(), 400);
+        assert!(sink.recorded().is_empty());
+    }
+}
@@ -1,93 +1,294 @@
 use hound::{self, WavReader};
-use lewton::{inside_ogg::OggStreamReader, samples::InterleavedSamples};
+use lewton::{
+    audio::AudioReadError, inside_ogg::OggStreamReader, samples::InterleavedSamples, VorbisError,
+};
 use sample::{
     frame::{Frame, Mono, Stereo},
     interpolate::{self, Converter, Interpolator},
     ring_buffer,
-    signal::{self, FromInterleavedSamplesIterator, IntoInterleavedSamplesIterator, Signal},
+    signal::{self, FromInterleavedSamplesIterator, Signal, UntilExhausted},
     Sample,
 };
 
-use std::{convert::TryInto, io::Cursor, num::NonZeroU32, vec};
+#[cfg(any(test, feature = "engine-api"))]
+use std::{cmp, f64::consts::PI, fmt};
+use std::{
+    convert::TryInto, f32::consts::FRAC_1_SQRT_2, io::Cursor, iter, num::NonZeroU32,
+    time::Duration, vec,
+};
+
+#[cfg(feature = "engine-api")]
+use super::decoder::AudioDecoder;
+use super::{decoder, sink::Sink, Channels, SampleFormat, HIGH_QUALITY_INTERPOLATION};
+use crate::{assets::Asset, util::clamp};
+
+pub(super) const SINC_BUFFER_SIZE: usize = 100;
+
+// how much earlier than asked to seek an Ogg file, if seeking straight there
+// lands too late (see seek_ogg). that happens when the position is in the
+// first packet of a page, which is at most half of the largest Vorbis block
+#[cfg(any(test, feature = "engine-api"))]
+const OGG_SEEK_MARGIN: u64 = 4096;
 
-use super::{sink::Sink, Channels, SampleFormat, HIGH_QUALITY_INTERPOLATION};
-use crate::assets::Asset;
+// resampling by more than this factor in either direction is almost certainly
+// a mislabeled source, and would only produce garbage (or eat all our memory)
+const MAX_RESAMPLE_RATIO: f64 = 16.0;
 
-const SINC_BUFFER_SIZE: usize = 100;
+// the range Source::gain_db clamps to. -120 dB is already far below anything
+// audible (without being small enough to make denormals), and +40 dB (100x)
+// is plenty to clip anything that isn't already silent
+#[cfg(any(test, feature = "engine-api"))]
+const MIN_GAIN_DB: SampleFormat = -120.0;
+#[cfg(any(test, feature = "engine-api"))]
+const MAX_GAIN_DB: SampleFormat = 40.0;
 
-type SourceResampler<T, F, I> =
-    IntoInterleavedSamplesIterator<Converter<FromInterleavedSamplesIterator<T, F>, I>>;
+// (a Signal goes on forever, so this stops once the source has run out)
+type SourceResampler<T, F, I> = iter::FlatMap<
+    UntilExhausted<Converter<FromInterleavedSamplesIterator<T, F>, I>>,
+    <F as Frame>::Channels,
+    fn(F) -> <F as Frame>::Channels,
+>;
 enum Resampler<'a, F: Frame<Sample = SampleFormat>> {
     Linear(SourceResampler<Box<Source<'a>>, F, interpolate::Linear<F>>),
     Sinc(SourceResampler<Box<Source<'a>>, F, interpolate::Sinc<[F; SINC_BUFFER_SIZE]>>),
 }
 
-// TODO: should SourceReader be a trait?
+// TODO: should Reader be a trait (like SourceReader)?
 // clippy seems to think so.
 // the current enum variants would be separate structs implementing the trait,
 // and boxed versions would be passed around.
 // most things here are boxed anyway, so it wouldn't be too much perf loss
 // and of course, this is all premature optimization because I've never seen
 // the audio thread take more than 10% CPU, even on debug mode.
-enum SourceReader<'a> {
+enum Reader<'a> {
     Wav(WavReader<Cursor<&'a [u8]>>),
+    // the current packet, and how many samples have been read so far
     Ogg(
         OggStreamReader<Cursor<&'a [u8]>>,
         Option<vec::IntoIter<f32>>,
+        u64,
     ),
 
     Iterator(Box<dyn Iterator<Item = SampleFormat> + Send + Sync + 'a>),
+    // from an AudioDecoder
+    Decoded(Box<dyn SourceReader + 'a>),
 
     MonoResampler(Resampler<'a, Mono<SampleFormat>>),
     StereoResampler(Resampler<'a, Stereo<SampleFormat>>),
 
     MonoToStereo(Box<Source<'a>>, Option<SampleFormat>),
     StereoToMono(Box<Source<'a>>),
+
+    // how many (interleaved) samples of silence are left before the source
+    #[cfg(feature = "engine-api")]
+    Delayed(Box<Source<'a>>, u64),
+    // how many samples into the current frame the source is
+    PaddedToFrames(Box<Source<'a>>, usize),
+    // how many (interleaved) samples are left before the source is cut off
+    #[cfg(any(test, feature = "engine-api"))]
+    Truncated(Box<Source<'a>>, u64),
+}
+
+// what an AudioDecoder hands back: the decoded (interleaved) samples, and
+// what's needed to make sense of them
+pub trait SourceReader: Iterator<Item = SampleFormat> + Send + Sync {
+    fn sample_rate(&self) -> u32;
+    fn channels(&self) -> Channels;
+
+    // how long the whole stream is, if that's known (see Source::duration)
+    fn duration(&self) -> Option<Duration> {
+        None
+    }
+
+    // see Source::seek_by
+    #[cfg(any(test, feature = "engine-api"))]
+    fn seek_by(&mut self, _delta_frames: i64) -> Result<(), SeekError> {
+        Err(SeekError::Unsupported)
+    }
+}
+
+// a Source is already all of the above, which is how the built-in decoders
+// (whose sources can seek) fit in
+impl<'a> SourceReader for Source<'a> {
+    fn sample_rate(&self) -> u32 {
+        Source::sample_rate(self)
+    }
+
+    fn channels(&self) -> Channels {
+        Source::channels(self)
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        Source::duration(self)
+    }
+
+    #[cfg(any(test, feature = "engine-api"))]
+    fn seek_by(&mut self, delta_frames: i64) -> Result<(), SeekError> {
+        Source::seek_by(self, delta_frames)
+    }
+}
+
+// interleaved, and (for multi-byte types) little-endian
+#[cfg(any(test, feature = "engine-api"))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum PcmSampleType {
+    U8,
+    I16,
+    I32,
+    F32,
+}
+
+#[cfg(any(test, feature = "engine-api"))]
+impl PcmSampleType {
+    fn size(self) -> usize {
+        match self {
+            PcmSampleType::U8 => 1,
+            PcmSampleType::I16 => 2,
+            PcmSampleType::I32 | PcmSampleType::F32 => 4,
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> SampleFormat {
+        match self {
+            PcmSampleType::U8 => bytes[0].to_sample(),
+            PcmSampleType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]).to_sample(),
+            PcmSampleType::I32 => {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).to_sample()
+            }
+            PcmSampleType::F32 => {
+                f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).to_sample()
+            }
+        }
+    }
+}
+
+#[cfg(any(test, feature = "engine-api"))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub sample_rate: u32,
+    pub channels: Channels,
+    pub sample_type: PcmSampleType,
+}
+
+#[cfg(any(test, feature = "engine-api"))]
+#[derive(Debug, PartialEq, Eq)]
+pub enum PcmError {
+    // the data has to hold a whole number of frames (i.e. a sample for every
+    // channel), each frame_size bytes long
+    LengthNotMultipleOfFrame { len: usize, frame_size: usize },
+}
+
+#[cfg(any(test, feature = "engine-api"))]
+impl fmt::Display for PcmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PcmError::LengthNotMultipleOfFrame { len, frame_size } => write!(
+                f,
+                "{} bytes of PCM data isn't a whole number of {}-byte frames",
+                len, frame_size
+            ),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "engine-api"))]
+#[derive(Debug, PartialEq, Eq)]
+pub enum SeekError {
+    // only sources read straight from a file can seek. generated sources, and
+    // ones that have been converted (resampled, delayed, etc.), can't
+    Unsupported,
+    // the decoder couldn't get to the new position
+    Failed,
+}
+
+#[cfg(any(test, feature = "engine-api"))]
+impl fmt::Display for SeekError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SeekError::Unsupported => write!(f, "this kind of source can't seek"),
+            SeekError::Failed => write!(f, "failed to seek in the audio data"),
+        }
+    }
 }
 
 pub struct Source<'a> {
-    reader: SourceReader<'a>,
+    reader: Reader<'a>,
     sample_rate: u32,
     channels: Channels,
+    // the whole length, from the beginning (see Source::duration)
+    duration: Option<Duration>,
 }
 
 impl<'a> Source<'a> {
+    // the format is figured out from the data, like from_bytes(), so assets
+    // can be in any format a decoder has been registered for
     pub fn new(asset: &'a Asset) -> Self {
-        match asset {
-            Asset::Wav(data) => Self::from_wav(data),
-            Asset::Ogg(data) => Self::from_ogg(data),
-            _ => unreachable!(),
+        let data = match asset {
+            Asset::Ogg(data) | Asset::Wav(data) => data,
+            Asset::Txt(_) => panic!("text assets can't be played"),
+        };
+
+        Self::from_bytes(data).expect("no decoder recognizes the audio asset")
+    }
+
+    // figures out the format from the data itself, so it also works for
+    // formats added with register_decoder()
+    pub fn from_bytes(data: &'a [u8]) -> Option<Self> {
+        decoder::open(data)
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn register_decoder(decoder: Box<dyn AudioDecoder>) {
+        decoder::register(decoder);
+    }
+
+    pub fn from_reader(reader: Box<dyn SourceReader + 'a>) -> Self {
+        let sample_rate = reader.sample_rate();
+        let channels = reader.channels();
+        let duration = reader.duration();
+
+        Self {
+            reader: Reader::Decoded(reader),
+            sample_rate,
+            channels,
+            duration,
         }
     }
 
-    fn from_wav(data: &'a [u8]) -> Self {
+    pub(super) fn from_wav(data: &'a [u8]) -> Self {
         let reader = WavReader::new(Cursor::new(data)).unwrap();
         let sample_rate = reader.spec().sample_rate;
         let channels = reader.spec().channels.try_into().unwrap();
 
+        let duration = Some(Self::duration_of(u64::from(reader.duration()), sample_rate));
+
         Self {
-            reader: SourceReader::Wav(reader),
+            reader: Reader::Wav(reader),
             sample_rate,
             channels,
+            duration,
         }
     }
 
-    fn from_ogg(data: &'a [u8]) -> Self {
+    pub(super) fn from_ogg(data: &'a [u8]) -> Self {
         let mut reader = OggStreamReader::new(Cursor::new(data)).unwrap();
-        let chunk: vec::IntoIter<f32> = reader
-            .read_dec_packet_generic::<InterleavedSamples<f32>>()
-            .unwrap()
-            .unwrap()
-            .samples
-            .into_iter();
 
         let sample_rate = reader.ident_hdr.audio_sample_rate;
-        let channels = reader.ident_hdr.audio_channels.try_into().unwrap();
+        let audio_channels = reader.ident_hdr.audio_channels;
+        // anything wider than stereo is mixed down as it's decoded
+        let channels = match audio_channels {
+            1 | 2 => audio_channels.try_into().unwrap(),
+            _ => Channels::Stereo,
+        };
+
+        let chunk = read_ogg_packet(&mut reader).unwrap();
+        let length = ogg_length(data);
 
         Self {
-            reader: SourceReader::Ogg(reader, Some(chunk)),
+            reader: Reader::Ogg(reader, Some(chunk), 0),
             sample_rate,
             channels,
+            duration: length.map(|frames| Self::duration_of(frames, sample_rate)),
         }
     }
 
@@ -97,41 +298,287 @@ impl<'a> Source<'a> {
         //I::IntoIter: Send + Sync + 'b,
     {
         Self {
-            reader: SourceReader::Iterator(Box::new(iterator)),
+            reader: Reader::Iterator(Box::new(iterator)),
             sample_rate,
             channels,
+            duration: None,
         }
     }
 
+    // for sources whose length is known even though they're made from an
+    // iterator (which could otherwise go on forever)
+    fn with_known_duration(mut self, duration: Option<Duration>) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    // like from_iterator(), but the frame type says how many channels there
+    // are, so generated audio can't be interleaved wrong
+    #[cfg(feature = "engine-api")]
+    pub fn from_mono_frames<I>(frames: I, sample_rate: u32) -> Self
+    where
+        I: Iterator<Item = Mono<SampleFormat>> + Send + Sync + 'a,
+    {
+        Self::from_iterator(frames.map(|frame| frame[0]), sample_rate, Channels::Mono)
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn from_stereo_frames<I>(frames: I, sample_rate: u32) -> Self
+    where
+        I: Iterator<Item = Stereo<SampleFormat>> + Send + Sync + 'a,
+    {
+        Self::from_iterator(
+            frames.flat_map(Frame::channels),
+            sample_rate,
+            Channels::Stereo,
+        )
+    }
+
+    // raw samples with no header, e.g. from a procedural generator or another
+    // decoder. fails if the data doesn't hold a whole number of frames.
+    #[cfg(any(test, feature = "engine-api"))]
+    pub fn from_pcm(data: &'a [u8], format: PcmFormat) -> Result<Self, PcmError> {
+        let sample_size = format.sample_type.size();
+        let frame_size = sample_size * format.channels.count();
+        if data.len() % frame_size != 0 {
+            return Err(PcmError::LengthNotMultipleOfFrame {
+                len: data.len(),
+                frame_size,
+            });
+        }
+
+        let sample_type = format.sample_type;
+        let frames = data.len() / frame_size;
+        Ok(Self::from_iterator(
+            data.chunks_exact(sample_size)
+                .map(move |bytes| sample_type.decode(bytes)),
+            format.sample_rate,
+            format.channels,
+        )
+        .with_known_duration(Some(Self::duration_of(frames as u64, format.sample_rate))))
+    }
+
+    #[cfg(any(test, feature = "engine-api"))]
+    pub fn silence(duration: Duration, sample_rate: u32, channels: Channels) -> Self {
+        let frames = Self::frames_in(duration, sample_rate);
+        let samples = frames * channels.count();
+
+        Self::from_iterator(
+            iter::repeat(SampleFormat::equilibrium()).take(samples),
+            sample_rate,
+            channels,
+        )
+        .with_known_duration(Some(Self::duration_of(frames as u64, sample_rate)))
+    }
+
+    #[cfg(any(test, feature = "engine-api"))]
+    pub fn sine(frequency: f64, duration: Duration, sample_rate: u32, channels: Channels) -> Self {
+        let frames = Self::frames_in(duration, sample_rate);
+        let step = 2.0 * PI * frequency / f64::from(sample_rate);
+
+        Self::from_iterator(
+            (0..frames).flat_map(move |i| {
+                // the same waveform goes out to every channel
+                iter::repeat((step * i as f64).sin()).take(channels.count())
+            }),
+            sample_rate,
+            channels,
+        )
+        .with_known_duration(Some(Self::duration_of(frames as u64, sample_rate)))
+    }
+
+    fn frames_in(duration: Duration, sample_rate: u32) -> usize {
+        (duration.as_secs_f64() * f64::from(sample_rate)).round() as usize
+    }
+
+    fn duration_of(frames: u64, sample_rate: u32) -> Duration {
+        Duration::from_secs_f64(frames as f64 / f64::from(sample_rate))
+    }
+
+    // how long the source plays for in total (not how much of it is left),
+    // if that's known. generated sources, and anything built on one (or on an
+    // Ogg file without a final granule position), don't know. resampling
+    // doesn't change it, since the source takes just as long to play.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
     pub fn chain(self, other: Source<'a>) -> Self {
         assert!(self.sample_rate == other.sample_rate);
         assert!(self.channels == other.channels);
 
         let sample_rate = self.sample_rate;
         let channels = self.channels;
+        let duration = self
+            .duration
+            .and_then(|first| other.duration.map(|second| first + second));
 
         Self::from_iterator(Iterator::chain(self, other), sample_rate, channels)
+            .with_known_duration(duration)
+    }
+
+    // starts playing after `frames` frames of silence (e.g. to line a sound
+    // up with an animation)
+    #[cfg(feature = "engine-api")]
+    pub fn delayed(self, frames: u64) -> Self {
+        let sample_rate = self.sample_rate;
+        let channels = self.channels;
+        let samples = frames.saturating_mul(channels.count() as u64);
+        let duration = self
+            .duration
+            .map(|duration| duration + Self::duration_of(frames, sample_rate));
+
+        Self {
+            reader: Reader::Delayed(Box::new(self), samples),
+            sample_rate,
+            channels,
+            duration,
+        }
+    }
+
+    // stops the source after `duration`, or when it runs out, whichever
+    // comes first. the cutoff is always on a frame boundary.
+    #[cfg(any(test, feature = "engine-api"))]
+    pub fn take_duration(self, duration: Duration) -> Self {
+        let sample_rate = self.sample_rate;
+        let channels = self.channels;
+        let frames = Self::frames_in(duration, sample_rate) as u64;
+        let samples = frames.saturating_mul(channels.count() as u64);
+        // a source of unknown length might end before the cutoff
+        let duration = self
+            .duration
+            .map(|duration| duration.min(Self::duration_of(frames, sample_rate)));
+
+        Self {
+            reader: Reader::Truncated(Box::new(self), samples),
+            sample_rate,
+            channels,
+            duration,
+        }
+    }
+
+    // scales every sample by `gain` (1.0 is unchanged)
+    pub fn with_gain(self, gain: f64) -> Self {
+        let sample_rate = self.sample_rate;
+        let channels = self.channels;
+        let duration = self.duration;
+
+        Self::from_iterator(self.map(move |s| s * gain), sample_rate, channels)
+            .with_known_duration(duration)
+    }
+
+    // like with_gain(), but in decibels: -6.0 is about half as loud (in
+    // amplitude), 0.0 is unchanged
+    #[cfg(any(test, feature = "engine-api"))]
+    pub fn gain_db(self, db: SampleFormat) -> Self {
+        let db = clamp(db, MIN_GAIN_DB, MAX_GAIN_DB);
+        self.with_gain(SampleFormat::powf(10.0, db / 20.0))
+    }
+
+    // ramps the volume up linearly from silence over `duration`
+    pub fn fade_in(self, duration: Duration) -> Self {
+        let sample_rate = self.sample_rate;
+        let channels = self.channels;
+        let fade_frames = Self::frames_in(duration, sample_rate);
+        let duration = self.duration;
+
+        if fade_frames == 0 {
+            return self;
+        }
+
+        Self::from_iterator(
+            self.enumerate().map(move |(i, s)| {
+                let frame = i / channels.count();
+                s * (frame as f64 / fade_frames as f64).min(1.0)
+            }),
+            sample_rate,
+            channels,
+        )
+        .with_known_duration(duration)
+    }
+
+    // plays the sources made by `f` back to back forever. every source has to
+    // have the same format as the first.
+    pub fn repeat_with<F>(mut f: F) -> Self
+    where
+        F: FnMut() -> Self + Send + Sync + 'a,
+    {
+        let first = f();
+        let sample_rate = first.sample_rate;
+        let channels = first.channels;
+
+        Self::from_iterator(
+            iter::once(first).chain(iter::repeat_with(f)).flatten(),
+            sample_rate,
+            channels,
+        )
     }
 
     pub fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
 
+    // skips ahead `delta_frames` frames (or back, if it's negative), stopping
+    // at the start, or (for WAV files, and Ogg files that know their length)
+    // the end. Ogg files are decoded from the start of the page the new
+    // position is on, so seeking one costs up to a page's worth of decoding.
+    #[cfg(any(test, feature = "engine-api"))]
+    pub fn seek_by(&mut self, delta_frames: i64) -> Result<(), SeekError> {
+        let channels = self.channels.count() as u64;
+
+        match &mut self.reader {
+            Reader::Wav(reader) => {
+                let length = u64::from(reader.duration());
+                // (the sample type doesn't matter for counting them)
+                let remaining = reader.samples::<i32>().len() as u64 / channels;
+                let target = offset_frames(length - remaining, delta_frames).min(length);
+
+                reader.seek(target as u32).map_err(|_| SeekError::Failed)
+            }
+            Reader::Ogg(reader, chunk, read) => {
+                // (an Ogg source's duration comes straight from its length)
+                let sample_rate = self.sample_rate;
+                let length = self
+                    .duration
+                    .map(|duration| Self::frames_in(duration, sample_rate) as u64);
+                let mut target = offset_frames(*read / channels, delta_frames);
+                if let Some(length) = length {
+                    target = target.min(length);
+                }
+
+                // whatever was already decoded is from the old position
+                *chunk = seek_ogg(reader, channels as usize, length, target)?;
+                *read = target * channels;
+
+                Ok(())
+            }
+            Reader::Decoded(reader) => reader.seek_by(delta_frames),
+            _ => Err(SeekError::Unsupported),
+        }
+    }
+
     pub fn channels(&self) -> Channels {
         self.channels
     }
 
+    // adapts the source to the sink's channels and sample rate. this is a
+    // no-op if they already match, and so is calling it twice. a sink that
+    // doesn't know its format (i.e. the DummySink used when audio is
+    // disabled) reports None for both, and gets the source back untouched.
     pub fn canonicalize(self, sink: &dyn Sink) -> Self {
-        if sink.channels().map(|c| self.channels > c).unwrap_or(false) {
+        let (channels, sample_rate) = match (sink.channels(), sink.sample_rate()) {
+            (None, None) => return self,
+            format => format,
+        };
+
+        if channels.map(|c| self.channels > c).unwrap_or(false) {
             // resampling is an expensive operation, so if this source will be
             // mixed down to fewer channels, do that before resampling
-            self.with_channels(sink.channels())
-                .with_sample_rate(sink.sample_rate())
+            self.with_channels(channels).with_sample_rate(sample_rate)
         } else {
             // on the other hand, if channels are being duplicated (e.g. mono
             // to stereo) we should resample first
-            self.with_sample_rate(sink.sample_rate())
-                .with_channels(sink.channels())
+            self.with_sample_rate(sample_rate).with_channels(channels)
         }
     }
 
@@ -139,19 +586,22 @@ impl<'a> Source<'a> {
         use Channels::*;
 
         let sample_rate = self.sample_rate;
+        let duration = self.duration;
 
         if let Some(channels) = channels.and_then(|c| c.try_into().ok()) {
             match (self.channels, channels) {
                 (Stereo, Stereo) | (Mono, Mono) => self,
                 (Mono, Stereo) => Self {
-                    reader: SourceReader::MonoToStereo(Box::new(self), None),
+                    reader: Reader::MonoToStereo(Box::new(self), None),
                     sample_rate,
                     channels,
+                    duration,
                 },
                 (Stereo, Mono) => Self {
-                    reader: SourceReader::StereoToMono(Box::new(self)),
+                    reader: Reader::StereoToMono(Box::new(self)),
                     sample_rate,
                     channels,
+                    duration,
                 },
             }
         } else {
@@ -161,39 +611,77 @@ impl<'a> Source<'a> {
 
     pub fn with_sample_rate<T: TryInto<NonZeroU32>>(self, sample_rate: Option<T>) -> Self {
         let channels = self.channels;
+        let duration = self.duration;
 
         match sample_rate.and_then(|c| c.try_into().ok()) {
-            Some(sample_rate) if self.sample_rate != sample_rate.get() => Self {
-                reader: match self.channels {
-                    Channels::Mono => SourceReader::MonoResampler(self.into_resampler(sample_rate)),
-                    Channels::Stereo => {
-                        SourceReader::StereoResampler(self.into_resampler(sample_rate))
-                    }
-                },
-                sample_rate: sample_rate.get(),
-                channels,
-            },
+            Some(sample_rate) if self.sample_rate != sample_rate.get() => {
+                let reader = match self.channels {
+                    Channels::Mono => self.into_resampler(sample_rate).map(Reader::MonoResampler),
+                    Channels::Stereo => self
+                        .into_resampler(sample_rate)
+                        .map(Reader::StereoResampler),
+                };
+
+                match reader {
+                    Ok(reader) => Self {
+                        reader,
+                        sample_rate: sample_rate.get(),
+                        channels,
+                        duration,
+                    },
+                    // the source ran dry before the resampler could even be
+                    // primed, so there is nothing left to play anyway
+                    Err(()) => Self::from_iterator(iter::empty(), sample_rate.get(), channels),
+                }
+            }
             _ => self,
         }
     }
 
+    // a stereo source that ends halfway through a frame (e.g. a truncated
+    // asset) gets its last frame filled out with silence. the resampler reads
+    // whole frames at a time, and would otherwise drop the dangling sample
+    // (or worse, pair it up with the wrong channel).
+    fn padded_to_frames(self) -> Self {
+        if self.channels == Channels::Mono {
+            return self;
+        }
+
+        let sample_rate = self.sample_rate;
+        let channels = self.channels;
+        let duration = self.duration;
+
+        Self {
+            reader: Reader::PaddedToFrames(Box::new(self), 0),
+            sample_rate,
+            channels,
+            duration,
+        }
+    }
+
     fn into_resampler<F: Frame<Sample = SampleFormat>>(
-        mut self,
+        self,
         sample_rate: NonZeroU32,
-    ) -> Resampler<'a, F> {
+    ) -> Result<Resampler<'a, F>, ()> {
+        let mut source = self.padded_to_frames();
+
         if HIGH_QUALITY_INTERPOLATION {
             let buffer = ring_buffer::Fixed::from([F::equilibrium(); SINC_BUFFER_SIZE]);
 
-            Resampler::Sinc(
-                self.resample_with_interpolator(sample_rate, interpolate::Sinc::new(buffer)),
-            )
+            Ok(Resampler::Sinc(source.resample_with_interpolator(
+                sample_rate,
+                interpolate::Sinc::new(buffer),
+            )))
         } else {
-            let left = F::from_samples(&mut self).unwrap();
-            let right = F::from_samples(&mut self).unwrap();
+            // a source with only one frame (e.g. a tiny UI blip) can't fill
+            // both sides of the interpolator, so pad it out with silence
+            let left = F::from_samples(&mut source).ok_or(())?;
+            let right = F::from_samples(&mut source).unwrap_or_else(F::equilibrium);
 
-            Resampler::Linear(
-                self.resample_with_interpolator(sample_rate, interpolate::Linear::new(left, right)),
-            )
+            Ok(Resampler::Linear(source.resample_with_interpolator(
+                sample_rate,
+                interpolate::Linear::new(left, right),
+            )))
         }
     }
 
@@ -202,16 +690,195 @@ impl<'a> Source<'a> {
         new_sample_rate: NonZeroU32,
         interpolator: I,
     ) -> SourceResampler<Box<Source<'a>>, F, I> {
-        let old_sample_rate = self.sample_rate;
+        let new_sample_rate = f64::from(new_sample_rate.get());
+        let old_sample_rate = clamp(
+            f64::from(self.sample_rate),
+            new_sample_rate / MAX_RESAMPLE_RATIO,
+            new_sample_rate * MAX_RESAMPLE_RATIO,
+        );
+
+        if old_sample_rate != f64::from(self.sample_rate) {
+            log::warn!(
+                "source sample rate {} Hz is too far from {} Hz; resampling as if it were {} Hz",
+                self.sample_rate,
+                new_sample_rate,
+                old_sample_rate
+            );
+        }
 
         signal::from_interleaved_samples_iter(Box::new(self))
-            .from_hz_to_hz(
-                interpolator,
-                old_sample_rate.into(),
-                new_sample_rate.get().into(),
-            )
-            .into_interleaved_samples()
-            .into_iter()
+            .from_hz_to_hz(interpolator, old_sample_rate, new_sample_rate)
+            .until_exhausted()
+            .flat_map(F::channels as fn(F) -> F::Channels)
+    }
+}
+
+// the granule position of an Ogg Vorbis stream's last page is the number of
+// frames in the whole stream, so the length can be found without decoding it
+fn ogg_length(data: &[u8]) -> Option<u64> {
+    const CAPTURE_PATTERN: &[u8] = b"OggS";
+    const HEADER_LEN: usize = 27;
+
+    // (the stream structure version is always 0, which weeds out most
+    // places the capture pattern happens to show up inside a packet)
+    let start = data
+        .windows(CAPTURE_PATTERN.len() + 1)
+        .rposition(|window| window.starts_with(CAPTURE_PATTERN) && window[4] == 0)?;
+    let header = data.get(start..start + HEADER_LEN)?;
+
+    let mut granule = [0; 8];
+    granule.copy_from_slice(&header[6..14]);
+    // -1 means no packet ends on this page
+    Some(i64::from_le_bytes(granule))
+        .filter(|&g| g >= 0)
+        .map(|g| g as u64)
+}
+
+// where each of a Vorbis stream's channels goes when it's mixed down to
+// stereo, as (left, right) gains. the spec fixes the order of up to 8
+// channels (section 4.3.9), and lewton interleaves them in that order. None
+// for mono and stereo (which play as is), and for more than 8 channels, whose
+// order is up to whatever made them.
+fn vorbis_downmix(channels: usize) -> Option<&'static [(f32, f32)]> {
+    const L: (f32, f32) = (1.0, 0.0);
+    const R: (f32, f32) = (0.0, 1.0);
+    const C: (f32, f32) = (FRAC_1_SQRT_2, FRAC_1_SQRT_2);
+    // side and rear channels
+    const SL: (f32, f32) = (FRAC_1_SQRT_2, 0.0);
+    const SR: (f32, f32) = (0.0, FRAC_1_SQRT_2);
+    const RC: (f32, f32) = (0.5, 0.5);
+    // the subwoofer channel has nothing a stereo mix wants
+    const LFE: (f32, f32) = (0.0, 0.0);
+
+    match channels {
+        3 => Some(&[L, C, R]),
+        4 => Some(&[L, R, SL, SR]),
+        5 => Some(&[L, C, R, SL, SR]),
+        6 => Some(&[L, C, R, SL, SR, LFE]),
+        7 => Some(&[L, C, R, SL, SR, RC, LFE]),
+        8 => Some(&[L, C, R, SL, SR, SL, SR, LFE]),
+        _ => None,
+    }
+}
+
+// the next packet's samples, in mono or stereo. frames are split up by the
+// channel count lewton gives with each packet, not the one in the header.
+fn read_ogg_packet(reader: &mut OggStreamReader<Cursor<&[u8]>>) -> Option<vec::IntoIter<f32>> {
+    let packet: InterleavedSamples<f32> = loop {
+        match reader.read_dec_packet_generic() {
+            // seeking to the very start lands before the header packets
+            Err(VorbisError::BadAudio(AudioReadError::AudioIsHeader)) => continue,
+            result => break result.unwrap()?,
+        }
+    };
+    let channel_count = packet.channel_count;
+
+    let gains = match vorbis_downmix(channel_count) {
+        Some(gains) => gains,
+        None if channel_count <= 2 => return Some(packet.samples.into_iter()),
+        // there's no telling where these go, so they all go in the middle
+        None => {
+            let mut stereo = Vec::with_capacity(packet.samples.len() / channel_count * 2);
+            for frame in packet.samples.chunks_exact(channel_count) {
+                let average = frame.iter().sum::<f32>() / channel_count as f32;
+                stereo.push(average);
+                stereo.push(average);
+            }
+            return Some(stereo.into_iter());
+        }
+    };
+
+    let mut stereo = Vec::with_capacity(packet.samples.len() / channel_count * 2);
+    for frame in packet.samples.chunks_exact(channel_count) {
+        let (left, right) = frame
+            .iter()
+            .zip(gains)
+            .fold((0.0, 0.0), |(left, right), (sample, (l, r))| {
+                (left + sample * l, right + sample * r)
+            });
+        stereo.push(left);
+        stereo.push(right);
+    }
+
+    Some(stereo.into_iter())
+}
+
+// lewton can only seek to the start of a page, and only knows where it is
+// once it has decoded the last packet on one. so this seeks to the page
+// `target` (in frames) is on, decodes until it knows where it is, and drops
+// whatever comes before `target`. returns what's been decoded from `target`
+// on, or None if that's past the end.
+#[cfg(any(test, feature = "engine-api"))]
+fn seek_ogg(
+    reader: &mut OggStreamReader<Cursor<&[u8]>>,
+    channels: usize,
+    length: Option<u64>,
+    target: u64,
+) -> Result<Option<vec::IntoIter<f32>>, SeekError> {
+    // the first packet after a seek only primes the decoder, so it decodes to
+    // nothing. if that's where `target` was, start earlier and try again
+    let mut margin = 0;
+    let (start, pending) = loop {
+        let from = target.saturating_sub(margin);
+        reader.seek_absgp_pg(from).map_err(|_| SeekError::Failed)?;
+        // the start of the stream is the one place that's known up front
+        if from == 0 {
+            break (0, Vec::new());
+        }
+
+        // decoded since the seek, before it was known where they were
+        let mut pending = Vec::new();
+        let mut pending_frames = 0;
+        let end = loop {
+            let packet = match read_ogg_packet(reader) {
+                Some(packet) => packet,
+                None => return Ok(None),
+            };
+            pending_frames += (packet.len() / channels) as u64;
+            pending.push(packet);
+
+            // (where the last packet ends)
+            if let Some(end) = reader.get_last_absgp() {
+                break end;
+            }
+        };
+
+        // the stream's last packet is only cut down to its real length if
+        // the reader already knew where it was, so its end is no help
+        let start = end.saturating_sub(pending_frames);
+        if start <= target && Some(end) != length {
+            break (start, pending);
+        }
+        margin = cmp::max(margin * 2, OGG_SEEK_MARGIN);
+    };
+
+    let mut position = start;
+    let mut pending = pending.into_iter();
+    loop {
+        let packet = match pending.next().or_else(|| read_ogg_packet(reader)) {
+            Some(packet) => packet,
+            None => return Ok(None),
+        };
+
+        let frames = (packet.len() / channels) as u64;
+        if position + frames > target {
+            // (along with anything decoded after it, which the reader is
+            // already past)
+            let skip = (target.saturating_sub(position)) as usize * channels;
+            let rest: Vec<_> = packet.skip(skip).chain(pending.flatten()).collect();
+            return Ok(Some(rest.into_iter()));
+        }
+        position += frames;
+    }
+}
+
+// saturates at 0 rather than going negative
+#[cfg(any(test, feature = "engine-api"))]
+fn offset_frames(position: u64, delta: i64) -> u64 {
+    if delta < 0 {
+        position.saturating_sub(delta.wrapping_neg() as u64)
+    } else {
+        position.saturating_add(delta as u64)
     }
 }
 
@@ -220,7 +887,7 @@ impl<'a> Iterator for Source<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         match &mut self.reader {
-            SourceReader::Wav(reader) => match reader.spec().sample_format {
+            Reader::Wav(reader) => match reader.spec().sample_format {
                 hound::SampleFormat::Float => reader
                     .samples::<f32>()
                     .next()
@@ -245,33 +912,62 @@ impl<'a> Iterator for Source<'a> {
             },
             // TODO: fork lewton to output to a &mut [f32]
             // or at least reuse its vector. there's lots of unnecessary allocations
-            SourceReader::Ogg(reader, chunk) => chunk
+            Reader::Ogg(reader, chunk, read) => chunk
                 .as_mut()
                 .and_then(Iterator::next)
                 .or_else(|| {
-                    *chunk = reader
-                        .read_dec_packet_generic()
-                        .unwrap()
-                        .map(|s: InterleavedSamples<f32>| s.samples.into_iter());
+                    *chunk = read_ogg_packet(reader);
                     chunk.as_mut().and_then(Iterator::next)
                 })
-                .map(Sample::to_sample),
-            SourceReader::Iterator(iterator) => iterator.next(),
-            SourceReader::MonoResampler(resampler) => match resampler {
+                .map(|sample| {
+                    *read += 1;
+                    sample.to_sample()
+                }),
+            Reader::Iterator(iterator) => iterator.next(),
+            Reader::Decoded(reader) => reader.next(),
+            Reader::MonoResampler(resampler) => match resampler {
                 Resampler::Linear(linear) => linear.next(),
                 Resampler::Sinc(sinc) => sinc.next(),
             },
-            SourceReader::StereoResampler(resampler) => match resampler {
+            Reader::StereoResampler(resampler) => match resampler {
                 Resampler::Linear(linear) => linear.next(),
                 Resampler::Sinc(sinc) => sinc.next(),
             },
-            SourceReader::MonoToStereo(source, mut accum) => {
+            Reader::MonoToStereo(source, mut accum) => {
                 if accum.is_none() {
                     accum = source.next();
                 }
                 accum
             }
-            SourceReader::StereoToMono(source) => {
+            #[cfg(feature = "engine-api")]
+            Reader::Delayed(source, remaining) => {
+                if *remaining > 0 {
+                    *remaining -= 1;
+                    Some(SampleFormat::equilibrium())
+                } else {
+                    source.next()
+                }
+            }
+            #[cfg(any(test, feature = "engine-api"))]
+            Reader::Truncated(source, remaining) => {
+                if *remaining > 0 {
+                    *remaining -= 1;
+                    source.next()
+                } else {
+                    None
+                }
+            }
+            Reader::PaddedToFrames(source, position) => {
+                let frame_len = source.channels.count();
+                let sample = source.next().or_else(|| {
+                    // (only pads if the last frame was cut short)
+                    Some(SampleFormat::equilibrium()).filter(|_| *position != 0)
+                })?;
+
+                *position = (*position + 1) % frame_len;
+                Some(sample)
+            }
+            Reader::StereoToMono(source) => {
                 if let Some(left) = source.next() {
                     let right = source.next().unwrap_or_else(SampleFormat::equilibrium);
                     Some(left.add_amp(right))
@@ -282,3 +978,243 @@ impl<'a> Iterator for Source<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets;
+
+    // what a freshly opened asset plays `frames` frames in
+    fn samples_from(asset: &'static Asset, frames: usize, count: usize) -> Vec<SampleFormat> {
+        let source = Source::new(asset);
+        let channels = source.channels().count();
+        source.skip(frames * channels).take(count).collect()
+    }
+
+    #[test]
+    fn seek_by_moves_forward_and_back() {
+        for &asset in &[&assets::menu1, &assets::vlem0] {
+            let mut source = Source::new(asset);
+            let channels = source.channels().count();
+
+            source.seek_by(20_000).unwrap();
+            let forward: Vec<_> = source.by_ref().take(100).collect();
+            assert_eq!(forward, samples_from(asset, 20_000, 100));
+
+            source.seek_by(-15_000).unwrap();
+            let back: Vec<_> = source.take(100).collect();
+            assert_eq!(
+                back,
+                samples_from(asset, 20_000 + 100 / channels - 15_000, 100)
+            );
+        }
+    }
+
+    #[test]
+    fn seek_by_stops_at_the_ends() {
+        for &asset in &[&assets::menu1, &assets::vlem0] {
+            let mut source = Source::new(asset);
+            source.seek_by(i64::max_value()).unwrap();
+            assert_eq!(source.next(), None);
+
+            source.seek_by(i64::min_value()).unwrap();
+            assert_eq!(
+                source.take(100).collect::<Vec<_>>(),
+                samples_from(asset, 0, 100)
+            );
+        }
+    }
+
+    // (see testdata/generate-fixtures.py)
+    const PANNED_LEFT: &[u8] = include_bytes!("testdata/panned_left.ogg");
+    const PANNED_RIGHT: &[u8] = include_bytes!("testdata/panned_right.ogg");
+    const TEN_CHANNELS: &[u8] = include_bytes!("testdata/ten_channels.ogg");
+
+    fn channel_rms(data: &'static [u8]) -> (SampleFormat, SampleFormat) {
+        let samples: Vec<_> = Source::from_ogg(data).collect();
+        let rms = |channel: usize| {
+            let frames = samples.len() / 2;
+            let sum: SampleFormat = samples[channel..].iter().step_by(2).map(|s| s * s).sum();
+            (sum / frames as SampleFormat).sqrt()
+        };
+        (rms(0), rms(1))
+    }
+
+    #[test]
+    fn stereo_ogg_channels_stay_put() {
+        // the quieter side is 12 dB (a quarter as loud) down
+        let (left, right) = channel_rms(PANNED_LEFT);
+        assert!((left / right - 4.0).abs() < 0.1, "{} {}", left, right);
+
+        let (left, right) = channel_rms(PANNED_RIGHT);
+        assert!((right / left - 4.0).abs() < 0.1, "{} {}", left, right);
+    }
+
+    #[test]
+    fn oggs_with_unknown_channel_orders_are_averaged() {
+        let source = Source::from_ogg(TEN_CHANNELS);
+        assert!(source.channels() == Channels::Stereo);
+
+        // only the first of the ten channels has anything in it
+        let (left, right) = channel_rms(TEN_CHANNELS);
+        let (full, _) = channel_rms(PANNED_LEFT);
+        assert_eq!(left, right);
+        assert!((left - full / 10.0).abs() < 1e-6, "{} {}", left, full);
+    }
+
+    #[test]
+    fn pcm_has_to_be_whole_frames() {
+        let format = PcmFormat {
+            sample_rate: 44100,
+            channels: Channels::Stereo,
+            sample_type: PcmSampleType::I16,
+        };
+
+        assert_eq!(
+            Source::from_pcm(&[0; 6], format).err(),
+            Some(PcmError::LengthNotMultipleOfFrame {
+                len: 6,
+                frame_size: 4
+            })
+        );
+
+        let data = [0x00, 0x40, 0x00, 0xc0];
+        let source = Source::from_pcm(&data, format).unwrap();
+        assert_eq!(source.collect::<Vec<_>>(), [0.5, -0.5]);
+    }
+
+    #[test]
+    fn duration_matches_what_plays() {
+        for &asset in &[&assets::menu1, &assets::vlem0] {
+            let source = Source::new(asset);
+            let sample_rate = source.sample_rate();
+            let channels = source.channels().count();
+            let duration = source.duration().unwrap();
+
+            let frames = source.count() / channels;
+            assert_eq!(duration, Source::duration_of(frames as u64, sample_rate));
+        }
+
+        assert_eq!(
+            Source::new(&assets::menu1).duration(),
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn resampling_keeps_the_duration() {
+        let source = Source::new(&assets::vlem0);
+        let duration = source.duration();
+        assert!(duration.is_some());
+
+        assert_eq!(
+            source.with_sample_rate(NonZeroU32::new(48000)).duration(),
+            duration
+        );
+    }
+
+    #[test]
+    fn resampled_sources_end() {
+        let source = Source::sine(440.0, Duration::from_millis(100), 22050, Channels::Stereo);
+        let resampled = source.with_sample_rate(NonZeroU32::new(44100));
+
+        // (give or take a couple of frames, depending on where the
+        // interpolator lands)
+        let frames = resampled.count() / 2;
+        assert!((frames as i64 - 4410).abs() <= 2, "{}", frames);
+    }
+
+    #[test]
+    fn single_frame_sources_can_be_resampled() {
+        // it doesn't even have to be audible (it's shorter than the sinc
+        // interpolator's window), as long as it doesn't panic or go on forever
+        for &channels in &[Channels::Mono, Channels::Stereo] {
+            let samples = iter::repeat(0.5).take(channels.count());
+            let blip = Source::from_iterator(samples, 22050, channels);
+
+            let frames = blip.with_sample_rate(NonZeroU32::new(44100)).count() / channels.count();
+            assert!(frames <= 4, "{}", frames);
+        }
+    }
+
+    #[test]
+    fn dangling_half_frames_keep_the_channels_apart() {
+        // a tone on the left, silence on the right, and one sample too many
+        let left = Source::sine(440.0, Duration::from_millis(100), 22050, Channels::Mono);
+        let samples = left.flat_map(|s| vec![s, 0.0]).chain(iter::once(0.5));
+        let source = Source::from_iterator(samples, 22050, Channels::Stereo);
+
+        let resampled: Vec<_> = source.with_sample_rate(NonZeroU32::new(44100)).collect();
+        assert_eq!(resampled.len() % 2, 0);
+
+        let rms = |channel: usize| {
+            let squares: SampleFormat = resampled
+                .iter()
+                .skip(channel)
+                .step_by(2)
+                .map(|s| s * s)
+                .sum();
+            (squares / (resampled.len() / 2) as SampleFormat).sqrt()
+        };
+        assert!(rms(0) > 0.5, "{}", rms(0));
+        assert!(rms(1) < 0.05, "{}", rms(1));
+    }
+
+    #[test]
+    fn sines_have_the_right_period() {
+        let sine = Source::sine(440.0, Duration::from_secs(1), 44100, Channels::Mono);
+        let samples: Vec<_> = sine.collect();
+        assert_eq!(samples.len(), 44100);
+
+        // where the wave crosses zero on its way up
+        let rising: Vec<_> = samples
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+            .map(|(i, _)| i)
+            .collect();
+
+        let periods = rising.len() - 1;
+        let period = (rising[periods] - rising[0]) as f64 / periods as f64;
+        assert!((period - 44100.0 / 440.0).abs() < 0.1, "{}", period);
+    }
+
+    #[test]
+    fn sines_play_the_same_on_every_channel() {
+        let sine = Source::sine(440.0, Duration::from_millis(10), 44100, Channels::Stereo);
+        let samples: Vec<_> = sine.collect();
+        assert_eq!(samples.len(), 441 * 2);
+
+        for frame in samples.chunks(2) {
+            assert_eq!(frame[0], frame[1]);
+        }
+    }
+
+    #[test]
+    fn take_duration_stops_on_time() {
+        let source = Source::silence(Duration::from_secs(1), 44100, Channels::Stereo);
+        let taken = source.take_duration(Duration::from_millis(250));
+        assert_eq!(taken.duration(), Some(Duration::from_millis(250)));
+        assert_eq!(taken.count(), 11025 * 2);
+
+        // or when the source runs out, if that's first
+        let source = Source::silence(Duration::from_millis(100), 44100, Channels::Mono);
+        assert_eq!(source.take_duration(Duration::from_secs(1)).count(), 4410);
+    }
+
+    #[test]
+    fn minus_six_db_is_about_half() {
+        let peak = |source: Source| source.fold(0.0, |peak: SampleFormat, s| peak.max(s.abs()));
+        let sine = || Source::sine(440.0, Duration::from_millis(10), 44100, Channels::Mono);
+
+        let ratio = peak(sine().gain_db(-6.0)) / peak(sine());
+        assert!((ratio - 0.5).abs() < 0.01, "{}", ratio);
+        assert_eq!(peak(sine().gain_db(0.0)), peak(sine()));
+    }
+
+    #[test]
+    fn generated_sources_cant_seek() {
+        let mut source = Source::silence(Duration::from_secs(1), 44100, Channels::Mono);
+        assert_eq!(source.seek_by(1), Err(SeekError::Unsupported));
+    }
+}
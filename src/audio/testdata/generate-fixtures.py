@@ -0,0 +1,230 @@
+#!/usr/bin/env python3
+# writes the Ogg Vorbis files the audio tests decode. there's no encoder to
+# make them with, so this is (about) the smallest one that makes a sound: it
+# only knows how to put one steady tone into any of the channels, at a
+# loudness set separately for each.
+#
+# usage: ./generate-fixtures.py (from this directory)
+
+import struct
+
+SAMPLE_RATE = 44100
+# short blocks only, so each packet after the first decodes to 128 frames
+BLOCKSIZE_EXPONENT = 8
+BLOCKSIZE = 1 << BLOCKSIZE_EXPONENT
+# the residue is split into partitions this big, and TONE_BIN is the only
+# frequency bin that's ever nonzero
+PARTITION_SIZE = 8
+TONE_BIN = 10
+# how loud the tone is at full scale (the bin it's in takes this value)
+TONE_VALUE = 0.5
+PACKETS_PER_PAGE = 32
+
+
+class BitWriter:
+    # Vorbis packs bits least significant first
+    def __init__(self):
+        self.bytes = bytearray()
+        self.bit = 0
+
+    def write(self, value, bits):
+        for i in range(bits):
+            if self.bit == 0:
+                self.bytes.append(0)
+            self.bytes[-1] |= ((value >> i) & 1) << self.bit
+            self.bit = (self.bit + 1) % 8
+
+    def write_bytes(self, data):
+        for byte in data:
+            self.write(byte, 8)
+
+
+def vorbis_float(value):
+    # mantissa * 2^(exponent - 788), with a 21-bit mantissa
+    if value == 0:
+        return 0
+    exponent = 788
+    while value != int(value):
+        value *= 2
+        exponent -= 1
+    return (exponent << 21) | int(value)
+
+
+def header(packet_type):
+    w = BitWriter()
+    w.write(packet_type, 8)
+    w.write_bytes(b"vorbis")
+    return w
+
+
+def identification_header(channels):
+    w = header(1)
+    w.write(0, 32)  # version
+    w.write(channels, 8)
+    w.write(SAMPLE_RATE, 32)
+    w.write(0, 32)  # maximum bitrate
+    w.write(0, 32)  # nominal bitrate
+    w.write(0, 32)  # minimum bitrate
+    w.write(BLOCKSIZE_EXPONENT, 4)
+    w.write(BLOCKSIZE_EXPONENT, 4)
+    w.write(1, 1)  # framing
+    return bytes(w.bytes)
+
+
+def comment_header():
+    vendor = b"planets test fixture"
+    w = header(3)
+    w.write(len(vendor), 32)
+    w.write_bytes(vendor)
+    w.write(0, 32)  # no comments
+    w.write(1, 1)  # framing
+    return bytes(w.bytes)
+
+
+def codebook(w, lookup_values=None):
+    # two entries with one-bit codewords: entry 0 is "0", entry 1 is "1"
+    w.write(0x564342, 24)
+    w.write(1, 16)  # dimensions
+    w.write(2, 24)  # entries
+    w.write(0, 1)  # not ordered
+    w.write(0, 1)  # not sparse
+    for _ in range(2):
+        w.write(1 - 1, 5)  # codeword length - 1
+    if lookup_values is None:
+        w.write(0, 4)
+    else:
+        minimum, delta = lookup_values
+        w.write(1, 4)
+        w.write(vorbis_float(minimum), 32)
+        w.write(vorbis_float(delta), 32)
+        w.write(1 - 1, 4)  # bits per multiplicand - 1
+        w.write(0, 1)  # not a sequence
+        w.write(0, 1)
+        w.write(1, 1)
+
+
+def setup_header():
+    w = header(5)
+
+    w.write(2 - 1, 8)
+    codebook(w)  # 0: residue partition classes
+    codebook(w, (0.0, TONE_VALUE))  # 1: residue values
+
+    w.write(1 - 1, 6)  # time domain transforms
+    w.write(0, 16)
+
+    w.write(1 - 1, 6)  # floors
+    w.write(1, 16)  # floor 1
+    w.write(0, 5)  # no partitions, so just the two posts at either end
+    w.write(1 - 1, 2)  # multiplier - 1
+    w.write(BLOCKSIZE_EXPONENT - 1, 4)  # range bits
+
+    w.write(1 - 1, 6)  # residues
+    w.write(1, 16)  # residue 1
+    w.write(0, 24)  # begin
+    w.write(BLOCKSIZE // 2, 24)  # end
+    w.write(PARTITION_SIZE - 1, 24)
+    w.write(2 - 1, 6)  # classifications
+    w.write(0, 8)  # classbook
+    w.write(0, 3)  # class 0 is silent
+    w.write(0, 1)
+    w.write(1, 3)  # class 1 decodes its values in the first pass
+    w.write(0, 1)
+    w.write(1, 8)
+
+    w.write(1 - 1, 6)  # mappings
+    w.write(0, 16)
+    w.write(0, 1)  # one submap
+    w.write(0, 1)  # no coupling
+    w.write(0, 2)
+    w.write(0, 8)  # submap 0's time config, floor, and residue
+    w.write(0, 8)
+    w.write(0, 8)
+
+    w.write(1 - 1, 6)  # modes
+    w.write(0, 1)  # short blocks
+    w.write(0, 16)
+    w.write(0, 16)
+    w.write(0, 8)
+
+    w.write(1, 1)  # framing
+    return bytes(w.bytes)
+
+
+def audio_packet(levels):
+    w = BitWriter()
+    w.write(0, 1)  # audio packet (and with one mode, that's all it says)
+
+    # floors: flat, at each channel's level (255 is full scale, and each
+    # step down is about 0.55 dB). a level of None leaves the channel silent
+    for level in levels:
+        if level is None:
+            w.write(0, 1)
+        else:
+            w.write(1, 1)
+            w.write(level, 8)
+            w.write(level, 8)
+
+    # residue: silent channels aren't coded at all
+    for partition in range(BLOCKSIZE // 2 // PARTITION_SIZE):
+        has_tone = partition == TONE_BIN // PARTITION_SIZE
+        for level in levels:
+            if level is not None:
+                w.write(int(has_tone), 1)
+        for level in levels:
+            if level is not None and has_tone:
+                for i in range(PARTITION_SIZE):
+                    w.write(int(partition * PARTITION_SIZE + i == TONE_BIN), 1)
+
+    return bytes(w.bytes)
+
+
+def crc32(data):
+    crc = 0
+    for byte in data:
+        crc ^= byte << 24
+        for _ in range(8):
+            crc = (crc << 1) ^ 0x04C11DB7 if crc & 0x80000000 else crc << 1
+            crc &= 0xFFFFFFFF
+    return crc
+
+
+def page(packets, granule, sequence, flags):
+    lacing = bytearray()
+    for packet in packets:
+        lacing += b"\xff" * (len(packet) // 255) + bytes([len(packet) % 255])
+    assert len(lacing) <= 255
+
+    header = b"OggS" + struct.pack("<BBqIIIB", 0, flags, granule, 1, sequence, 0, len(lacing))
+    data = bytearray(header + lacing + b"".join(packets))
+    data[22:26] = struct.pack("<I", crc32(data))
+    return bytes(data)
+
+
+def vorbis_file(levels, packets):
+    pages = [
+        page([identification_header(len(levels))], 0, 0, 0x02),
+        page([comment_header(), setup_header()], 0, 1, 0),
+    ]
+
+    audio = [audio_packet(levels)] * packets
+    for start in range(0, packets, PACKETS_PER_PAGE):
+        end = min(start + PACKETS_PER_PAGE, packets)
+        # (the first packet only primes the decoder)
+        granule = (end - 1) * BLOCKSIZE // 2
+        flags = 0x04 if end == packets else 0
+        pages.append(page(audio[start:end], granule, len(pages), flags))
+
+    return b"".join(pages)
+
+
+if __name__ == "__main__":
+    # a tone panned hard left (right is 12 dB quieter), and the same panned
+    # right: about a tenth of a second each
+    with open("panned_left.ogg", "wb") as f:
+        f.write(vorbis_file([255, 233], 36))
+    with open("panned_right.ogg", "wb") as f:
+        f.write(vorbis_file([233, 255], 36))
+    # more channels than Vorbis gives an order for (only the first is on)
+    with open("ten_channels.ogg", "wb") as f:
+        f.write(vorbis_file([255] + [None] * 9, 36))
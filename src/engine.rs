@@ -0,0 +1,311 @@
+use vulkano::{
+    app_info_from_cargo_toml,
+    instance::{ApplicationInfo, Version},
+};
+
+use winit::dpi::LogicalSize;
+
+use std::borrow::Cow;
+#[cfg(feature = "engine-api")]
+use std::sync::Arc;
+
+#[cfg(feature = "engine-api")]
+use crate::render::ValidationMessage;
+use crate::{
+    audio::{AudioThread, Sink},
+    render::{create_instance, ValidationHandler},
+    window::{EventLoopPolicy, RenderThread, Window, WindowThread},
+    DEFAULT_WINDOW_SIZE,
+};
+
+// the application and engine names and versions are passed on to the Vulkan
+// driver, which may use them to enable workarounds (or just for telemetry),
+// so anything embedding the engine should set its own
+#[derive(Clone)]
+pub struct EngineConfig {
+    pub app_name: Option<Cow<'static, str>>,
+    pub app_version: Option<Version>,
+    pub engine_name: Option<Cow<'static, str>>,
+    pub engine_version: Option<Version>,
+    // None picks whichever suits where the renderer runs (see
+    // EngineBuilder::with_render_thread)
+    pub event_loop_policy: Option<EventLoopPolicy>,
+    // if false, the window stays hidden until Window::show() is called (the
+    // Render does this once it has presented a frame, to avoid a flash of
+    // whatever was in the window before)
+    pub window_visible: bool,
+    pub window_size: Option<LogicalSize>,
+    // the app name is used if this isn't set
+    pub window_title: Option<Cow<'static, str>>,
+    pub window_decorations: bool,
+    // (min, max) for the DPI factor the app sees, for platforms that report
+    // absurd ones. equal bounds override it outright. None trusts winit.
+    pub dpi_factor_range: Option<(f64, f64)>,
+    // overrides PLANETS_VALIDATION and the build's default (see create_instance)
+    pub validation: Option<bool>,
+    // only used if the validation layers are enabled (see create_instance)
+    pub validation_handler: Option<ValidationHandler>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        let ApplicationInfo {
+            application_name,
+            application_version,
+            engine_version,
+            ..
+        } = app_info_from_cargo_toml!();
+
+        Self {
+            app_name: application_name,
+            app_version: application_version,
+            engine_name: Some("Newton".into()),
+            engine_version,
+            event_loop_policy: None,
+            window_visible: true,
+            window_size: DEFAULT_WINDOW_SIZE,
+            window_title: None,
+            window_decorations: true,
+            dpi_factor_range: None,
+            validation: None,
+            validation_handler: None,
+        }
+    }
+}
+
+impl EngineConfig {
+    #[cfg(feature = "engine-api")]
+    pub fn with_app_name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.app_name = Some(name.into());
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_app_version(mut self, version: Version) -> Self {
+        self.app_version = Some(version);
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_engine_name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.engine_name = Some(name.into());
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_engine_version(mut self, version: Version) -> Self {
+        self.engine_version = Some(version);
+        self
+    }
+
+    #[cfg(any(test, feature = "engine-api"))]
+    pub fn with_event_loop_policy(mut self, policy: EventLoopPolicy) -> Self {
+        self.event_loop_policy = Some(policy);
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_window_visible(mut self, visible: bool) -> Self {
+        self.window_visible = visible;
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_window_size(mut self, size: LogicalSize) -> Self {
+        self.window_size = Some(size);
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_window_title(mut self, title: impl Into<Cow<'static, str>>) -> Self {
+        self.window_title = Some(title.into());
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_window_decorations(mut self, decorations: bool) -> Self {
+        self.window_decorations = decorations;
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_dpi_factor_range(mut self, min: f64, max: f64) -> Self {
+        assert!(min > 0.0 && min <= max);
+        self.dpi_factor_range = Some((min, max));
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_validation(mut self, validation: bool) -> Self {
+        self.validation = Some(validation);
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_validation_handler(
+        mut self,
+        handler: impl Fn(&ValidationMessage) + Send + Sync + 'static,
+    ) -> Self {
+        self.validation_handler = Some(Arc::new(handler));
+        self
+    }
+
+    pub fn app_info(&self) -> ApplicationInfo {
+        ApplicationInfo {
+            application_name: self.app_name.clone(),
+            application_version: self.app_version,
+            engine_name: self.engine_name.clone(),
+            engine_version: self.engine_version,
+        }
+    }
+}
+
+// starts everything up in the right order: the audio thread, then the Vulkan
+// instance, then the window (and its event loop), and finally hands them over
+// to the application. the audio doesn't depend on the window in any way, so
+// it keeps playing if the window (or just its surface) is recreated.
+//
+// where the renderer runs depends on the platform (see RenderThread), which
+// is why it's passed in separately:
+//
+//   render_loop(window) is called once on the render thread, and returns a
+//   closure that draws a frame each time it's called
+//   app(window, sink, draw) is the application's main loop. it should call
+//   draw() once per iteration, which also starts a new frame of input (see
+//   Window::update). if rendering happens on the event loop's thread, draw()
+//   waits for the event loop to draw the frame instead, so the loop doesn't
+//   spin.
+#[derive(Clone)]
+pub struct EngineBuilder {
+    config: EngineConfig,
+    render_thread: RenderThread,
+    audio: bool,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self {
+            config: EngineConfig::default(),
+            render_thread: Default::default(),
+            audio: true,
+        }
+    }
+
+    #[cfg(any(test, feature = "engine-api"))]
+    pub fn with_config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    // also picks the event loop policy that suits it, unless the config sets
+    // one (in whichever order the two are set)
+    #[cfg(any(test, feature = "engine-api"))]
+    pub fn with_render_thread(mut self, render_thread: RenderThread) -> Self {
+        self.render_thread = render_thread;
+        self
+    }
+
+    // TODO: pick the audio device too (see issue #2)
+    #[cfg(feature = "engine-api")]
+    pub fn with_audio(mut self, audio: bool) -> Self {
+        self.audio = audio;
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    // the one run() will use: the config's, if it sets one
+    pub fn event_loop_policy(&self) -> EventLoopPolicy {
+        // when rendering on the event loop's thread, frames are only drawn when
+        // the event loop wakes up, so it can't just sit around waiting for input
+        self.config
+            .event_loop_policy
+            .unwrap_or(match self.render_thread {
+                RenderThread::Main => EventLoopPolicy::Poll,
+                RenderThread::Background => EventLoopPolicy::Wait,
+            })
+    }
+
+    pub fn run<R, F>(self, render_loop: R, app: F)
+    where
+        R: for<'w> FnOnce(&'w Window) -> Box<dyn FnMut() + 'w> + Send + 'static,
+        F: for<'w> FnOnce(&'w Window, &mut dyn Sink<'static>, &mut dyn FnMut()) + Send + 'static,
+    {
+        let event_loop_policy = self.event_loop_policy();
+        let Self {
+            mut config,
+            render_thread,
+            audio,
+        } = self;
+        config.event_loop_policy = Some(event_loop_policy);
+
+        let mut audio = if audio {
+            AudioThread::start()
+        } else {
+            AudioThread::start_silent()
+        };
+
+        // the debug callback has to live as long as the instance is in use
+        let (instance, _debug_callback) = create_instance(&config);
+
+        let with_audio =
+            move |window: &Window, draw: &mut dyn FnMut()| app(window, audio.sink(), draw);
+
+        match render_thread {
+            RenderThread::Background => WindowThread::with(instance, &config, move |window| {
+                let mut draw = render_loop(&window);
+                with_audio(&window, &mut || {
+                    window.update();
+                    draw();
+                })
+            }),
+            RenderThread::Main => {
+                WindowThread::with_render(instance, &config, render_loop, move |window| {
+                    with_audio(&window, &mut || window.draw_on_event_loop())
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn with_config_keeps_the_render_threads_policy() {
+        let builder = EngineBuilder::new()
+            .with_render_thread(RenderThread::Main)
+            .with_config(EngineConfig::default());
+        assert_eq!(builder.event_loop_policy(), EventLoopPolicy::Poll);
+
+        let builder = EngineBuilder::new()
+            .with_config(EngineConfig::default())
+            .with_render_thread(RenderThread::Background);
+        assert_eq!(builder.event_loop_policy(), EventLoopPolicy::Wait);
+    }
+
+    #[test]
+    fn the_configs_policy_wins_in_either_order() {
+        let config = EngineConfig::default().with_event_loop_policy(EventLoopPolicy::Wait);
+
+        let builder = EngineBuilder::new()
+            .with_render_thread(RenderThread::Main)
+            .with_config(config.clone());
+        assert_eq!(builder.event_loop_policy(), EventLoopPolicy::Wait);
+
+        let builder = EngineBuilder::new()
+            .with_config(config)
+            .with_render_thread(RenderThread::Main);
+        assert_eq!(builder.event_loop_policy(), EventLoopPolicy::Wait);
+    }
+}
@@ -1,48 +1,289 @@
-use vulkano::{app_info_from_cargo_toml, instance::ApplicationInfo};
+#[cfg(feature = "graphics")]
 use winit::dpi::LogicalSize;
 
+#[cfg(not(feature = "graphics"))]
+use std::io;
+#[cfg(feature = "graphics")]
+use std::time::{Duration, Instant};
+
 mod assets;
+#[cfg(feature = "graphics")]
+mod engine;
+#[cfg(feature = "graphics")]
 mod shaders;
 mod util;
 
+// everything but audio (and assets) is behind the "graphics" feature, so
+// audio-only tools don't have to pull in Vulkan or winit
 mod audio;
+#[cfg(feature = "graphics")]
 mod render;
+#[cfg(feature = "graphics")]
+mod simulation;
+#[cfg(feature = "graphics")]
 mod window;
 
-use audio::{music, AudioThread};
-use render::{create_instance, Render};
-use window::{InputID, WindowThread};
-
-pub fn get_app_info() -> ApplicationInfo<'static> {
-    ApplicationInfo {
-        engine_name: Some("Newton".into()),
-        ..app_info_from_cargo_toml!()
-    }
-}
+use audio::music;
+#[cfg(not(feature = "graphics"))]
+use audio::AudioThread;
+#[cfg(feature = "graphics")]
+use audio::Sink;
+#[cfg(feature = "graphics")]
+use engine::EngineBuilder;
+#[cfg(feature = "graphics")]
+use render::{Render, RenderError};
+#[cfg(feature = "graphics")]
+use window::{InputID, InputSet, Trigger, Window};
 
+#[cfg(feature = "graphics")]
 const DEFAULT_WINDOW_SIZE: Option<LogicalSize> = Some(LogicalSize {
     width: 1280.0,
     height: 720.0,
 });
 
+// quit as soon as Q goes down, rather than waiting for it to be let go
+#[cfg(feature = "graphics")]
+const QUIT_TRIGGER: Trigger = Trigger::Press;
+
+// how much each press of [ or ] (gravity) and - or = (softening) changes them
+#[cfg(feature = "graphics")]
+const TWEAK_FACTOR: f32 = 1.25;
+
+// how far the arrow keys (or WASD) move the camera each second (the window
+// is 2.0 across, either way), and how many times closer page up (or further
+// page down) gets
+#[cfg(feature = "graphics")]
+const PAN_SPEED: f32 = 2.0;
+#[cfg(feature = "graphics")]
+const ZOOM_SPEED: f32 = 2.0;
+
+// how many times in a row to rebuild the renderer after losing the GPU before
+// giving up on rendering altogether
+#[cfg(feature = "graphics")]
+const MAX_RECOVERIES: u32 = 3;
+
+// how often to log how the simulation is going (PLANETS_LOG=debug to see it)
+#[cfg(feature = "graphics")]
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+// with no window to close (or Q to press), play the music until enter is
+// pressed or stdin is closed
+#[cfg(not(feature = "graphics"))]
 fn main() {
-    let (instance, _debug_callback) = create_instance();
-    WindowThread::with(instance.clone(), move |window| {
-        AudioThread::with(|mut sink| {
-            let mut render = Render::new(&window);
+    util::init_logger();
+
+    AudioThread::with(|mut sink| {
+        sink.play(None, music::vlem(sink.as_ref()));
 
-            sink.play(None, music::vlem(sink.as_ref()));
+        let _ = io::stdin().read_line(&mut String::new());
+    });
+}
 
-            let events = window.events();
+#[cfg(feature = "graphics")]
+fn main() {
+    util::init_logger();
 
-            let quit_key = events
-                .key_state()
-                .bind(InputID::Key(16).into())
-                .into_inner(); // Q
-            while !(events.closed() || quit_key.released()) {
-                window.update();
-                render.update();
+    EngineBuilder::new().run(render_loop, run);
+}
+
+#[cfg(feature = "graphics")]
+fn try_render(window: &Window) -> Option<Render> {
+    let render = Render::try_new(window)
+        .map_err(|e| log::warn!("rendering disabled: {}", e))
+        .ok()?;
+
+    let extent = render.extent();
+    log::debug!(
+        "drawing {}x{} frames in {:?}",
+        extent[0],
+        extent[1],
+        render.swapchain_format()
+    );
+
+    if !render.is_discrete_gpu() {
+        log::info!("not rendering on a discrete GPU, so big simulations may be slow");
+    }
+
+    if render.present_mode_fell_back() {
+        log::warn!(
+            "presenting with {:?}, so expect some tearing or stutter",
+            render.present_mode()
+        );
+    }
+
+    Some(render)
+}
+
+// tears the renderer down with Render::destroy() once the render loop is
+// dropped (i.e. on shutdown), so the GPU is finished with everything before
+// the window goes away, and it's reported if the GPU was lost on the way out
+#[cfg(feature = "graphics")]
+struct Renderer<'a>(Option<Render<'a>>);
+
+#[cfg(feature = "graphics")]
+impl Drop for Renderer<'_> {
+    fn drop(&mut self) {
+        if let Some(render) = self.0.take() {
+            if let Err(e) = render.destroy() {
+                log::error!("couldn't shut rendering down cleanly: {}", e);
             }
-        });
-    });
+        }
+    }
+}
+
+#[cfg(feature = "graphics")]
+fn render_loop(window: &Window) -> Box<dyn FnMut() + '_> {
+    let mut renderer = Renderer(try_render(window));
+
+    let pause_key = window
+        .key_state()
+        .bind(InputID::Key(25).into())
+        .into_inner(); // P
+    let step_key = window
+        .key_state()
+        .bind(InputID::Key(49).into())
+        .into_inner(); // N
+    let reset_key = window
+        .key_state()
+        .bind(InputID::Key(19).into())
+        .into_inner(); // R
+    let weaker_gravity_key = window
+        .key_state()
+        .bind(InputID::Key(26).into())
+        .into_inner(); // [
+    let stronger_gravity_key = window
+        .key_state()
+        .bind(InputID::Key(27).into())
+        .into_inner(); // ]
+    let less_softening_key = window
+        .key_state()
+        .bind(InputID::Key(12).into())
+        .into_inner(); // -
+    let more_softening_key = window
+        .key_state()
+        .bind(InputID::Key(13).into())
+        .into_inner(); // =
+
+    let keys = |codes: &[u32]| {
+        window
+            .key_state()
+            .bind_any(codes.iter().map(|&code| InputID::Key(code).into()))
+    };
+    let pan_up_keys = keys(&[103, 17]); // up, W
+    let pan_left_keys = keys(&[105, 30]); // left, A
+    let pan_down_keys = keys(&[108, 31]); // down, S
+    let pan_right_keys = keys(&[106, 32]); // right, D
+    let zoom_in_key = keys(&[104]); // page up
+    let zoom_out_key = keys(&[109]); // page down
+    let mut last_frame = Instant::now();
+    let mut last_stats = last_frame;
+    let mut recoveries = 0;
+
+    Box::new(move || {
+        // the camera moves by how long the last frame took, so it glides at
+        // the same speed however fast frames are drawn
+        let now = Instant::now();
+        let dt = now.duration_since(last_frame).as_secs_f32();
+        last_frame = now;
+
+        let render = &mut renderer.0;
+        if let Some(r) = render {
+            match r.update() {
+                Ok(()) => recoveries = 0,
+                Err(RenderError::DeviceLost) if recoveries < MAX_RECOVERIES => {
+                    recoveries += 1;
+                    log::warn!(
+                        "lost the GPU, starting over (try {} of {})",
+                        recoveries,
+                        MAX_RECOVERIES
+                    );
+                    *render = render.take().and_then(|r| {
+                        r.recover()
+                            .map_err(|e| log::error!("rendering stopped: {}", e))
+                            .ok()
+                    });
+                }
+                Err(e) => {
+                    log::error!("rendering stopped: {}", e);
+                    *render = None;
+                }
+            }
+        }
+
+        // input is read after drawing, right before the next frame's
+        // Window::update(), so presses that came in while this one was being
+        // drawn aren't missed
+        if let Some(r) = render {
+            let axis = |negative: &InputSet, positive: &InputSet| {
+                (positive.down() as i32 - negative.down() as i32) as f32
+            };
+            let pan = [
+                axis(&pan_left_keys, &pan_right_keys) * PAN_SPEED * dt,
+                // (y points down on screen)
+                axis(&pan_up_keys, &pan_down_keys) * PAN_SPEED * dt,
+            ];
+            let zoom = ZOOM_SPEED.powf(axis(&zoom_out_key, &zoom_in_key) * dt);
+            // cheap even when nothing moved; the view eases toward it
+            r.set_camera(r.camera().panned_by(pan).zoomed_by(zoom));
+
+            if pause_key.pressed() {
+                r.set_paused(!r.paused());
+            }
+            if step_key.pressed() {
+                r.step(1);
+            }
+            if reset_key.pressed() {
+                r.reset_simulation();
+            }
+            if weaker_gravity_key.pressed() {
+                r.set_gravity(r.gravity() / TWEAK_FACTOR);
+            }
+            if stronger_gravity_key.pressed() {
+                r.set_gravity(r.gravity() * TWEAK_FACTOR);
+            }
+            if less_softening_key.pressed() {
+                r.set_softening(r.softening() / TWEAK_FACTOR);
+            }
+            if more_softening_key.pressed() {
+                r.set_softening(r.softening() * TWEAK_FACTOR);
+            }
+
+            let elapsed = now.duration_since(last_stats);
+            if elapsed >= STATS_INTERVAL {
+                last_stats = now;
+                let frames = r.frame_stats();
+                log::debug!(
+                    "T+{:.1} s ({} steps), frames took {:?} on average \
+                     ({:?} to {:?}, p50 {:?}, p99 {:?})",
+                    r.sim_time(),
+                    r.sim_steps(),
+                    frames.avg,
+                    frames.min,
+                    frames.max,
+                    frames.p50,
+                    frames.p99
+                );
+                log::debug!(
+                    "the event loop sat idle for {:?} of the last {:?}",
+                    window.events().take_idle_time(),
+                    elapsed
+                );
+            }
+        }
+    })
+}
+
+#[cfg(feature = "graphics")]
+fn run(window: &Window, sink: &mut dyn Sink<'static>, draw: &mut dyn FnMut()) {
+    sink.play(None, music::vlem(sink));
+
+    let events = window.events();
+
+    let quit_key = events
+        .key_state()
+        .bind(InputID::Key(16).into())
+        .into_inner(); // Q
+    while !(events.closed() || quit_key.triggered(QUIT_TRIGGER)) {
+        draw();
+    }
 }
@@ -1,56 +1,191 @@
+#[cfg(feature = "engine-api")]
+use vulkano::swapchain::Capabilities;
 use vulkano::{
-    buffer::BufferAccess,
-    command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder, DynamicState},
-    device::Device,
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    device::{Device, DeviceCreationError},
+    format::Format,
     framebuffer::{FramebufferAbstract, RenderPassAbstract},
     image::swapchain::SwapchainImage,
+    instance::PhysicalDeviceType,
     pipeline::GraphicsPipelineAbstract,
-    swapchain::{acquire_next_image, AcquireError, Swapchain},
-    sync::{self, GpuFuture},
+    swapchain::{acquire_next_image, AcquireError, PresentMode, Swapchain, SwapchainCreationError},
+    sync::{self, FenceSignalFuture, GpuFuture},
 };
 use winit::{dpi::PhysicalSize, window::Window as WinitWindow};
 
-use std::sync::Arc;
+use std::{
+    fmt, mem,
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
+mod camera;
 mod config;
+mod frames;
+mod handle;
+mod pacing;
+mod particles;
 mod queues;
 mod setup;
+mod spirv;
+mod stats;
+mod trails;
 
 use config::DeviceConfig;
+use frames::{FrameSlot, Vertices};
+use handle::Command;
+use pacing::FramePacer;
 use queues::Queues;
+use setup::{PipelineSettings, SwapchainParts};
+use spirv::ShaderModules;
+use stats::FrameTimer;
+use trails::Trails;
 
 use crate::{
-    util::ToExtents,
+    simulation::{Integrator, Precision, Simulation, SimulationMethod},
+    util::{clamp, clamp_window_size, ToExtents},
     window::{Window, WindowEvents},
 };
 
-pub use setup::create_instance;
+pub use camera::Camera;
+pub use handle::RenderHandle;
+pub use particles::{ColorMode, Particle, ParticleDistribution, PointShape};
+pub use setup::{create_instance, ValidationHandler};
+#[cfg(feature = "engine-api")]
+pub use setup::{validation_enabled, ValidationMessage, ValidationSeverity};
+pub use spirv::{ParticleShaders, ShaderError};
+pub use stats::FrameStats;
+
+// how many frames the CPU can get ahead of the GPU by
+const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+type FrameFuture = FenceSignalFuture<Box<dyn GpuFuture>>;
+
+// the clear color is the only transparency in the game (see choose_alpha_mode)
+const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
 
-#[derive(Default)]
-struct Particle; // TODO: real Particle struct linked to vertex shader
+#[derive(Debug)]
+pub enum RenderError {
+    NoSuitableDevice,
+    DeviceCreation(DeviceCreationError),
+    SwapchainCreation(SwapchainCreationError),
+    Shader(ShaderError),
+    SurfaceLost,
+    // the GPU hung, was reset, or had its driver updated out from under us.
+    // Render::recover() can start over with a new device.
+    DeviceLost,
+}
 
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::NoSuitableDevice => write!(f, "no Vulkan-capable devices (GPUs) found"),
+            RenderError::DeviceCreation(e) => write!(f, "failed to create logical device: {}", e),
+            RenderError::SwapchainCreation(e) => write!(f, "failed to create swapchain: {}", e),
+            RenderError::Shader(e) => write!(f, "{}", e),
+            RenderError::SurfaceLost => write!(f, "the window's surface was lost"),
+            RenderError::DeviceLost => write!(f, "lost the connection to the GPU"),
+        }
+    }
+}
+
+// NOTE: fields are dropped in the order they're declared, and Vulkan objects
+// have to be destroyed before whatever they were made from. so the frames in
+// flight go first (dropping one waits for the GPU to finish it), then what
+// they used, and the device last. the surface and instance belong to the
+// Window, which outlives every Render that borrows it.
 pub struct Render<'a> {
+    frames_in_flight: Vec<Option<FrameFuture>>,
+    // one for each frame in flight
+    frame_slots: Vec<FrameSlot>,
+    swapchain_framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+    graphics_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    trail_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    shader_modules: ShaderModules,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    swapchain_images: Vec<Arc<SwapchainImage<WinitWindow>>>,
+    swapchain: Arc<Swapchain<WinitWindow>>,
+    queues: Queues,
+    device: Arc<Device>,
+    device_config: DeviceConfig,
     window: &'a Window,
     events: Arc<WindowEvents>,
-    device_config: DeviceConfig,
-    device: Arc<Device>,
-    queues: Queues,
-    swapchain: Arc<Swapchain<WinitWindow>>,
-    swapchain_images: Vec<Arc<SwapchainImage<WinitWindow>>>,
-    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
-    graphics_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
-    swapchain_framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
-    vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
-    command_buffers: Vec<Arc<AutoCommandBuffer>>,
-    previous_frame_end: Option<Box<dyn GpuFuture>>,
+    shaders: ParticleShaders,
+    pipeline_settings: PipelineSettings,
+    simulation: Simulation,
+    particles: Vec<Particle>,
+    // what reset_simulation() goes back to
+    initial_particles: Vec<Particle>,
+    // (the vertices have to be recomputed from them)
+    particles_dirty: bool,
+    vertices: Vertices,
+    trails: Trails,
+    // what's on screen, which glides toward target_camera
+    camera: Camera,
+    target_camera: Camera,
+    camera_eased: Instant,
+    color_mode: ColorMode,
+    frame_index: usize,
+    // unlike frame_index, only counts frames that were actually presented
+    frame_count: u64,
+    frame_timer: FrameTimer,
+    frame_pacer: FramePacer,
+    target_refresh: Option<u32>,
+    prefer_hdr: bool,
+    commands: Receiver<Command>,
+    command_sender: Sender<Command>,
+}
+
+// the parts of a Render that don't live on the GPU (see Render::recover)
+struct RenderState<'a> {
+    window: &'a Window,
+    shaders: ParticleShaders,
+    pipeline_settings: PipelineSettings,
+    simulation: Simulation,
+    particles: Vec<Particle>,
+    initial_particles: Vec<Particle>,
+    trails: Trails,
+    camera: Camera,
+    target_camera: Camera,
+    color_mode: ColorMode,
+    frame_count: u64,
+    target_refresh: Option<u32>,
+    prefer_hdr: bool,
+    commands: Receiver<Command>,
+    command_sender: Sender<Command>,
 }
 
 impl<'a> Render<'a> {
+    #[cfg(feature = "engine-api")]
     pub fn new(window: &'a Window) -> Self {
+        Self::with_frames_in_flight(window, DEFAULT_FRAMES_IN_FLIGHT)
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_frames_in_flight(window: &'a Window, frames_in_flight: usize) -> Self {
+        Self::try_with_frames_in_flight(window, frames_in_flight)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // like new(), but fails cleanly if there is no usable GPU (so the caller
+    // can carry on without rendering, like the audio side does)
+    pub fn try_new(window: &'a Window) -> Result<Self, RenderError> {
+        Self::try_with_frames_in_flight(window, DEFAULT_FRAMES_IN_FLIGHT)
+    }
+
+    pub fn try_with_frames_in_flight(
+        window: &'a Window,
+        frames_in_flight: usize,
+    ) -> Result<Self, RenderError> {
+        assert!(frames_in_flight > 0);
+
         let events = window.events();
 
         let (device, device_config, queues) =
-            setup::create_logical_device(&window.instance(), &window.surface());
+            setup::create_logical_device(&window.instance(), &window.surface())?;
 
         let dimensions = window.dimensions();
 
@@ -60,22 +195,53 @@ impl<'a> Render<'a> {
             dimensions,
             &device_config,
             &queues,
-        );
+            None,
+        )
+        .map_err(RenderError::SwapchainCreation)?;
 
         let render_pass = setup::create_render_pass(device.clone(), swapchain.format());
 
+        let shaders = ParticleShaders::builtin();
+        let shader_modules =
+            ShaderModules::load(device.clone(), &shaders).map_err(RenderError::Shader)?;
+
+        let pipeline_settings = PipelineSettings::default();
         let graphics_pipeline = setup::create_graphics_pipeline(
             device.clone(),
-            dimensions,
-            &device_config,
             render_pass.clone(),
+            &shader_modules,
+            pipeline_settings,
         );
+        let trail_pipeline =
+            setup::create_trail_pipeline(device.clone(), render_pass.clone(), pipeline_settings);
 
         let swapchain_framebuffers = setup::create_framebuffers(&swapchain_images, &render_pass);
 
-        let vertex_buffer = setup::create_vertex_buffer(device.clone());
+        let particles = vec![
+            Particle {
+                position: [-0.5, -0.5],
+                ..Default::default()
+            },
+            Particle {
+                position: [-0.5, 0.5],
+                ..Default::default()
+            },
+            Particle {
+                position: [0.5, 0.5],
+                ..Default::default()
+            },
+            Particle {
+                position: [0.5, -0.5],
+                ..Default::default()
+            },
+        ];
 
-        let previous_frame_end = Some(setup::create_sync_objects(device.clone()));
+        let frame_slots = (0..frames_in_flight)
+            .map(|_| FrameSlot::new(device.clone()))
+            .collect();
+        let frames_in_flight = setup::create_sync_objects(frames_in_flight);
+
+        let (command_sender, commands) = handle::channel();
 
         let mut me = Self {
             window,
@@ -86,112 +252,687 @@ impl<'a> Render<'a> {
             swapchain,
             swapchain_images,
             render_pass,
+            shaders,
+            shader_modules,
+            pipeline_settings,
             graphics_pipeline,
+            trail_pipeline,
             swapchain_framebuffers,
-            vertex_buffer,
-            command_buffers: Vec::new(),
-            previous_frame_end,
+            simulation: Simulation::new(),
+            initial_particles: particles.clone(),
+            particles,
+            particles_dirty: true,
+            vertices: Default::default(),
+            trails: Trails::new(),
+            camera: Default::default(),
+            target_camera: Default::default(),
+            camera_eased: Instant::now(),
+            color_mode: Default::default(),
+            frame_slots,
+            frames_in_flight,
+            frame_index: 0,
+            frame_count: 0,
+            frame_timer: FrameTimer::new(),
+            frame_pacer: FramePacer::new(),
+            target_refresh: None,
+            prefer_hdr: false,
+            commands,
+            command_sender,
         };
 
         me.create_command_buffers();
 
-        me
+        Ok(me)
     }
 
+    // for every frame in flight, e.g. after a resize. the vertices changing
+    // doesn't need this (see FrameSlot).
     fn create_command_buffers(&mut self) {
+        for slot in 0..self.frame_slots.len() {
+            self.record_command_buffers(slot);
+        }
+    }
+
+    fn record_command_buffers(&mut self, slot: usize) {
         let queue_family = self.queues.graphics.family();
-        self.command_buffers = self
+        let clear_color =
+            config::clear_color_for_alpha_mode(CLEAR_COLOR, self.device_config.composite_alpha);
+        let particles = self.frame_slots[slot].particle_slice();
+        let trails = self.frame_slots[slot].trail_slice();
+        let dynamic_state = DynamicState {
+            viewports: Some(vec![setup::create_viewport(self.swapchain.dimensions())]),
+            ..DynamicState::none()
+        };
+
+        self.frame_slots[slot].command_buffers = self
             .swapchain_framebuffers
             .iter()
             .map(|fb| {
-                Arc::new(
-                    AutoCommandBufferBuilder::primary_simultaneous_use(
-                        self.device.clone(),
-                        queue_family,
-                    )
-                    .unwrap()
-                    .begin_render_pass(fb.clone(), false, vec![[0.0, 0.0, 0.0, 1.0].into()])
-                    .unwrap()
-                    .draw(
-                        self.graphics_pipeline.clone(),
-                        &DynamicState::none(),
-                        vec![self.vertex_buffer.clone()],
-                        (),
-                        (),
-                    )
-                    .unwrap()
-                    .end_render_pass()
-                    .unwrap()
-                    .build()
-                    .unwrap(),
+                let mut builder = AutoCommandBufferBuilder::primary_simultaneous_use(
+                    self.device.clone(),
+                    queue_family,
                 )
+                .unwrap()
+                .begin_render_pass(fb.clone(), false, vec![clear_color.into()])
+                .unwrap();
+
+                // drawing zero vertices isn't allowed. the trails go first,
+                // so the particles are drawn over them
+                if let Some(trails) = &trails {
+                    builder = builder
+                        .draw(
+                            self.trail_pipeline.clone(),
+                            &dynamic_state,
+                            vec![trails.clone()],
+                            (),
+                            (),
+                        )
+                        .unwrap();
+                }
+                if let Some(particles) = &particles {
+                    builder = builder
+                        .draw(
+                            self.graphics_pipeline.clone(),
+                            &dynamic_state,
+                            vec![particles.clone()],
+                            (),
+                            (),
+                        )
+                        .unwrap();
+                }
+
+                Arc::new(builder.end_render_pass().unwrap().build().unwrap())
             })
             .collect();
     }
 
-    fn resize_to(&mut self, dimensions: PhysicalSize) {
-        let (swapchain, swapchain_images) = self
-            .swapchain
-            .recreate_with_dimension(dimensions.to_extents())
-            .unwrap();
+    fn resize_to(&mut self, dimensions: PhysicalSize) -> Result<(), RenderError> {
+        let (swapchain, swapchain_images) = match self.recreate_with_dimension(dimensions) {
+            Err(SwapchainCreationError::SurfaceLost) => self.rebuild_swapchain(dimensions)?,
+            Err(SwapchainCreationError::DeviceLost) => return Err(self.device_lost()),
+            result => result.map_err(RenderError::SwapchainCreation)?,
+        };
+        self.replace_swapchain(swapchain, swapchain_images);
+
+        Ok(())
+    }
+
+    fn replace_swapchain(
+        &mut self,
+        swapchain: Arc<Swapchain<WinitWindow>>,
+        swapchain_images: Vec<Arc<SwapchainImage<WinitWindow>>>,
+    ) {
+        let new_pipeline = setup::needs_new_pipeline(self.swapchain.format(), swapchain.format());
         self.swapchain = swapchain;
         self.swapchain_images = swapchain_images;
 
-        self.render_pass = setup::create_render_pass(self.device.clone(), self.swapchain.format());
-        self.graphics_pipeline = setup::create_graphics_pipeline(
-            self.device.clone(),
-            dimensions,
-            &self.device_config,
-            self.render_pass.clone(),
-        );
+        // the format practically never changes (except with set_prefer_hdr),
+        // so usually only the framebuffers (and the command buffers drawing
+        // to them) have to be remade, and the shaders aren't recompiled
+        if new_pipeline {
+            self.render_pass =
+                setup::create_render_pass(self.device.clone(), self.swapchain.format());
+            self.rebuild_pipeline();
+        }
         self.swapchain_framebuffers =
             setup::create_framebuffers(&self.swapchain_images, &self.render_pass);
         self.create_command_buffers();
     }
 
-    fn recreate_swapchain(&mut self) {
-        self.resize_to(self.window.dimensions());
+    fn recreate_with_dimension(
+        &self,
+        dimensions: PhysicalSize,
+    ) -> Result<SwapchainParts, SwapchainCreationError> {
+        let extents = dimensions.to_extents();
+
+        match self.swapchain.recreate_with_dimension(extents) {
+            // retrying won't bring the surface back (see rebuild_swapchain)
+            Err(SwapchainCreationError::SurfaceLost) => Err(SwapchainCreationError::SurfaceLost),
+            Err(e) => {
+                // some drivers fail spuriously while e.g. alt-tabbing out of
+                // fullscreen, and succeed if asked again
+                log::warn!("failed to recreate swapchain ({}), retrying", e);
+                self.swapchain.recreate_with_dimension(extents)
+            }
+            result => result,
+        }
+    }
+
+    // NOTE: a lost surface should really be replaced along with the
+    // swapchain, but the surface owns the winit window, so it can't be
+    // rebuilt in place. instead, re-query the surface (which fails if it's
+    // really gone) and build a new swapchain from scratch with whatever
+    // capabilities it has now, which is enough for drivers that report
+    // VK_ERROR_SURFACE_LOST_KHR on display changes.
+    fn rebuild_swapchain(
+        &mut self,
+        dimensions: PhysicalSize,
+    ) -> Result<SwapchainParts, RenderError> {
+        let surface = self.window.surface();
+
+        self.device_config.capabilities = surface
+            .capabilities(self.device.physical_device())
+            .map_err(|_| RenderError::SurfaceLost)?;
+
+        setup::create_swapchain(
+            surface,
+            self.device.clone(),
+            dimensions,
+            &self.device_config,
+            &self.queues,
+            Some(&self.swapchain),
+        )
+        .map_err(|e| match e {
+            SwapchainCreationError::SurfaceLost => RenderError::SurfaceLost,
+            e => RenderError::SwapchainCreation(e),
+        })
+    }
+
+    fn swapchain_suboptimal(&self) -> bool {
+        let capabilities = &self.device_config.capabilities;
+        let window_extents: [u32; 2] =
+            clamp_window_size(self.window.dimensions(), capabilities).to_extents();
+
+        self.swapchain.dimensions() != window_extents
+    }
+
+    fn recreate_swapchain(&mut self) -> Result<(), RenderError> {
+        self.resize_to(self.window.dimensions())
+    }
+
+    fn wait_for_frame(&mut self, slot: usize) -> Result<(), RenderError> {
+        if let Some(frame) = self.frames_in_flight[slot].take() {
+            match frame.wait(None) {
+                Ok(()) => {}
+                Err(sync::FlushError::DeviceLost) => {
+                    mem::forget(frame);
+                    return Err(self.device_lost());
+                }
+                Err(e) => log::error!("waiting for frame in flight failed: {:?}", e),
+            }
+        }
+
+        Ok(())
+    }
+
+    // a lost device's fences will never be signaled, and vulkano panics when
+    // it drops a frame it can't wait for, so leak the frames in flight instead
+    fn device_lost(&mut self) -> RenderError {
+        for frame in self.frames_in_flight.iter_mut().filter_map(Option::take) {
+            mem::forget(frame);
+        }
+
+        RenderError::DeviceLost
     }
 
-    fn draw_frame(&mut self) {
-        self.previous_frame_end.as_mut().unwrap().cleanup_finished();
+    fn draw_frame(&mut self) -> Result<(), RenderError> {
+        let slot = self.frame_index % self.frames_in_flight.len();
+
+        // before reusing this slot, wait for the frame that last used it. the
+        // other frames in flight can keep the GPU busy in the meantime.
+        self.wait_for_frame(slot)?;
+
+        for frame in self.frames_in_flight.iter_mut().flatten() {
+            frame.cleanup_finished();
+        }
+
+        // now that nothing's reading this slot's vertices, they can be updated
+        self.prepare_slot(slot);
 
         let (index, acquire_future) = loop {
             match acquire_next_image(self.swapchain.clone(), None) {
-                Err(AcquireError::OutOfDate) => self.recreate_swapchain(),
+                Err(AcquireError::OutOfDate) => self.recreate_swapchain()?,
+                Err(AcquireError::DeviceLost) => return Err(self.device_lost()),
                 x => break x.unwrap(),
             }
         };
 
-        let command_buffer = self.command_buffers[index].clone();
+        // vulkano treats VK_SUBOPTIMAL_KHR as success without telling us, so
+        // check for its usual cause (the window changing size, e.g. when
+        // moved to a display with a different DPI) ourselves. unlike
+        // OutOfDate, the image can still be presented, so draw it first and
+        // recreate the swapchain afterwards instead of dropping a frame.
+        let suboptimal = self.swapchain_suboptimal();
 
-        let future = self
-            .previous_frame_end
-            .take()
-            .unwrap()
+        let command_buffer = self.frame_slots[slot].command_buffers[index].clone();
+
+        let rendered = sync::now(self.device.clone())
             .join(acquire_future)
             .then_execute(self.queues.graphics.clone(), command_buffer)
-            .unwrap()
-            .then_swapchain_present(self.queues.present.clone(), self.swapchain.clone(), index)
-            .then_signal_fence_and_flush();
-
-        self.previous_frame_end = Some(match future {
-            Ok(future) => Box::new(future),
-            Err(sync::FlushError::OutOfDate) => {
-                self.recreate_swapchain();
-                Box::new(sync::now(self.device.clone()))
+            .unwrap();
+
+        let future: Box<dyn GpuFuture> = if self.queues.graphics.is_same(&self.queues.present) {
+            Box::new(rendered.then_swapchain_present(
+                self.queues.present.clone(),
+                self.swapchain.clone(),
+                index,
+            ))
+        } else {
+            // the present queue has to wait for drawing to finish on the
+            // graphics queue, which takes a semaphore. the image itself needs
+            // no ownership transfer: when the queue families differ, the
+            // swapchain is shared between them (see queues::get_sharing_mode).
+            Box::new(rendered.then_signal_semaphore().then_swapchain_present(
+                self.queues.present.clone(),
+                self.swapchain.clone(),
+                index,
+            ))
+        };
+
+        match future.then_signal_fence_and_flush() {
+            Ok(future) => {
+                self.frames_in_flight[slot] = Some(future);
+                self.frame_count += 1;
+
+                // now there's something to show (see EngineConfig::window_visible)
+                if self.frame_count == 1 {
+                    self.window.show();
+                }
             }
-            Err(e) => {
-                eprintln!("frame end sync failed: {:?}", e);
-                Box::new(sync::now(self.device.clone()))
+            Err(sync::FlushError::OutOfDate) => self.recreate_swapchain()?,
+            Err(sync::FlushError::DeviceLost) => return Err(self.device_lost()),
+            Err(e) => log::error!("frame end sync failed: {:?}", e),
+        }
+
+        self.frame_index = self.frame_index.wrapping_add(1);
+        self.frame_timer.record();
+
+        // (unless presenting found it out of date and it's been recreated)
+        if suboptimal && self.swapchain_suboptimal() {
+            self.recreate_swapchain()?;
+        }
+
+        Ok(())
+    }
+
+    // for chaining GPU work of your own onto the frame that was submitted
+    // last (e.g. copying it out for a video encoder). `f` gets that frame's
+    // future, and whatever it returns is flushed and takes the frame's place,
+    // so draw_frame() waits for both before reusing the frame's slot. the
+    // future can't be handed out for good, since the frame's resources have
+    // to stay alive (and waited on) until the GPU is done with them.
+    // returns false (without calling `f`) if there's no such frame, e.g.
+    // because submitting it failed.
+    #[cfg(feature = "engine-api")]
+    pub fn chain_present_future<F>(&mut self, f: F) -> Result<bool, RenderError>
+    where
+        F: FnOnce(Box<dyn GpuFuture>) -> Box<dyn GpuFuture>,
+    {
+        let slot = self.frame_index.wrapping_sub(1) % self.frames_in_flight.len();
+        let frame = match self.frames_in_flight[slot].take() {
+            Some(frame) => frame,
+            None => return Ok(false),
+        };
+
+        match f(Box::new(frame)).then_signal_fence_and_flush() {
+            Ok(future) => self.frames_in_flight[slot] = Some(future),
+            Err(sync::FlushError::DeviceLost) => return Err(self.device_lost()),
+            Err(e) => log::error!("chained frame work failed to flush: {:?}", e),
+        }
+
+        Ok(true)
+    }
+
+    // swaps out the particle shaders; if the new ones can't be loaded, the
+    // old ones stay
+    pub fn set_shaders(&mut self, shaders: ParticleShaders) -> Result<(), ShaderError> {
+        self.shader_modules = ShaderModules::load(self.device.clone(), &shaders)?;
+        self.shaders = shaders;
+
+        self.rebuild_pipeline();
+        self.create_command_buffers();
+
+        Ok(())
+    }
+
+    // (the command buffers have to be rebuilt afterwards too)
+    fn rebuild_pipeline(&mut self) {
+        self.graphics_pipeline = setup::create_graphics_pipeline(
+            self.device.clone(),
+            self.render_pass.clone(),
+            &self.shader_modules,
+            self.pipeline_settings,
+        );
+        self.trail_pipeline = setup::create_trail_pipeline(
+            self.device.clone(),
+            self.render_pass.clone(),
+            self.pipeline_settings,
+        );
+    }
+
+    fn set_pipeline_settings(&mut self, settings: PipelineSettings) {
+        if settings == self.pipeline_settings {
+            return;
+        }
+
+        self.pipeline_settings = settings;
+        self.rebuild_pipeline();
+        self.create_command_buffers();
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn point_shape(&self) -> PointShape {
+        self.pipeline_settings.point_shape
+    }
+
+    pub fn set_point_shape(&mut self, point_shape: PointShape) {
+        self.set_pipeline_settings(PipelineSettings {
+            point_shape,
+            ..self.pipeline_settings
+        });
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn particle_alpha(&self) -> f32 {
+        self.pipeline_settings.particle_alpha
+    }
+
+    // makes every particle translucent (1.0 is opaque). like the point shape,
+    // this is baked into the pipeline, so it's meant to be set now and then
+    // rather than animated every frame. see create_graphics_pipeline for how
+    // this works with the window's composite alpha mode.
+    pub fn set_particle_alpha(&mut self, alpha: f32) {
+        self.set_pipeline_settings(PipelineSettings {
+            particle_alpha: clamp(alpha, 0.0, 1.0),
+            ..self.pipeline_settings
+        });
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn line_width(&self) -> f32 {
+        self.pipeline_settings.line_width
+    }
+
+    // how wide the trails are drawn (see set_trail_length). widths the GPU
+    // can't do are clamped to the closest one it can.
+    pub fn set_line_width(&mut self, width: f32) {
+        let line_width = match self.device_config.line_width_range {
+            Some([min, max]) => clamp(width, min, max),
+            None => {
+                if width != 1.0 {
+                    log::warn!("GPU doesn't support wide lines; using a line width of 1.0");
+                }
+                1.0
             }
+        };
+
+        self.set_pipeline_settings(PipelineSettings {
+            line_width,
+            ..self.pipeline_settings
         });
     }
 
-    pub fn update(&mut self) {
+    // what the window's surface supports (e.g. min/max_image_extent), as of
+    // the last time the swapchain was created
+    #[cfg(feature = "engine-api")]
+    pub fn surface_capabilities(&self) -> &Capabilities {
+        &self.device_config.capabilities
+    }
+
+    // what the swapchain's images (i.e. what's drawn) are made of, e.g. for
+    // swizzling them after copying them out. B8G8R8A8Unorm on most systems,
+    // unless set_prefer_hdr picked something wider
+    pub fn swapchain_format(&self) -> Format {
+        self.swapchain.format()
+    }
+
+    // the size of the swapchain's images, which can lag behind the window's
+    // for a frame while it's being resized
+    pub fn extent(&self) -> [u32; 2] {
+        self.swapchain.dimensions()
+    }
+
+    // the closest size to the given one the swapchain can actually be, e.g.
+    // for capping a window size before requesting it
+    #[cfg(feature = "engine-api")]
+    pub fn clamp_window_size(&self, dimensions: PhysicalSize) -> PhysicalSize {
+        clamp_window_size(dimensions, self.surface_capabilities())
+    }
+
+    // how many frames have been presented so far, e.g. for matching up a
+    // profiler capture with what was on screen. keeps counting after recover()
+    #[cfg(feature = "engine-api")]
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_timer.stats()
+    }
+
+    // what the swapchain presents with, e.g. for telling whether tearing or
+    // stutter is down to the driver not supporting Mailbox
+    pub fn present_mode(&self) -> PresentMode {
+        self.device_config.present_mode
+    }
+
+    // true if present_mode() is Fifo (or Immediate) only because Mailbox
+    // isn't available
+    pub fn present_mode_fell_back(&self) -> bool {
+        !self.device_config.got_preferred_present_mode()
+    }
+
+    // false for integrated GPUs, but also for software renderers and the like
+    pub fn is_discrete_gpu(&self) -> bool {
+        self.device_config.device_type == PhysicalDeviceType::DiscreteGpu
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn target_refresh(&self) -> Option<u32> {
+        self.target_refresh
+    }
+
+    // caps the frame rate to about `fps`, rounded so every frame stays on
+    // screen for the same number of the display's refreshes (see
+    // pacing::paced_interval). None draws as fast as presenting allows.
+    pub fn set_target_refresh(&mut self, fps: Option<u32>) {
+        self.target_refresh = fps;
+        self.frame_pacer
+            .set_interval(fps.map(|fps| pacing::paced_interval(fps, self.window.refresh_rate())));
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn prefer_hdr(&self) -> bool {
+        self.prefer_hdr
+    }
+
+    // draw to a 10-bit surface format if the window has one (see
+    // config::choose_surface_format for what that does and doesn't mean),
+    // otherwise keep using the usual 8-bit one. takes effect immediately.
+    pub fn set_prefer_hdr(&mut self, prefer: bool) -> Result<(), RenderError> {
+        self.prefer_hdr = prefer;
+
+        let available = &self.device_config.capabilities.supported_formats;
+        let format = match config::choose_surface_format(available, prefer) {
+            Ok(format) => format,
+            // can't happen, the surface had formats when the device was picked
+            Err(()) => return Ok(()),
+        };
+        if prefer && !config::HDR_FORMATS.contains(&format.0) {
+            log::warn!("no 10-bit surface format available, staying in SDR");
+        }
+        if format == self.device_config.surface_format {
+            return Ok(());
+        }
+
+        // unlike the size, the format can only change with a new swapchain
+        self.device_config.surface_format = format;
+        let (swapchain, swapchain_images) = self.rebuild_swapchain(self.window.dimensions())?;
+        self.replace_swapchain(swapchain, swapchain_images);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn scale_factor(&self) -> f64 {
+        self.events.dpi_factor()
+    }
+
+    pub fn paused(&self) -> bool {
+        self.simulation.paused()
+    }
+
+    // while paused, frames are still drawn but the particles don't move
+    pub fn set_paused(&mut self, paused: bool) {
+        self.simulation.set_paused(paused);
+    }
+
+    pub fn step(&mut self, steps: u32) {
+        self.simulation.step(steps);
+    }
+
+    pub fn sim_time(&self) -> f64 {
+        self.simulation.time()
+    }
+
+    pub fn sim_steps(&self) -> u64 {
+        self.simulation.steps()
+    }
+
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        self.simulation.set_integrator(integrator);
+    }
+
+    pub fn set_simulation_method(&mut self, method: SimulationMethod) {
+        self.simulation.set_method(method);
+    }
+
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.simulation.set_precision(precision);
+    }
+
+    pub fn gravity(&self) -> f32 {
+        self.simulation.gravity()
+    }
+
+    // see Simulation::set_gravity for sensible values. this and
+    // set_softening() are cheap enough to call every frame.
+    pub fn set_gravity(&mut self, gravity: f32) {
+        self.simulation.set_gravity(gravity);
+    }
+
+    pub fn softening(&self) -> f32 {
+        self.simulation.softening()
+    }
+
+    pub fn set_softening(&mut self, softening: f32) {
+        self.simulation.set_softening(softening);
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn fixed_timestep(&self) -> Option<Duration> {
+        self.simulation.fixed_timestep()
+    }
+
+    // see Simulation::set_fixed_timestep
+    pub fn set_fixed_timestep(&mut self, timestep: Option<Duration>) {
+        self.simulation.set_fixed_timestep(timestep);
+    }
+
+    // draws exactly one frame of the particles as they are now (without
+    // advancing the simulation) and blocks until the GPU has finished it.
+    // this is meant for tests and tools that need deterministic output; the
+    // main loop should use update(), which keeps several frames in flight.
+    #[cfg(feature = "engine-api")]
+    pub fn render_once(&mut self) -> Result<(), RenderError> {
+        self.sync_particles();
+        self.draw_frame()?;
+
+        for slot in 0..self.frames_in_flight.len() {
+            self.wait_for_frame(slot)?;
+        }
+
+        Ok(())
+    }
+
+    // tears everything down in order (see the NOTE on Render), like dropping
+    // it would, but reports it if the GPU was lost while finishing up
+    pub fn destroy(mut self) -> Result<(), RenderError> {
+        for slot in 0..self.frames_in_flight.len() {
+            self.wait_for_frame(slot)?;
+        }
+
+        Ok(())
+    }
+
+    // rebuilds everything on the GPU from scratch (e.g. after a
+    // RenderError::DeviceLost), keeping the simulation and its particles
+    pub fn recover(self) -> Result<Self, RenderError> {
+        let frames_in_flight = self.frames_in_flight.len();
+        let state = self.into_state();
+
+        let mut render = Self::try_with_frames_in_flight(state.window, frames_in_flight)?;
+        render.simulation = state.simulation;
+        render.particles = state.particles;
+        render.initial_particles = state.initial_particles;
+        render.trails = state.trails;
+        render.camera = state.camera;
+        render.target_camera = state.target_camera;
+        render.color_mode = state.color_mode;
+        render.frame_count = state.frame_count;
+        render.particles_dirty = true;
+        // set_shaders() rebuilds the pipeline with this
+        render.pipeline_settings = state.pipeline_settings;
+        render.set_target_refresh(state.target_refresh);
+        render.set_prefer_hdr(state.prefer_hdr)?;
+        render
+            .set_shaders(state.shaders)
+            .map_err(RenderError::Shader)?;
+        // so existing RenderHandles keep working
+        render.commands = state.commands;
+        render.command_sender = state.command_sender;
+
+        Ok(render)
+    }
+
+    // everything else (i.e. the old swapchain) is dropped on return, so a new
+    // one can be made for the same window
+    fn into_state(self) -> RenderState<'a> {
+        RenderState {
+            window: self.window,
+            shaders: self.shaders,
+            pipeline_settings: self.pipeline_settings,
+            simulation: self.simulation,
+            particles: self.particles,
+            initial_particles: self.initial_particles,
+            trails: self.trails,
+            camera: self.camera,
+            target_camera: self.target_camera,
+            color_mode: self.color_mode,
+            frame_count: self.frame_count,
+            target_refresh: self.target_refresh,
+            prefer_hdr: self.prefer_hdr,
+            commands: self.commands,
+            command_sender: self.command_sender,
+        }
+    }
+
+    // if this fails, the swapchain couldn't be recreated (even after retrying)
+    // and nothing more can be drawn
+    pub fn update(&mut self) -> Result<(), RenderError> {
+        self.apply_commands();
+
+        if let Some(dt) = self.simulation.advance() {
+            self.simulation.integrate(&mut self.particles, dt);
+            self.trails.record(&self.particles);
+            self.particles_dirty = true;
+        }
+
+        self.ease_camera();
+        self.sync_particles();
+
+        let scale_change = self.events.take_scale_change();
+
+        // a DPI change usually comes with a resize, but not always (e.g. if
+        // the window's logical size didn't change). either way, only
+        // recreate the swapchain once.
         if let Some(new_size) = self.events.resize_to() {
-            self.resize_to(new_size);
+            self.resize_to(new_size)?;
+        } else if scale_change.is_some() {
+            self.recreate_swapchain()?;
         }
-        self.draw_frame();
+
+        self.frame_pacer.wait();
+        self.draw_frame()
     }
 }
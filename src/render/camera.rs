@@ -0,0 +1,152 @@
+use std::time::Instant;
+
+use super::Render;
+
+// how long the camera on screen takes to get ~63% of the way to where it was
+// asked to be (it never quite arrives, but see Camera::eased_toward). short
+// enough to feel responsive, long enough to hide the odd uneven frame.
+const CAMERA_SMOOTHING: f32 = 0.08;
+
+// which part of the simulation is on screen: `center` is drawn in the middle
+// of the window, and everything is drawn `zoom` times bigger than it is.
+// there's no uniform buffer for this; it's applied as the particles are
+// uploaded (which happens every frame anyway while the simulation runs), so
+// custom particle shaders get it for free.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera {
+    pub center: [f32; 2],
+    pub zoom: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            center: [0.0, 0.0],
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Camera {
+    // moves by `delta` as measured on screen, so panning feels the same at
+    // every zoom level
+    pub fn panned_by(self, delta: [f32; 2]) -> Self {
+        Self {
+            center: [
+                self.center[0] + delta[0] / self.zoom,
+                self.center[1] + delta[1] / self.zoom,
+            ],
+            ..self
+        }
+    }
+
+    pub fn zoomed_by(self, factor: f32) -> Self {
+        Self {
+            zoom: self.zoom * factor,
+            ..self
+        }
+    }
+
+    // `t` of the way from here to `target`: 0 stays put, 1 jumps there. zoom
+    // is eased geometrically, so zooming in and out feel the same. snaps to
+    // `target` once the difference would be too small to see.
+    pub fn eased_toward(self, target: Self, t: f32) -> Self {
+        let eased = Self {
+            center: [
+                self.center[0] + (target.center[0] - self.center[0]) * t,
+                self.center[1] + (target.center[1] - self.center[1]) * t,
+            ],
+            zoom: self.zoom * (target.zoom / self.zoom).powf(t),
+        };
+
+        // (a ten-thousandth of the window, or of the zoom level)
+        let moved = [
+            (target.center[0] - eased.center[0]) * eased.zoom,
+            (target.center[1] - eased.center[1]) * eased.zoom,
+        ];
+        if moved[0].abs() < 1e-4
+            && moved[1].abs() < 1e-4
+            && (target.zoom / eased.zoom - 1.0).abs() < 1e-4
+        {
+            target
+        } else {
+            eased
+        }
+    }
+
+    // where a point in the simulation ends up on screen
+    pub fn apply(&self, position: [f32; 2]) -> [f32; 2] {
+        [
+            (position[0] - self.center[0]) * self.zoom,
+            (position[1] - self.center[1]) * self.zoom,
+        ]
+    }
+}
+
+impl<'a> Render<'a> {
+    // where the camera is headed, which is where it was last set to (what's
+    // on screen may still be catching up)
+    pub fn camera(&self) -> Camera {
+        self.target_camera
+    }
+
+    // cheap enough to call every frame: the camera on screen eases toward
+    // the new one over the next few update()s, so jerky input (e.g. a key
+    // polled at an uneven frame rate) still moves it smoothly
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.target_camera = camera;
+    }
+
+    // like set_camera(), but without easing, e.g. to cut to a new scene
+    #[cfg(feature = "engine-api")]
+    pub fn snap_camera(&mut self, camera: Camera) {
+        self.target_camera = camera;
+        if camera != self.camera {
+            self.camera = camera;
+            self.particles_dirty = true;
+        }
+    }
+
+    pub(super) fn ease_camera(&mut self) {
+        let now = Instant::now();
+        let dt = now.duration_since(self.camera_eased).as_secs_f32();
+        self.camera_eased = now;
+
+        // nothing to re-upload once the camera has caught up
+        if self.camera != self.target_camera {
+            let t = 1.0 - (-dt / CAMERA_SMOOTHING).exp();
+            self.camera = self.camera.eased_toward(self.target_camera, t);
+            self.particles_dirty = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn easing_ends_up_at_the_target() {
+        let target = Camera::default().panned_by([1.0, -0.5]).zoomed_by(4.0);
+
+        assert_eq!(
+            Camera::default().eased_toward(target, 0.0),
+            Camera::default()
+        );
+        assert_eq!(Camera::default().eased_toward(target, 1.0), target);
+
+        let mut camera = Camera::default();
+        for _ in 0..100 {
+            camera = camera.eased_toward(target, 0.25);
+        }
+        assert_eq!(camera, target);
+    }
+
+    #[test]
+    fn easing_zooms_geometrically() {
+        let target = Camera::default().zoomed_by(4.0);
+        let halfway = Camera::default().eased_toward(target, 0.5);
+
+        assert!((halfway.zoom - 2.0).abs() < 1e-6);
+    }
+}
@@ -1,7 +1,7 @@
 use vulkano::{
     device::DeviceExtensions,
     format::Format,
-    instance::{Instance, PhysicalDevice},
+    instance::{Instance, PhysicalDevice, PhysicalDeviceType},
     swapchain::{
         Capabilities, ColorSpace, CompositeAlpha, PresentMode, SupportedCompositeAlpha,
         SupportedPresentModes, Surface,
@@ -11,21 +11,59 @@ use winit::window::Window;
 
 use std::sync::Arc;
 
-use super::queues::{self, QueueFamilies};
+use super::queues::{self, QueueFamilies, QueueReport};
 use crate::util::prefer;
 
 pub struct DeviceConfig {
+    pub device_type: PhysicalDeviceType,
     pub queue_families: QueueFamilies,
     pub capabilities: Capabilities,
     pub surface_format: (Format, ColorSpace),
     pub present_mode: PresentMode,
+    pub composite_alpha: CompositeAlpha,
+    // None if the GPU can only draw lines 1.0 wide
+    pub line_width_range: Option<[f32; 2]>,
+}
+
+// best first. Mailbox doesn't tear and doesn't wait for vsync; Immediate
+// doesn't wait but tears; Fifo (vsync) is the only one every device has
+const PREFERRED_PRESENT_MODES: &[PresentMode] = &[
+    PresentMode::Mailbox,
+    PresentMode::Immediate,
+    PresentMode::Fifo,
+];
+
+impl DeviceConfig {
+    pub fn queue_report(&self) -> QueueReport {
+        QueueReport {
+            families: self.queue_families.clone(),
+        }
+    }
+
+    // false if the best present mode (Mailbox) wasn't available
+    pub fn got_preferred_present_mode(&self) -> bool {
+        self.present_mode == PREFERRED_PRESENT_MODES[0]
+    }
+
+    pub fn present_mode_report(&self) -> String {
+        if self.got_preferred_present_mode() {
+            format!("present mode: {:?}", self.present_mode)
+        } else {
+            format!(
+                "present mode: {:?} ({:?} isn't supported)",
+                self.present_mode, PREFERRED_PRESENT_MODES[0]
+            )
+        }
+    }
 }
 
 pub fn choose_alpha_mode(supported: SupportedCompositeAlpha) -> CompositeAlpha {
     // prefer premultiplied over opaque over inherit alpha modes
     // postmultiplied mode won't work well because we're cheating
     // by making the clear color the only transparency in the game
-    // and drawing everything else as if there were none (alpha 255)
+    // and drawing everything else as if there were none (alpha 255).
+    // translucent particles are blended into the framebuffer, so they
+    // don't change this (see create_graphics_pipeline)
     const WANTED: &[CompositeAlpha] = &[
         CompositeAlpha::PreMultiplied,
         CompositeAlpha::Opaque,
@@ -35,18 +73,31 @@ pub fn choose_alpha_mode(supported: SupportedCompositeAlpha) -> CompositeAlpha {
     prefer(WANTED, supported.iter(), true).unwrap()
 }
 
+pub fn clear_color_for_alpha_mode(color: [f32; 4], alpha_mode: CompositeAlpha) -> [f32; 4] {
+    let [r, g, b, a] = color;
+
+    match alpha_mode {
+        // the compositor expects the color channels to already be scaled by
+        // alpha, otherwise translucent areas come out too bright (halos)
+        CompositeAlpha::PreMultiplied => [r * a, g * a, b * a, a],
+        // alpha is ignored entirely, so don't pretend otherwise
+        CompositeAlpha::Opaque => [r, g, b, 1.0],
+        // postmultiplied does the scaling itself, and for inherit we have no
+        // way of knowing what the platform will do with it anyway
+        _ => color,
+    }
+}
+
 pub fn pick_physical_device<'a>(
     instance: &'a Arc<Instance>,
     surface: &Surface<Window>,
-) -> (PhysicalDevice<'a>, DeviceConfig) {
+) -> Option<(PhysicalDevice<'a>, DeviceConfig)> {
     let mut device_config = Err(());
-    let device = PhysicalDevice::enumerate(&instance)
-        .find(|device| {
-            device_config = create_device_config(surface, &device);
-            device_config.is_ok()
-        })
-        .expect("No Vulkan-capable devices (GPUs) found");
-    (device, device_config.unwrap())
+    let device = PhysicalDevice::enumerate(&instance).find(|device| {
+        device_config = create_device_config(surface, &device);
+        device_config.is_ok()
+    })?;
+    Some((device, device_config.unwrap()))
 }
 
 pub fn create_device_config(
@@ -63,14 +114,24 @@ pub fn create_device_config(
     // instead of just moving onto the next GPU/physical device
     //let capabilities = surface.capabilities(*device).expect("Failed to enumerate surface capabilities");
     let capabilities = surface.capabilities(*device).ok().ok_or(())?;
-    let surface_format = choose_surface_format(&capabilities.supported_formats)?;
+    let surface_format = choose_surface_format(&capabilities.supported_formats, false)?;
     let present_mode = choose_present_mode(capabilities.present_modes)?;
+    let composite_alpha = choose_alpha_mode(capabilities.supported_composite_alpha);
+    // every supported feature is enabled (see create_logical_device)
+    let line_width_range = if device.supported_features().wide_lines {
+        Some(device.limits().line_width_range())
+    } else {
+        None
+    };
 
     Ok(DeviceConfig {
+        device_type: device.ty(),
         queue_families,
         capabilities,
         surface_format,
         present_mode,
+        composite_alpha,
+        line_width_range,
     })
 }
 
@@ -88,27 +149,68 @@ fn check_device_extension_support(device: &PhysicalDevice) -> bool {
     required_device_extensions(Some(available)) == available
 }
 
-fn choose_surface_format(
+// 10-bit formats, for less banding in the faint parts of the trails and glow
+pub const HDR_FORMATS: &[Format] = &[
+    Format::A2B10G10R10UnormPack32,
+    Format::A2R10G10B10UnormPack32,
+];
+
+// NOTE: vulkano always creates swapchains in SrgbNonLinear (it has no way to
+// pass the color space through), so "HDR" here only means a 10-bit format in
+// the usual color space. formats the surface only offers in an HDR color
+// space (e.g. HDR10 ST2084) are skipped, since they'd be presented wrong.
+// falls back to the SDR choice if there's no such format.
+pub fn choose_surface_format(
     available_formats: &[(Format, ColorSpace)],
+    prefer_hdr: bool,
 ) -> Result<(Format, ColorSpace), ()> {
+    let hdr_format = || {
+        HDR_FORMATS.iter().find_map(|wanted| {
+            available_formats.iter().find(|(format, color_space)| {
+                format == wanted && *color_space == ColorSpace::SrgbNonLinear
+            })
+        })
+    };
+
     // TODO: why prefer Unorm and not Srgb?
     // is it more widely supported?
-    available_formats
-        .iter()
-        .find(|(format, color_space)| {
+    let sdr_format = || {
+        available_formats.iter().find(|(format, color_space)| {
             *format == Format::B8G8R8A8Unorm && *color_space == ColorSpace::SrgbNonLinear
         })
+    };
+
+    let preferred = if prefer_hdr { hdr_format() } else { None };
+    preferred
+        .or_else(sdr_format)
         .or_else(|| available_formats.first())
         .copied()
         .ok_or(())
 }
 
 fn choose_present_mode(available: SupportedPresentModes) -> Result<PresentMode, ()> {
-    const WANTED: &[PresentMode] = &[
-        PresentMode::Mailbox,
-        PresentMode::Immediate,
-        PresentMode::Fifo,
-    ];
+    prefer(PREFERRED_PRESENT_MODES, available.iter(), true).ok_or(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    prefer(WANTED, available.iter(), true).ok_or(())
+    #[test]
+    fn clear_colors_are_premultiplied_only_when_asked() {
+        let color = [1.0, 0.5, 0.25, 0.5];
+
+        assert_eq!(
+            clear_color_for_alpha_mode(color, CompositeAlpha::PreMultiplied),
+            [0.5, 0.25, 0.125, 0.5]
+        );
+        assert_eq!(
+            clear_color_for_alpha_mode(color, CompositeAlpha::Opaque),
+            [1.0, 0.5, 0.25, 1.0]
+        );
+        assert_eq!(
+            clear_color_for_alpha_mode(color, CompositeAlpha::PostMultiplied),
+            color
+        );
+    }
 }
@@ -0,0 +1,131 @@
+use vulkano::{
+    buffer::{BufferAccess, BufferSlice, CpuAccessibleBuffer},
+    command_buffer::AutoCommandBuffer,
+    device::Device,
+};
+
+use std::sync::Arc;
+
+use super::{particles::ParticleBuffer, setup, trails::TrailBuffer, Render};
+use crate::shaders::{particle_vert, trail_vert};
+
+// the vertices every frame should be drawing, as of the last sync_particles()
+#[derive(Default)]
+pub(super) struct Vertices {
+    particles: Vec<particle_vert::Vertex>,
+    trails: Vec<trail_vert::Vertex>,
+    // bumped every time they change, so each FrameSlot can tell whether its
+    // copy is out of date
+    version: u64,
+}
+
+impl Vertices {
+    pub fn set(&mut self, particles: Vec<particle_vert::Vertex>, trails: Vec<trail_vert::Vertex>) {
+        self.particles = particles;
+        self.trails = trails;
+        self.version += 1;
+    }
+}
+
+// what one frame in flight draws from. every slot has its own copy of the
+// vertices, so the next frame's can be written while the GPU is still
+// drawing the others, and its own command buffers (one per swapchain image)
+// drawing them. those only have to be re-recorded when a buffer is replaced
+// or the number of vertices changes, not every time the particles move.
+pub(super) struct FrameSlot {
+    pub command_buffers: Vec<Arc<AutoCommandBuffer>>,
+    vertex_buffer: ParticleBuffer,
+    trail_buffer: Option<TrailBuffer>,
+    // how much of each buffer the command buffers draw
+    particle_count: usize,
+    trail_count: usize,
+    version: u64,
+}
+
+impl FrameSlot {
+    pub fn new(device: Arc<Device>) -> Self {
+        Self {
+            command_buffers: Vec::new(),
+            vertex_buffer: setup::create_vertex_buffer(device, Vec::new(), 0),
+            trail_buffer: None,
+            particle_count: 0,
+            trail_count: 0,
+            version: 0,
+        }
+    }
+
+    // copies the vertices into this slot's buffers, if they've changed since.
+    // the frame that last used the slot has to be finished first. returns
+    // true if the command buffers have to be re-recorded.
+    pub fn upload(&mut self, device: Arc<Device>, vertices: &Vertices) -> bool {
+        if self.version == vertices.version {
+            return false;
+        }
+        self.version = vertices.version;
+
+        let mut rerecord = setup::update_vertex_buffer(
+            device.clone(),
+            &mut self.vertex_buffer,
+            &vertices.particles,
+        );
+
+        // (this is also how turning trails off frees their buffers)
+        if vertices.trails.is_empty() {
+            rerecord |= self.trail_buffer.take().is_some();
+        } else if let Some(buffer) = &mut self.trail_buffer {
+            rerecord |= setup::update_vertex_buffer(device, buffer, &vertices.trails);
+        } else {
+            let capacity = vertices.trails.len();
+            self.trail_buffer = Some(setup::create_vertex_buffer(
+                device,
+                vertices.trails.clone(),
+                capacity,
+            ));
+            rerecord = true;
+        }
+
+        rerecord |= self.particle_count != vertices.particles.len()
+            || self.trail_count != vertices.trails.len();
+        self.particle_count = vertices.particles.len();
+        self.trail_count = vertices.trails.len();
+
+        rerecord
+    }
+
+    pub fn particle_slice(&self) -> Option<Arc<dyn BufferAccess + Send + Sync>> {
+        in_use(&self.vertex_buffer, self.particle_count)
+    }
+
+    pub fn trail_slice(&self) -> Option<Arc<dyn BufferAccess + Send + Sync>> {
+        self.trail_buffer
+            .as_ref()
+            .and_then(|buffer| in_use(buffer, self.trail_count))
+    }
+}
+
+// only the part of the buffer in use, not the whole (over-allocated) thing.
+// None if that's nothing, since drawing zero vertices isn't allowed.
+fn in_use<V>(
+    buffer: &Arc<CpuAccessibleBuffer<[V]>>,
+    count: usize,
+) -> Option<Arc<dyn BufferAccess + Send + Sync>>
+where
+    V: Send + Sync + 'static,
+{
+    if count == 0 {
+        None
+    } else {
+        BufferSlice::from_typed_buffer_access(buffer.clone())
+            .slice(0..count)
+            .map(|slice| Arc::new(slice) as Arc<dyn BufferAccess + Send + Sync>)
+    }
+}
+
+impl<'a> Render<'a> {
+    // gets a slot ready to draw the next frame from (see FrameSlot)
+    pub(super) fn prepare_slot(&mut self, slot: usize) {
+        if self.frame_slots[slot].upload(self.device.clone(), &self.vertices) {
+            self.record_command_buffers(slot);
+        }
+    }
+}
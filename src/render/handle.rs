@@ -0,0 +1,159 @@
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    time::Duration,
+};
+
+use super::{Camera, ColorMode, Particle, ParticleDistribution, PointShape, Render};
+use crate::simulation::{Integrator, Precision, SimulationMethod};
+
+// like the audio Mixer, Render (and everything on the GPU it owns) stays on
+// one thread. other threads control it through a RenderHandle, whose commands
+// are applied at the top of the next update().
+pub(super) enum Command {
+    SetPaused(bool),
+    Step(u32),
+    SetIntegrator(Integrator),
+    SetSimulationMethod(SimulationMethod),
+    SetPrecision(Precision),
+    SetGravity(f32),
+    SetSoftening(f32),
+    SetFixedTimestep(Option<Duration>),
+    SetColorMode(ColorMode),
+    SetTargetRefresh(Option<u32>),
+    SetPointShape(PointShape),
+    SetParticleAlpha(f32),
+    SetLineWidth(f32),
+    SetTrailLength(usize),
+    SetCamera(Camera),
+    SpawnParticle(Particle),
+    ClearParticles,
+    ResetSimulation,
+    SeedParticles(usize, u64, ParticleDistribution),
+}
+
+pub(super) fn channel() -> (Sender<Command>, Receiver<Command>) {
+    mpsc::channel()
+}
+
+#[derive(Clone)]
+pub struct RenderHandle(Sender<Command>);
+
+impl RenderHandle {
+    // sending only fails if the Render is gone, in which case there's nothing
+    // left to control anyway
+    fn send(&self, command: Command) {
+        let _ = self.0.send(command);
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.send(Command::SetPaused(paused));
+    }
+
+    pub fn step(&self, steps: u32) {
+        self.send(Command::Step(steps));
+    }
+
+    pub fn set_integrator(&self, integrator: Integrator) {
+        self.send(Command::SetIntegrator(integrator));
+    }
+
+    pub fn set_simulation_method(&self, method: SimulationMethod) {
+        self.send(Command::SetSimulationMethod(method));
+    }
+
+    pub fn set_precision(&self, precision: Precision) {
+        self.send(Command::SetPrecision(precision));
+    }
+
+    pub fn set_gravity(&self, gravity: f32) {
+        self.send(Command::SetGravity(gravity));
+    }
+
+    pub fn set_softening(&self, softening: f32) {
+        self.send(Command::SetSoftening(softening));
+    }
+
+    pub fn set_fixed_timestep(&self, timestep: Option<Duration>) {
+        self.send(Command::SetFixedTimestep(timestep));
+    }
+
+    pub fn set_color_mode(&self, color_mode: ColorMode) {
+        self.send(Command::SetColorMode(color_mode));
+    }
+
+    pub fn set_target_refresh(&self, fps: Option<u32>) {
+        self.send(Command::SetTargetRefresh(fps));
+    }
+
+    pub fn set_point_shape(&self, point_shape: PointShape) {
+        self.send(Command::SetPointShape(point_shape));
+    }
+
+    pub fn set_particle_alpha(&self, alpha: f32) {
+        self.send(Command::SetParticleAlpha(alpha));
+    }
+
+    pub fn set_line_width(&self, width: f32) {
+        self.send(Command::SetLineWidth(width));
+    }
+
+    pub fn set_trail_length(&self, length: usize) {
+        self.send(Command::SetTrailLength(length));
+    }
+
+    pub fn set_camera(&self, camera: Camera) {
+        self.send(Command::SetCamera(camera));
+    }
+
+    pub fn spawn_particle(&self, particle: Particle) {
+        self.send(Command::SpawnParticle(particle));
+    }
+
+    pub fn clear_particles(&self) {
+        self.send(Command::ClearParticles);
+    }
+
+    pub fn reset_simulation(&self) {
+        self.send(Command::ResetSimulation);
+    }
+
+    pub fn seed_particles_with(&self, count: usize, seed: u64, distribution: ParticleDistribution) {
+        self.send(Command::SeedParticles(count, seed, distribution));
+    }
+}
+
+impl<'a> Render<'a> {
+    pub fn handle(&self) -> RenderHandle {
+        RenderHandle(self.command_sender.clone())
+    }
+
+    pub(super) fn apply_commands(&mut self) {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                Command::SetPaused(paused) => self.set_paused(paused),
+                Command::Step(steps) => self.step(steps),
+                Command::SetIntegrator(integrator) => self.set_integrator(integrator),
+                Command::SetSimulationMethod(method) => self.set_simulation_method(method),
+                Command::SetPrecision(precision) => self.set_precision(precision),
+                Command::SetGravity(gravity) => self.set_gravity(gravity),
+                Command::SetSoftening(softening) => self.set_softening(softening),
+                Command::SetFixedTimestep(timestep) => self.set_fixed_timestep(timestep),
+                Command::SetColorMode(color_mode) => self.set_color_mode(color_mode),
+                Command::SetTargetRefresh(fps) => self.set_target_refresh(fps),
+                Command::SetPointShape(point_shape) => self.set_point_shape(point_shape),
+                Command::SetParticleAlpha(alpha) => self.set_particle_alpha(alpha),
+                Command::SetLineWidth(width) => self.set_line_width(width),
+                Command::SetTrailLength(length) => self.set_trail_length(length),
+                Command::SetCamera(camera) => self.set_camera(camera),
+                Command::SpawnParticle(particle) => {
+                    self.spawn_particle(particle);
+                }
+                Command::ClearParticles => self.clear_particles(),
+                Command::ResetSimulation => self.reset_simulation(),
+                Command::SeedParticles(count, seed, distribution) => {
+                    self.seed_particles_with(count, seed, distribution)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// the frame interval closest to `target` fps that's a whole number of
+// refreshes of a `display` Hz monitor. e.g. on a 144Hz display, a 60fps
+// target becomes 72fps (every other refresh): each frame is then on screen
+// for the same number of refreshes, which is what avoids judder.
+pub(super) fn paced_interval(target: u32, display: Option<u32>) -> Duration {
+    let target = target.max(1);
+
+    match display {
+        Some(display) => {
+            let refreshes = ((f64::from(display) / f64::from(target)).round() as u32).max(1);
+            Duration::from_secs(1) * refreshes / display
+        }
+        None => Duration::from_secs(1) / target,
+    }
+}
+
+// sleeps between frames so they come at most once per interval. the deadline
+// moves forward by exactly one interval per frame (unless a frame runs late),
+// so oversleeping on one frame is made up for on the next.
+pub(super) struct FramePacer {
+    interval: Option<Duration>,
+    next_frame: Option<Instant>,
+}
+
+impl FramePacer {
+    pub fn new() -> Self {
+        Self {
+            interval: None,
+            next_frame: None,
+        }
+    }
+
+    pub fn set_interval(&mut self, interval: Option<Duration>) {
+        self.interval = interval;
+        self.next_frame = None;
+    }
+
+    pub fn wait(&mut self) {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        let now = Instant::now();
+        let next_frame = match self.next_frame {
+            Some(next_frame) if next_frame > now => {
+                thread::sleep(next_frame - now);
+                next_frame
+            }
+            // running behind; don't try to catch up with a burst of frames
+            _ => now,
+        };
+
+        self.next_frame = Some(next_frame + interval);
+    }
+}
@@ -0,0 +1,238 @@
+use vulkano::buffer::CpuAccessibleBuffer;
+
+use std::{f32::consts::PI, sync::Arc};
+
+use super::Render;
+use crate::{shaders::particle_vert::Vertex, util::Pcg32};
+
+pub(super) type ParticleBuffer = Arc<CpuAccessibleBuffer<[Vertex]>>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Particle {
+    pub position: [f32; 2],
+    pub velocity: [f32; 2],
+    pub mass: f32,
+}
+
+impl Default for Particle {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0],
+            velocity: [0.0, 0.0],
+            mass: 1.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    White,
+    // heat map relative to the fastest particle: fast ones glow red
+    Speed,
+    // heat map relative to the heaviest particle
+    Mass,
+}
+
+impl Default for ColorMode {
+    fn default() -> Self {
+        ColorMode::White
+    }
+}
+
+// what each particle's point looks like. these are passed to particle.frag as
+// a specialization constant, so changing them means rebuilding the pipeline.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PointShape {
+    Square = 0,
+    // anti-aliasing aside, a circle with hard edges
+    Circle = 1,
+    // a circle that fades out towards its edge
+    Soft = 2,
+}
+
+impl Default for PointShape {
+    fn default() -> Self {
+        PointShape::Square
+    }
+}
+
+fn speed(particle: &Particle) -> f32 {
+    particle.velocity[0].hypot(particle.velocity[1])
+}
+
+pub(super) fn to_vertices(particles: &[Particle], color_mode: ColorMode) -> Vec<Vertex> {
+    let heat: fn(&Particle) -> f32 = match color_mode {
+        ColorMode::White => |_| 0.0,
+        ColorMode::Speed => speed,
+        ColorMode::Mass => |p| p.mass,
+    };
+
+    let max_heat = particles.iter().map(heat).fold(0.0, f32::max);
+
+    particles
+        .iter()
+        .map(|particle| Vertex {
+            position: particle.position,
+            velocity: particle.velocity,
+            hue: if color_mode == ColorMode::White {
+                // see particle.frag
+                -1.0
+            } else if max_heat > 0.0 {
+                // red (0.0) is hottest, blue (2/3) is coldest
+                (1.0 - heat(particle) / max_heat) * 2.0 / 3.0
+            } else {
+                2.0 / 3.0
+            },
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParticleDistribution {
+    // spread evenly over a disc, slowly rotating around its center
+    UniformDisc { radius: f32 },
+    // normally distributed around the center, initially at rest
+    GaussianCluster { sigma: f32 },
+    // two gaussian clusters orbiting each other
+    TwoBodyOrbit { separation: f32, sigma: f32 },
+}
+
+impl Default for ParticleDistribution {
+    fn default() -> Self {
+        ParticleDistribution::UniformDisc { radius: 0.8 }
+    }
+}
+
+impl ParticleDistribution {
+    fn sample(self, rng: &mut Pcg32, index: usize) -> Particle {
+        match self {
+            ParticleDistribution::UniformDisc { radius } => {
+                // sqrt() so particles aren't bunched up in the middle
+                let r = radius * rng.next_f32().sqrt();
+                let theta = 2.0 * PI * rng.next_f32();
+                let (sin, cos) = theta.sin_cos();
+
+                Particle {
+                    position: [r * cos, r * sin],
+                    velocity: [-r * sin * 0.1, r * cos * 0.1],
+                    ..Default::default()
+                }
+            }
+            ParticleDistribution::GaussianCluster { sigma } => Particle {
+                position: [sigma * rng.next_gaussian(), sigma * rng.next_gaussian()],
+                ..Default::default()
+            },
+            ParticleDistribution::TwoBodyOrbit { separation, sigma } => {
+                // alternate between the bodies so both get half the particles
+                let side = if index % 2 == 0 { -1.0 } else { 1.0 };
+                let center = side * separation / 2.0;
+
+                Particle {
+                    position: [
+                        center + sigma * rng.next_gaussian(),
+                        sigma * rng.next_gaussian(),
+                    ],
+                    velocity: [0.0, side * 0.1],
+                    ..Default::default()
+                }
+            }
+        }
+    }
+}
+
+// NOTE: changes to the particle list only touch the CPU-side vector; they're
+// turned into vertices at the start of the next update(), and each frame in
+// flight copies those into its own buffers once the GPU is done with them
+// (see FrameSlot), so no buffer is written while a frame is drawing it.
+impl<'a> Render<'a> {
+    #[cfg(feature = "engine-api")]
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    // returns the index of the new particle
+    pub fn spawn_particle(&mut self, particle: Particle) -> usize {
+        self.particles.push(particle);
+        self.particles_dirty = true;
+        self.particles.len() - 1
+    }
+
+    // the last particle takes the place of the removed one, so this (like
+    // Vec::swap_remove) invalidates the index of the last particle
+    #[cfg(feature = "engine-api")]
+    pub fn despawn_particle(&mut self, index: usize) -> Particle {
+        self.particles_dirty = true;
+        self.particles.swap_remove(index)
+    }
+
+    pub fn set_color_mode(&mut self, color_mode: ColorMode) {
+        self.color_mode = color_mode;
+        self.particles_dirty = true;
+    }
+
+    pub fn clear_particles(&mut self) {
+        self.particles.clear();
+        self.initial_particles.clear();
+        self.trails.clear();
+        self.simulation.reset_interpolation();
+        self.particles_dirty = true;
+    }
+
+    // replaces all particles with a reproducible pseudo-random field; the
+    // same count, seed, and distribution always produce the same particles
+    #[cfg(feature = "engine-api")]
+    pub fn seed_particles(&mut self, count: usize, seed: u64) {
+        self.seed_particles_with(count, seed, Default::default());
+    }
+
+    pub fn seed_particles_with(
+        &mut self,
+        count: usize,
+        seed: u64,
+        distribution: ParticleDistribution,
+    ) {
+        let mut rng = Pcg32::new(seed);
+
+        self.particles.clear();
+        self.particles
+            .extend((0..count).map(|i| distribution.sample(&mut rng, i)));
+        self.initial_particles.clone_from(&self.particles);
+        self.trails.clear();
+        self.simulation.reset_interpolation();
+        self.particles_dirty = true;
+    }
+
+    // puts the particles back the way they were last seeded (or cleared, or
+    // the way Render::new() made them), undoing any that were spawned or
+    // despawned since, and starts the simulation clock over. that takes
+    // keeping a second copy of the particles around the whole time, which is
+    // a few dozen bytes per particle.
+    pub fn reset_simulation(&mut self) {
+        self.particles.clone_from(&self.initial_particles);
+        self.trails.clear();
+        self.simulation.restart();
+        self.particles_dirty = true;
+    }
+
+    pub(super) fn sync_particles(&mut self) {
+        if !self.particles_dirty {
+            return;
+        }
+
+        let mut vertices = to_vertices(&self.particles, self.color_mode);
+        if let Some(positions) = self.simulation.interpolated_positions(&self.particles) {
+            for (vertex, position) in vertices.iter_mut().zip(positions) {
+                vertex.position = position;
+            }
+        }
+        for vertex in &mut vertices {
+            vertex.position = self.camera.apply(vertex.position);
+        }
+        let trails = self.trails.to_vertices(&self.camera);
+
+        // each frame in flight picks them up when it's next drawn (see
+        // FrameSlot), so nothing has to be re-recorded here
+        self.vertices.set(vertices, trails);
+        self.particles_dirty = false;
+    }
+}
@@ -8,6 +8,7 @@ use winit::window::Window;
 
 use std::{
     convert::TryInto,
+    fmt,
     iter::{repeat, DoubleEndedIterator, ExactSizeIterator, FromIterator},
     sync::Arc,
     vec::IntoIter,
@@ -18,6 +19,7 @@ use crate::util::prefer_fn;
 // TODO: lib w/ proc_macro #derive(Iter) on structs with fields of uniform type
 
 const QUEUE_LIST_SIZE: u8 = 4;
+#[derive(Clone, Debug)]
 pub struct QueueList<T> {
     pub graphics: T,
     pub compute: T,
@@ -30,6 +32,42 @@ impl<'a, T> QueueList<T> {
     pub fn iter(&'a self) -> QueueListIterator<'a, T> {
         QueueListIterator::new(&self)
     }
+
+    pub fn get(&self, role: QueueRole) -> &T {
+        match role {
+            QueueRole::Graphics => &self.graphics,
+            QueueRole::Compute => &self.compute,
+            QueueRole::Transfer => &self.transfer,
+            QueueRole::Present => &self.present,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum QueueRole {
+    Graphics,
+    Compute,
+    Transfer,
+    Present,
+}
+
+impl QueueRole {
+    // in the same order as the fields of QueueList
+    pub const ALL: [QueueRole; QUEUE_LIST_SIZE as usize] = [
+        QueueRole::Graphics,
+        QueueRole::Compute,
+        QueueRole::Transfer,
+        QueueRole::Present,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            QueueRole::Graphics => "graphics",
+            QueueRole::Compute => "compute",
+            QueueRole::Transfer => "transfer",
+            QueueRole::Present => "present",
+        }
+    }
 }
 
 impl<T> FromIterator<T> for QueueList<T> {
@@ -221,6 +259,58 @@ pub fn find_queue_families(
     })
 }
 
+// a summary of which queue family find_queue_families() chose for each role
+pub struct QueueReport {
+    pub families: QueueFamilies,
+}
+
+impl QueueReport {
+    pub fn family(&self, role: QueueRole) -> u32 {
+        *self.families.get(role)
+    }
+
+    // the other roles sharing a queue family with this one
+    pub fn shared_with(&self, role: QueueRole) -> Vec<QueueRole> {
+        QueueRole::ALL
+            .iter()
+            .copied()
+            .filter(|&other| other != role && self.family(other) == self.family(role))
+            .collect()
+    }
+
+    pub fn any_shared(&self) -> bool {
+        QueueRole::ALL
+            .iter()
+            .any(|&role| !self.shared_with(role).is_empty())
+    }
+}
+
+impl fmt::Display for QueueReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "queue families:")?;
+        for &role in QueueRole::ALL.iter() {
+            write!(f, " {}={}", role.name(), self.family(role))?;
+        }
+
+        if self.any_shared() {
+            write!(f, " (shared:")?;
+            for (i, &role) in QueueRole::ALL.iter().enumerate() {
+                let shared = self.shared_with(role);
+                // only print each group once, starting from its first role
+                if !shared.is_empty() && QueueRole::ALL[..i].iter().all(|r| !shared.contains(r)) {
+                    write!(f, " {}", role.name())?;
+                    for other in shared {
+                        write!(f, "+{}", other.name())?;
+                    }
+                }
+            }
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
 // the sharing mode this function creates allows all queues to share
 pub fn get_sharing_mode(queue_families: &QueueFamilies, queues: &Queues) -> SharingMode {
     use std::collections::HashMap;
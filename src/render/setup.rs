@@ -1,40 +1,155 @@
+use log::Level;
 use vulkano::{
-    buffer::{cpu_access::CpuAccessibleBuffer, BufferAccess, BufferUsage},
+    buffer::{cpu_access::CpuAccessibleBuffer, BufferUsage},
     device::{Device, DeviceExtensions},
     format::Format,
     framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
     image::{swapchain::SwapchainImage, ImageUsage},
     instance::{
-        debug::{DebugCallback, MessageTypes},
+        debug::{DebugCallback, Message, MessageTypes},
         layers_list, Instance, QueueFamily,
     },
-    pipeline::{viewport::Viewport, GraphicsPipeline, GraphicsPipelineAbstract},
+    pipeline::{
+        blend::{AttachmentBlend, BlendFactor},
+        viewport::Viewport,
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
     single_pass_renderpass,
-    swapchain::{Surface, SurfaceTransform, Swapchain},
-    sync::{self, GpuFuture},
+    swapchain::{Surface, SurfaceTransform, Swapchain, SwapchainCreationError},
 };
 use winit::{dpi::PhysicalSize, window::Window};
 
-use std::{iter::FromIterator, sync::Arc, u32};
+use std::{
+    cmp, env,
+    iter::FromIterator,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    u32,
+};
 
 use super::{
     config::{self, DeviceConfig},
+    particles::{ParticleBuffer, PointShape},
     queues::{self, QueuePriorities, Queues},
+    spirv::ShaderModules,
+    RenderError,
 };
 use crate::{
-    get_app_info,
+    engine::EngineConfig,
+    shaders::{particle_frag, particle_vert::Vertex, trail_frag, trail_vert},
     util::{clamp_window_size, ToExtents},
 };
 
 const ENABLE_VALIDATION_LAYERS: bool = cfg!(debug_assertions);
+// set to 1 (or 0) to override ENABLE_VALIDATION_LAYERS, e.g. to capture a
+// driver bug in a release build. set to "strict" to panic if the layers
+// aren't installed instead of quietly going without (e.g. for CI).
+const VALIDATION_ENV_VAR: &str = "PLANETS_VALIDATION";
 const VALIDATION_LAYERS: &[&str] = &["VK_LAYER_KHRONOS_validation"];
 
-pub fn create_instance() -> (Arc<Instance>, Option<DebugCallback>) {
-    let layers = if ENABLE_VALIDATION_LAYERS {
+pub type SwapchainParts = (Arc<Swapchain<Window>>, Vec<Arc<SwapchainImage<Window>>>);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValidationSeverity {
+    Error,
+    Warning,
+    PerformanceWarning,
+    Information,
+    Debug,
+}
+
+#[derive(Clone, Debug)]
+pub struct ValidationMessage {
+    pub severity: ValidationSeverity,
+    pub layer: String,
+    pub description: String,
+}
+
+impl ValidationMessage {
+    fn new(msg: &Message) -> Self {
+        let severity = if msg.ty.error {
+            ValidationSeverity::Error
+        } else if msg.ty.warning {
+            ValidationSeverity::Warning
+        } else if msg.ty.performance_warning {
+            ValidationSeverity::PerformanceWarning
+        } else if msg.ty.information {
+            ValidationSeverity::Information
+        } else {
+            ValidationSeverity::Debug
+        };
+
+        Self {
+            severity,
+            layer: msg.layer_prefix.to_owned(),
+            description: msg.description.to_owned(),
+        }
+    }
+}
+
+// called (on whatever thread the driver likes) for every message from the
+// validation layers, on top of them being printed. e.g. tests can count the
+// errors to make sure there weren't any.
+pub type ValidationHandler = Arc<dyn Fn(&ValidationMessage) + Send + Sync>;
+
+static VALIDATION_ENABLED: AtomicBool = AtomicBool::new(false);
+const REPORT_QUEUE_FAMILIES: bool = cfg!(debug_assertions);
+// e.g. to tell whether tearing or stutter comes from falling back to Fifo
+const REPORT_PRESENT_MODE: bool = cfg!(debug_assertions);
+
+// whether the validation layers were actually loaded by create_instance()
+#[cfg(feature = "engine-api")]
+pub fn validation_enabled() -> bool {
+    VALIDATION_ENABLED.load(Ordering::Acquire)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ValidationMode {
+    Off,
+    // use the layers if they're installed
+    On,
+    // insist on them
+    Strict,
+}
+
+fn validation_requested(config: &EngineConfig) -> ValidationMode {
+    let mode = match env::var(VALIDATION_ENV_VAR) {
+        Ok(ref value) if value == "strict" => ValidationMode::Strict,
+        Ok(ref value) if !value.is_empty() && value != "0" => ValidationMode::On,
+        Ok(_) => ValidationMode::Off,
+        Err(_) if ENABLE_VALIDATION_LAYERS => ValidationMode::On,
+        Err(_) => ValidationMode::Off,
+    };
+
+    // EngineConfig::validation wins, except it can't make strict mode lenient
+    match config.validation {
+        Some(false) => ValidationMode::Off,
+        Some(true) if mode == ValidationMode::Off => ValidationMode::On,
+        _ => mode,
+    }
+}
+
+pub fn create_instance(config: &EngineConfig) -> (Arc<Instance>, Option<DebugCallback>) {
+    let mode = validation_requested(config);
+    let validation = mode != ValidationMode::Off;
+
+    let layers = if validation {
         if check_validation_layer_support() {
+            VALIDATION_ENABLED.store(true, Ordering::Release);
             VALIDATION_LAYERS
+        } else if mode == ValidationMode::Strict {
+            panic!(
+                "validation layers are unavailable (and {}=strict)",
+                VALIDATION_ENV_VAR
+            );
         } else {
-            eprintln!("warning: validation layers are unavailable");
+            log::warn!(
+                "validation layers are unavailable (set {}=strict to make this an error)",
+                VALIDATION_ENV_VAR
+            );
             &[]
         }
     } else {
@@ -46,16 +161,20 @@ pub fn create_instance() -> (Arc<Instance>, Option<DebugCallback>) {
     // window-drawing functionality is in non-core extensions
     let mut extensions = vulkano_win::required_extensions();
 
-    if ENABLE_VALIDATION_LAYERS {
+    if validation {
         // TODO: this should be ext_debug_utils (_report is deprecated)
         // ext_debug_utils doesn't yet exist in vulkano
         extensions.ext_debug_report = true;
     }
 
-    let instance = Instance::new(Some(&get_app_info()), &extensions, layers)
+    let instance = Instance::new(Some(&config.app_info()), &extensions, layers)
         .expect("Failed to create Vulkan instance");
 
-    let debug_callback = setup_debug_callback(&instance);
+    let debug_callback = if validation {
+        setup_debug_callback(&instance, config.validation_handler.clone())
+    } else {
+        None
+    };
 
     (instance, debug_callback)
 }
@@ -71,30 +190,48 @@ fn check_validation_layer_support() -> bool {
         .all(|layer_name| layers.contains(&layer_name.to_string()))
 }
 
-fn setup_debug_callback(instance: &Arc<Instance>) -> Option<DebugCallback> {
-    if ENABLE_VALIDATION_LAYERS {
-        let msg_types = MessageTypes {
-            error: true,
-            warning: true,
-            performance_warning: true,
-            information: false,
-            debug: true,
-        };
+fn setup_debug_callback(
+    instance: &Arc<Instance>,
+    handler: Option<ValidationHandler>,
+) -> Option<DebugCallback> {
+    let msg_types = MessageTypes {
+        error: true,
+        warning: true,
+        performance_warning: true,
+        information: false,
+        debug: true,
+    };
 
-        DebugCallback::new(&instance, msg_types, |msg| {
-            eprintln!("[validation]{}", msg.description);
-        })
-        .ok()
-    } else {
-        None
-    }
+    // a panicking handler can't leave anything of ours in a bad state
+    let handler = AssertUnwindSafe(handler);
+    DebugCallback::new(&instance, msg_types, move |msg| {
+        let msg = ValidationMessage::new(msg);
+        let level = match msg.severity {
+            ValidationSeverity::Error => Level::Error,
+            ValidationSeverity::Warning | ValidationSeverity::PerformanceWarning => Level::Warn,
+            ValidationSeverity::Information | ValidationSeverity::Debug => Level::Debug,
+        };
+        log::log!(level, "[validation][{}]{}", msg.layer, msg.description);
+        if let Some(handler) = &handler.0 {
+            handler(&msg);
+        }
+    })
+    .ok()
 }
 
 pub fn create_logical_device(
     instance: &Arc<Instance>,
     surface: &Arc<Surface<Window>>,
-) -> (Arc<Device>, DeviceConfig, Queues) {
-    let (physical_device, device_config) = config::pick_physical_device(&instance, &surface);
+) -> Result<(Arc<Device>, DeviceConfig, Queues), RenderError> {
+    let (physical_device, device_config) =
+        config::pick_physical_device(&instance, &surface).ok_or(RenderError::NoSuitableDevice)?;
+
+    if REPORT_QUEUE_FAMILIES {
+        log::info!("{}", device_config.queue_report());
+    }
+    if REPORT_PRESENT_MODE {
+        log::info!("{}", device_config.present_mode_report());
+    }
 
     // one might think if queue_families.graphics == queue_families.compute
     // we wouldn't have to have multiple (redundant, in this case) entries.
@@ -123,11 +260,11 @@ pub fn create_logical_device(
         &device_ext,
         queue_families,
     )
-    .expect("Failed to create logical device");
+    .map_err(RenderError::DeviceCreation)?;
 
     let queues = Queues::from_iter(queues);
 
-    (device, device_config, queues)
+    Ok((device, device_config, queues))
 }
 
 pub fn create_swapchain(
@@ -136,7 +273,8 @@ pub fn create_swapchain(
     dimensions: PhysicalSize,
     device_config: &DeviceConfig,
     queues: &Queues,
-) -> (Arc<Swapchain<Window>>, Vec<Arc<SwapchainImage<Window>>>) {
+    old_swapchain: Option<&Arc<Swapchain<Window>>>,
+) -> Result<SwapchainParts, SwapchainCreationError> {
     let capabilities = &device_config.capabilities;
 
     let image_count = capabilities
@@ -159,12 +297,11 @@ pub fn create_swapchain(
         image_usage,
         queues::get_sharing_mode(&device_config.queue_families, &queues),
         SurfaceTransform::Identity,
-        config::choose_alpha_mode(capabilities.supported_composite_alpha),
+        device_config.composite_alpha,
         device_config.present_mode,
         true,
-        None, // old_swapchain
+        old_swapchain,
     )
-    .expect("Failed to create swapchain")
 }
 
 pub fn create_render_pass(
@@ -190,37 +327,84 @@ pub fn create_render_pass(
     )
 }
 
+// the viewport is dynamic state (see create_graphics_pipeline), so it's set
+// when recording the command buffers instead
+pub fn create_viewport(dimensions: [u32; 2]) -> Viewport {
+    Viewport {
+        origin: [0.0, 0.0],
+        dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+        // NOTE: this is the range of Vulkan's NDC depth, not the near/far
+        // clip planes. the camera is only a 2D pan/zoom applied to positions
+        // as they're uploaded, and there's no depth buffer yet; once there
+        // is, near/far belong on a projection matrix (consider reversed Z,
+        // i.e. near -> 1.0 and far -> 0.0 with a GREATER depth test, for
+        // precision on distant planets), and this should stay 0.0..1.0.
+        depth_range: 0.0..1.0,
+    }
+}
+
+// the parts of the pipeline that can be changed while rendering. they're baked
+// into the pipeline, so changing any of them means building a new one.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PipelineSettings {
+    pub point_shape: PointShape,
+    pub particle_alpha: f32,
+    // how wide the trails are drawn (the particles are points, so it doesn't
+    // affect them). anything but 1.0 needs the wide_lines feature (see
+    // DeviceConfig::line_width_range)
+    pub line_width: f32,
+}
+
+impl Default for PipelineSettings {
+    fn default() -> Self {
+        Self {
+            point_shape: Default::default(),
+            particle_alpha: 1.0,
+            line_width: 1.0,
+        }
+    }
+}
+
+// the pipeline doesn't depend on the window size, so it only has to be
+// rebuilt if the render pass (i.e. the swapchain format) changes
+pub fn needs_new_pipeline(old_format: Format, new_format: Format) -> bool {
+    old_format != new_format
+}
+
 pub fn create_graphics_pipeline(
     device: Arc<Device>,
-    dimensions: PhysicalSize,
-    device_config: &DeviceConfig,
     render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    shaders: &ShaderModules,
+    settings: PipelineSettings,
 ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
-    use crate::shaders::{particle_frag, particle_vert};
-
-    let vertex = particle_vert::Shader::load(device.clone())
-        .expect("Failed to create/compile vertex shader module");
-    let fragment = particle_frag::Shader::load(device.clone())
-        .expect("Failed to create/compile fragment shader module");
+    let fragment_constants = particle_frag::SpecializationConstants {
+        point_shape: settings.point_shape as i32,
+        particle_alpha: settings.particle_alpha,
+    };
 
-    let capabilities = &device_config.capabilities;
-    let viewport = Viewport {
-        origin: [0.0, 0.0],
-        dimensions: clamp_window_size(dimensions, capabilities).to_extents(),
-        depth_range: 0.0..1.0,
+    // particle.frag outputs straight (not premultiplied) alpha, and blending
+    // it over the framebuffer like this leaves the colors premultiplied, which
+    // is what CompositeAlpha::PreMultiplied wants. the clear color is opaque,
+    // so with One as the alpha source factor, anything drawn over it stays
+    // opaque too; only the clear color ever shows through to the desktop.
+    let blend = AttachmentBlend {
+        alpha_source: BlendFactor::One,
+        ..AttachmentBlend::alpha_blending()
     };
 
     Arc::new(
         // TODO: simplify pipeline builder settings
         // see main.old.rs (old branch) and vulkan-tutorial-rs
         GraphicsPipeline::start()
-            .vertex_input_single_buffer::<particle_vert::Vertex>()
-            .vertex_shader(vertex.main_entry_point(), ())
+            .vertex_input_single_buffer::<Vertex>()
+            .vertex_shader(shaders.vertex_entry_point(), ())
             .point_list()
             .primitive_restart(false)
-            .viewports(vec![viewport])
-            .fragment_shader(fragment.main_entry_point(), ())
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(shaders.fragment_entry_point(), fragment_constants)
             .depth_clamp(false)
+            // for PointShape::Soft and translucent particles
+            .blend_collective(blend)
             // TODO: "there's a commented out .rasterizer_discard() in Vulkano..."
             .render_pass(Subpass::from(render_pass, 0).unwrap())
             .build(device)
@@ -228,6 +412,42 @@ pub fn create_graphics_pipeline(
     )
 }
 
+// trails are drawn as lines between each particle's past positions, fading
+// out with age. they always use the built-in shaders, so they don't depend on
+// the ShaderModules.
+pub fn create_trail_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    settings: PipelineSettings,
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+    let vertex_shader =
+        trail_vert::Shader::load(device.clone()).expect("Failed to load trail vertex shader");
+    let fragment_shader =
+        trail_frag::Shader::load(device.clone()).expect("Failed to load trail fragment shader");
+
+    // see create_graphics_pipeline
+    let blend = AttachmentBlend {
+        alpha_source: BlendFactor::One,
+        ..AttachmentBlend::alpha_blending()
+    };
+
+    Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input_single_buffer::<trail_vert::Vertex>()
+            .vertex_shader(vertex_shader.main_entry_point(), ())
+            .line_list()
+            .primitive_restart(false)
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fragment_shader.main_entry_point(), ())
+            .depth_clamp(false)
+            .line_width(settings.line_width)
+            .blend_collective(blend)
+            .render_pass(Subpass::from(render_pass, 0).unwrap())
+            .build(device)
+            .expect("Failed to create trail pipeline"),
+    )
+}
+
 pub fn create_framebuffers(
     swapchain_images: &[Arc<SwapchainImage<Window>>],
     render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
@@ -247,37 +467,83 @@ pub fn create_framebuffers(
         .collect()
 }
 
-pub fn create_vertex_buffer(device: Arc<Device>) -> Arc<dyn BufferAccess + Send + Sync> {
-    use crate::shaders::particle_vert::Vertex;
+pub fn create_vertex_buffer<V>(
+    device: Arc<Device>,
+    mut vertices: Vec<V>,
+    capacity: usize,
+) -> Arc<CpuAccessibleBuffer<[V]>>
+where
+    V: Clone + Default + Send + Sync + 'static,
+{
+    // the unused tail of the buffer is never drawn (see Render::particle_slice)
+    // but Vulkan doesn't allow empty buffers, so there's always at least one
+    let capacity = capacity.max(vertices.len()).max(1);
+    vertices.resize(capacity, V::default());
 
     // TODO: better buffer type
-    CpuAccessibleBuffer::from_iter(
-        device,
-        BufferUsage::vertex_buffer(),
-        [
-            Vertex {
-                position: [-0.5, -0.5],
-                ..Default::default()
-            },
-            Vertex {
-                position: [-0.5, 0.5],
-                ..Default::default()
-            },
-            Vertex {
-                position: [0.5, 0.5],
-                ..Default::default()
-            },
-            Vertex {
-                position: [0.5, -0.5],
-                ..Default::default()
-            },
-        ]
-        .iter()
-        .cloned(),
-    )
-    .expect("Failed to create vertex buffer")
+    CpuAccessibleBuffer::from_iter(device, BufferUsage::vertex_buffer(), vertices.into_iter())
+        .expect("Failed to create vertex buffer")
 }
 
-pub fn create_sync_objects(device: Arc<Device>) -> Box<dyn GpuFuture> {
-    Box::new(sync::now(device))
+// writes the vertices to the start of the buffer, or if that can't be done,
+// replaces it with a new one holding them (and returns true)
+pub fn update_vertex_buffer<V>(
+    device: Arc<Device>,
+    buffer: &mut Arc<CpuAccessibleBuffer<[V]>>,
+    vertices: &[V],
+) -> bool
+where
+    V: Clone + Default + Send + Sync + 'static,
+{
+    let capacity = buffer.len();
+    if vertices.len() <= capacity && write_vertices(buffer, vertices) {
+        return false;
+    }
+
+    // either the buffer is too small, or the GPU is still reading it (which
+    // shouldn't happen, see FrameSlot). rather than waiting, make a new one;
+    // the old one is freed once the frames using it are done.
+    let capacity = if vertices.len() > capacity {
+        // amortized doubling, just like Vec
+        cmp::max(vertices.len(), capacity * 2)
+    } else {
+        capacity
+    };
+
+    *buffer = create_vertex_buffer(device, vertices.to_vec(), capacity);
+    true
+}
+
+fn write_vertices<V: Clone>(buffer: &CpuAccessibleBuffer<[V]>, vertices: &[V]) -> bool {
+    match buffer.write() {
+        Ok(mut buffer) => {
+            buffer[..vertices.len()].clone_from_slice(vertices);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+pub fn create_sync_objects<T>(frames_in_flight: usize) -> Vec<Option<T>> {
+    // every slot starts out empty, i.e. with no frame to wait for
+    (0..frames_in_flight).map(|_| None).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resizing_keeps_the_pipeline() {
+        // a resize keeps the format, and the viewport is dynamic state
+        assert!(!needs_new_pipeline(
+            Format::B8G8R8A8Unorm,
+            Format::B8G8R8A8Unorm
+        ));
+        // (e.g. set_prefer_hdr)
+        assert!(needs_new_pipeline(
+            Format::B8G8R8A8Unorm,
+            Format::A2B10G10R10UnormPack32
+        ));
+    }
 }
@@ -8,8 +8,9 @@ pub mod particle_vert {
     pub struct Vertex {
         pub position: [f32; 2],
         pub velocity: [f32; 2],
+        pub hue: f32,
     }
-    vulkano::impl_vertex!(Vertex, position, velocity);
+    vulkano::impl_vertex!(Vertex, position, velocity, hue);
 }
 
 pub mod particle_frag {
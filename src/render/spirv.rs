@@ -0,0 +1,421 @@
+use vulkano::{
+    descriptor::descriptor::ShaderStages,
+    device::Device,
+    pipeline::shader::{GraphicsEntryPoint, GraphicsShaderType, ShaderModule},
+    OomError,
+};
+
+use std::{collections::HashMap, convert::TryInto, ffi::CStr, fmt, io, sync::Arc};
+#[cfg(feature = "engine-api")]
+use std::{fs, path::Path};
+
+use crate::shaders::{particle_frag, particle_vert};
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+type VertexEntryPoint<'a> = GraphicsEntryPoint<
+    'a,
+    (),
+    particle_vert::MainInput,
+    particle_vert::MainOutput,
+    particle_vert::Layout,
+>;
+type FragmentEntryPoint<'a> = GraphicsEntryPoint<
+    'a,
+    particle_frag::SpecializationConstants,
+    particle_frag::MainInput,
+    particle_frag::MainOutput,
+    particle_frag::Layout,
+>;
+
+// the few bits of the SPIR-V spec needed to check a shader's interface
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const DECORATION_LOCATION: u32 = 30;
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_INPUT: u32 = 1;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+const EXECUTION_MODEL_VERTEX: u32 = 0;
+const EXECUTION_MODEL_FRAGMENT: u32 = 4;
+
+// (location, number of floats) for what particle.vert gets from Vertex, and
+// what particle.frag gets from particle.vert. custom shaders can use fewer
+// inputs, but not more, and not of different types.
+const VERTEX_INPUTS: &[(u32, u32)] = &[(0, 2), (1, 2), (2, 1)];
+const FRAGMENT_INPUTS: &[(u32, u32)] = &[(0, 1)];
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Io(io::Error),
+    NotSpirv,
+    NoMainEntryPoint,
+    // the shader reads an input at a location nothing writes to
+    UnknownInput(u32),
+    // the shader reads an input as a different type than it's written as
+    // (e.g. a vec4 where there's only a vec2)
+    WrongInputType(u32),
+    // the shader uses uniforms, buffers, images or push constants, none of
+    // which the particle pipeline has
+    UnsupportedResource,
+    Creation(OomError),
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderError::Io(e) => write!(f, "failed to read shader: {}", e),
+            ShaderError::NotSpirv => write!(f, "shader is not valid SPIR-V"),
+            ShaderError::NoMainEntryPoint => write!(f, "shader has no main() of the right type"),
+            ShaderError::UnknownInput(location) => {
+                write!(f, "shader has an input at unknown location {}", location)
+            }
+            ShaderError::WrongInputType(location) => {
+                write!(f, "shader's input at location {} has the wrong type", location)
+            }
+            ShaderError::UnsupportedResource => write!(
+                f,
+                "shader uses uniforms, buffers, images or push constants, which custom shaders can't"
+            ),
+            ShaderError::Creation(e) => write!(f, "failed to create shader module: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for ShaderError {
+    fn from(e: io::Error) -> Self {
+        ShaderError::Io(e)
+    }
+}
+
+// compiled SPIR-V to use instead of the built-in particle shaders (e.g. to
+// iterate on a shader without rebuilding). anything not replaced stays
+// built-in. the replacements have to take the same inputs as the originals.
+#[derive(Clone, Default)]
+pub struct ParticleShaders {
+    vertex: Option<Vec<u8>>,
+    fragment: Option<Vec<u8>>,
+}
+
+impl ParticleShaders {
+    pub fn builtin() -> Self {
+        Default::default()
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_vertex_spirv(mut self, spirv: Vec<u8>) -> Self {
+        self.vertex = Some(spirv);
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_fragment_spirv(mut self, spirv: Vec<u8>) -> Self {
+        self.fragment = Some(spirv);
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn load_vertex<P: AsRef<Path>>(self, path: P) -> Result<Self, ShaderError> {
+        Ok(self.with_vertex_spirv(fs::read(path)?))
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn load_fragment<P: AsRef<Path>>(self, path: P) -> Result<Self, ShaderError> {
+        Ok(self.with_fragment_spirv(fs::read(path)?))
+    }
+}
+
+pub(super) struct ShaderModules {
+    vertex: Arc<ShaderModule>,
+    fragment: Arc<ShaderModule>,
+}
+
+impl ShaderModules {
+    pub fn load(device: Arc<Device>, shaders: &ParticleShaders) -> Result<Self, ShaderError> {
+        let vertex = match &shaders.vertex {
+            Some(spirv) => {
+                load_spirv(device.clone(), spirv, EXECUTION_MODEL_VERTEX, VERTEX_INPUTS)?
+            }
+            None => particle_vert::Shader::load(device.clone())
+                .map_err(ShaderError::Creation)?
+                .module()
+                .clone(),
+        };
+
+        let fragment = match &shaders.fragment {
+            Some(spirv) => load_spirv(device, spirv, EXECUTION_MODEL_FRAGMENT, FRAGMENT_INPUTS)?,
+            None => particle_frag::Shader::load(device)
+                .map_err(ShaderError::Creation)?
+                .module()
+                .clone(),
+        };
+
+        Ok(Self { vertex, fragment })
+    }
+
+    // NOTE: custom shaders reuse the built-in shaders' interfaces, which is
+    // only sound because load_spirv() checked they have no descriptors or
+    // push constants (the built-in layouts have none), and only read inputs
+    // the built-in shaders do, as the same types. (they get particle.frag's
+    // specialization constants too, but Vulkan ignores constants a shader
+    // doesn't declare.)
+
+    pub fn vertex_entry_point(&self) -> VertexEntryPoint {
+        let stages = ShaderStages {
+            vertex: true,
+            ..ShaderStages::none()
+        };
+
+        unsafe {
+            self.vertex.graphics_entry_point(
+                main_name(),
+                particle_vert::MainInput,
+                particle_vert::MainOutput,
+                particle_vert::Layout(stages),
+                GraphicsShaderType::Vertex,
+            )
+        }
+    }
+
+    pub fn fragment_entry_point(&self) -> FragmentEntryPoint {
+        let stages = ShaderStages {
+            fragment: true,
+            ..ShaderStages::none()
+        };
+
+        unsafe {
+            self.fragment.graphics_entry_point(
+                main_name(),
+                particle_frag::MainInput,
+                particle_frag::MainOutput,
+                particle_frag::Layout(stages),
+                GraphicsShaderType::Fragment,
+            )
+        }
+    }
+}
+
+fn main_name() -> &'static CStr {
+    CStr::from_bytes_with_nul(b"main\0").unwrap()
+}
+
+fn load_spirv(
+    device: Arc<Device>,
+    spirv: &[u8],
+    execution_model: u32,
+    inputs: &[(u32, u32)],
+) -> Result<Arc<ShaderModule>, ShaderError> {
+    check_interface(spirv, execution_model, inputs)?;
+
+    // unsafe because vulkano can't check the SPIR-V itself; check_interface()
+    // covered the parts that matter to us
+    unsafe { ShaderModule::new(device, spirv) }.map_err(ShaderError::Creation)
+}
+
+fn check_interface(
+    spirv: &[u8],
+    execution_model: u32,
+    allowed: &[(u32, u32)],
+) -> Result<(), ShaderError> {
+    if spirv.len() % 4 != 0 {
+        return Err(ShaderError::NotSpirv);
+    }
+
+    let words: Vec<u32> = spirv
+        .chunks(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+
+    // the header is 5 words long
+    if words.len() < 5 || words[0] != SPIRV_MAGIC {
+        return Err(ShaderError::NotSpirv);
+    }
+
+    let mut has_main = false;
+    // input variable ID -> its (pointer) type's ID
+    let mut inputs = HashMap::new();
+    let mut locations = HashMap::new();
+    // type ID -> what it points to, (component type, count), or width
+    let mut pointers = HashMap::new();
+    let mut vectors = HashMap::new();
+    let mut floats = HashMap::new();
+
+    let mut rest = &words[5..];
+    while let Some(&first) = rest.first() {
+        let (length, opcode) = ((first >> 16) as usize, first & 0xffff);
+        if length == 0 || length > rest.len() {
+            return Err(ShaderError::NotSpirv);
+        }
+
+        let operands = &rest[1..length];
+        match opcode {
+            // (execution model, function id, name)
+            OP_ENTRY_POINT if operands.len() >= 3 && operands[0] == execution_model => {
+                // the name is a nul-terminated string packed into words
+                let name: Vec<u8> = operands[2..]
+                    .iter()
+                    .flat_map(|w| w.to_le_bytes().to_vec())
+                    .collect();
+                has_main |= name.split(|&b| b == 0).next() == Some(&b"main"[..]);
+            }
+            // (id, width)
+            OP_TYPE_FLOAT if operands.len() >= 2 => {
+                floats.insert(operands[0], operands[1]);
+            }
+            // (id, component type, count)
+            OP_TYPE_VECTOR if operands.len() >= 3 => {
+                vectors.insert(operands[0], (operands[1], operands[2]));
+            }
+            // (id, storage class, type)
+            OP_TYPE_POINTER if operands.len() >= 3 => {
+                pointers.insert(operands[0], operands[2]);
+            }
+            // (type, id, storage class)
+            OP_VARIABLE if operands.len() >= 3 => match operands[2] {
+                STORAGE_CLASS_INPUT => {
+                    inputs.insert(operands[1], operands[0]);
+                }
+                STORAGE_CLASS_UNIFORM_CONSTANT
+                | STORAGE_CLASS_UNIFORM
+                | STORAGE_CLASS_PUSH_CONSTANT
+                | STORAGE_CLASS_STORAGE_BUFFER => return Err(ShaderError::UnsupportedResource),
+                _ => {}
+            },
+            // (id, decoration, location)
+            OP_DECORATE if operands.len() >= 3 && operands[1] == DECORATION_LOCATION => {
+                locations.insert(operands[0], operands[2]);
+            }
+            _ => {}
+        }
+
+        rest = &rest[length..];
+    }
+
+    if !has_main {
+        return Err(ShaderError::NoMainEntryPoint);
+    }
+
+    // how many 32-bit floats a type is made of, if that's all it is
+    let floats_in = |ty| match vectors.get(&ty) {
+        Some(&(component, count)) if floats.get(&component) == Some(&32) => Some(count),
+        Some(_) => None,
+        None if floats.get(&ty) == Some(&32) => Some(1),
+        None => None,
+    };
+
+    // built-ins (e.g. gl_VertexIndex) have no location, and are always fine
+    for (id, pointer) in &inputs {
+        if let Some(&location) = locations.get(id) {
+            let components = match allowed.iter().find(|&&(allowed, _)| allowed == location) {
+                Some(&(_, components)) => components,
+                None => return Err(ShaderError::UnknownInput(location)),
+            };
+
+            if pointers.get(pointer).and_then(|&ty| floats_in(ty)) != Some(components) {
+                return Err(ShaderError::WrongInputType(location));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OP_TYPE_INT: u32 = 21;
+    const STORAGE_CLASS_OUTPUT: u32 = 3;
+
+    fn instruction(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    // a vertex shader with a main() and whatever else is given (IDs 1-9 are
+    // taken: 1 is main, 2 is a float, 3 a vec2, 4 a vec4, 5 an int, and 6-9
+    // pointers to them as inputs)
+    fn vertex_shader(rest: &[Vec<u32>]) -> Vec<u8> {
+        let main = u32::from_le_bytes(*b"main");
+        let mut words = vec![SPIRV_MAGIC, 0x0001_0000, 0, 100, 0];
+
+        let mut instructions = vec![
+            instruction(OP_ENTRY_POINT, &[EXECUTION_MODEL_VERTEX, 1, main, 0]),
+            instruction(OP_TYPE_FLOAT, &[2, 32]),
+            instruction(OP_TYPE_VECTOR, &[3, 2, 2]),
+            instruction(OP_TYPE_VECTOR, &[4, 2, 4]),
+            instruction(OP_TYPE_INT, &[5, 32, 1]),
+            instruction(OP_TYPE_POINTER, &[6, STORAGE_CLASS_INPUT, 2]),
+            instruction(OP_TYPE_POINTER, &[7, STORAGE_CLASS_INPUT, 3]),
+            instruction(OP_TYPE_POINTER, &[8, STORAGE_CLASS_INPUT, 4]),
+            instruction(OP_TYPE_POINTER, &[9, STORAGE_CLASS_INPUT, 5]),
+        ];
+        instructions.extend_from_slice(rest);
+
+        words.extend(instructions.concat());
+        words
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    // an input of type `pointer` at `location`
+    fn input(id: u32, pointer: u32, location: u32) -> Vec<Vec<u32>> {
+        vec![
+            instruction(OP_DECORATE, &[id, DECORATION_LOCATION, location]),
+            instruction(OP_VARIABLE, &[pointer, id, STORAGE_CLASS_INPUT]),
+        ]
+    }
+
+    fn check(rest: &[Vec<u32>]) -> Result<(), ShaderError> {
+        check_interface(&vertex_shader(rest), EXECUTION_MODEL_VERTEX, VERTEX_INPUTS)
+    }
+
+    #[test]
+    fn matching_inputs_are_allowed() {
+        let inputs = [input(10, 7, 0), input(11, 7, 1), input(12, 6, 2)].concat();
+        assert!(check(&inputs).is_ok());
+
+        // (fewer is fine too, and so are outputs)
+        let output = instruction(OP_VARIABLE, &[8, 13, STORAGE_CLASS_OUTPUT]);
+        assert!(check(&[input(10, 7, 0), vec![output]].concat()).is_ok());
+    }
+
+    #[test]
+    fn inputs_have_to_match_the_vertex_layout() {
+        match check(&input(10, 7, 3)) {
+            Err(ShaderError::UnknownInput(3)) => {}
+            other => panic!("{:?}", other),
+        }
+
+        // a vec4 or an int where there's a vec2
+        for &pointer in &[8, 9] {
+            match check(&input(10, pointer, 0)) {
+                Err(ShaderError::WrongInputType(0)) => {}
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn resources_are_rejected() {
+        for &storage_class in &[
+            STORAGE_CLASS_UNIFORM_CONSTANT,
+            STORAGE_CLASS_UNIFORM,
+            STORAGE_CLASS_PUSH_CONSTANT,
+            STORAGE_CLASS_STORAGE_BUFFER,
+        ] {
+            let resource = instruction(OP_VARIABLE, &[6, 10, storage_class]);
+            match check(&[resource]) {
+                Err(ShaderError::UnsupportedResource) => {}
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+}
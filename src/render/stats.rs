@@ -0,0 +1,65 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+// how many of the most recent frames FrameStats covers
+const FRAME_HISTORY: usize = 240;
+
+// how long recent frames took from one to the next. hitches show up in p99
+// (and max) long before they move the average.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    pub min: Duration,
+    pub max: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub avg: Duration,
+}
+
+pub(super) struct FrameTimer {
+    last_frame: Option<Instant>,
+    durations: VecDeque<Duration>,
+}
+
+impl FrameTimer {
+    pub fn new() -> Self {
+        Self {
+            last_frame: None,
+            durations: VecDeque::with_capacity(FRAME_HISTORY),
+        }
+    }
+
+    pub fn record(&mut self) {
+        let now = Instant::now();
+
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            if self.durations.len() == FRAME_HISTORY {
+                self.durations.pop_front();
+            }
+            self.durations.push_back(now - last_frame);
+        }
+    }
+
+    // all zeroes until at least two frames have been drawn
+    pub fn stats(&self) -> FrameStats {
+        let mut sorted: Vec<Duration> = self.durations.iter().copied().collect();
+        sorted.sort_unstable();
+
+        let percentile = |p: f64| {
+            let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+            sorted[index]
+        };
+
+        match (sorted.first(), sorted.last()) {
+            (Some(&min), Some(&max)) => FrameStats {
+                min,
+                max,
+                p50: percentile(0.5),
+                p99: percentile(0.99),
+                avg: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+            },
+            _ => Default::default(),
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use vulkano::buffer::CpuAccessibleBuffer;
+
+use std::{collections::VecDeque, iter, sync::Arc};
+
+use super::{Camera, Particle, Render};
+use crate::shaders::trail_vert::Vertex;
+
+pub(super) type TrailBuffer = Arc<CpuAccessibleBuffer<[Vertex]>>;
+
+// the last few positions of every particle, newest first. old snapshots are
+// reused for new ones once the trail is full, so after warming up this
+// doesn't allocate.
+pub(super) struct Trails {
+    length: usize,
+    history: VecDeque<Vec<[f32; 2]>>,
+}
+
+impl Trails {
+    pub fn new() -> Self {
+        Self {
+            length: 0,
+            history: VecDeque::new(),
+        }
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn set_length(&mut self, length: usize) {
+        self.length = length;
+        self.history.truncate(length);
+        if length == 0 {
+            self.history = VecDeque::new();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+
+    // called every time the particles move
+    pub fn record(&mut self, particles: &[Particle]) {
+        if self.length == 0 {
+            return;
+        }
+
+        // a trail only means something if every particle stays in the same
+        // slot, so start over whenever particles come or go
+        if let Some(newest) = self.history.front() {
+            if newest.len() != particles.len() {
+                self.history.clear();
+            }
+        }
+
+        let mut snapshot = if self.history.len() == self.length {
+            self.history.pop_back().unwrap()
+        } else {
+            Vec::with_capacity(particles.len())
+        };
+
+        snapshot.clear();
+        snapshot.extend(particles.iter().map(|p| p.position));
+        self.history.push_front(snapshot);
+    }
+
+    // a line list: a segment from each particle's position in every snapshot
+    // to where it was in the one before. older positions fade out, reaching
+    // (almost) nothing at the end.
+    pub fn to_vertices(&self, camera: &Camera) -> Vec<Vertex> {
+        let length = self.length as f32;
+        let vertex = |position, age| Vertex {
+            position: camera.apply(position),
+            alpha: 1.0 - age as f32 / length,
+        };
+
+        self.history
+            .iter()
+            .zip(self.history.iter().skip(1))
+            .enumerate()
+            .flat_map(|(age, (newer, older))| {
+                newer.iter().zip(older).flat_map(move |(&to, &from)| {
+                    iter::once(vertex(to, age)).chain(iter::once(vertex(from, age + 1)))
+                })
+            })
+            .collect()
+    }
+}
+
+impl<'a> Render<'a> {
+    #[cfg(feature = "engine-api")]
+    pub fn trail_length(&self) -> usize {
+        self.trails.length()
+    }
+
+    // how many of each particle's past positions (one per simulation step)
+    // to draw behind it. 0 turns trails off, and frees everything they use.
+    pub fn set_trail_length(&mut self, length: usize) {
+        self.trails.set_length(length);
+        self.particles_dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f32) -> Particle {
+        Particle {
+            position: [x, 0.0],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn trails_are_line_segments_between_snapshots() {
+        let mut trails = Trails::new();
+        trails.set_length(4);
+        for &x in &[0.0, 0.1, 0.2] {
+            trails.record(&[at(x), at(-x)]);
+        }
+
+        let vertices = trails.to_vertices(&Camera::default());
+
+        let lines: Vec<_> = vertices
+            .chunks(2)
+            .map(|line| (line[0].position[0], line[1].position[0]))
+            .collect();
+        assert_eq!(lines, [(0.2, 0.1), (-0.2, -0.1), (0.1, 0.0), (-0.1, -0.0)]);
+
+        let alphas: Vec<_> = vertices.iter().map(|v| v.alpha).collect();
+        assert_eq!(alphas, [1.0, 0.75, 1.0, 0.75, 0.75, 0.5, 0.75, 0.5]);
+    }
+
+    #[test]
+    fn one_snapshot_is_no_trail() {
+        let mut trails = Trails::new();
+        trails.set_length(4);
+        trails.record(&[at(0.0)]);
+
+        assert!(trails.to_vertices(&Camera::default()).is_empty());
+    }
+}
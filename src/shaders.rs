@@ -1,3 +1,17 @@
+pub mod trail_vert {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "shaders/trail.vert"
+    }
+
+    #[derive(Debug, Clone, Default)]
+    pub struct Vertex {
+        pub position: [f32; 2],
+        pub alpha: f32,
+    }
+    vulkano::impl_vertex!(Vertex, position, alpha);
+}
+
 pub mod particle_vert {
     vulkano_shaders::shader! {
         ty: "vertex",
@@ -8,8 +22,9 @@ pub mod particle_vert {
     pub struct Vertex {
         pub position: [f32; 2],
         pub velocity: [f32; 2],
+        pub hue: f32,
     }
-    vulkano::impl_vertex!(Vertex, position, velocity);
+    vulkano::impl_vertex!(Vertex, position, velocity, hue);
 }
 
 pub mod particle_frag {
@@ -18,3 +33,10 @@ pub mod particle_frag {
         path: "shaders/particle.frag"
     }
 }
+
+pub mod trail_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shaders/trail.frag"
+    }
+}
@@ -0,0 +1,726 @@
+use std::{
+    mem,
+    time::{Duration, Instant},
+};
+
+use crate::render::Particle;
+
+mod barnes_hut;
+
+use barnes_hut::QuadTree;
+
+// how far a single step() advances the simulation while paused
+const STEP_DURATION: Duration = Duration::from_micros(16_667);
+// big frames are split up into steps no longer than this, since every
+// integrator gets less accurate as the time step grows
+const MAX_SUBSTEP: f32 = 1.0 / 120.0;
+// never try to catch up on more than this much time at once (e.g. after the
+// window was dragged around, or a breakpoint was hit)
+const MAX_FRAME_DURATION: Duration = Duration::from_millis(100);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Integrator {
+    // cheap, and unlike explicit Euler, doesn't gain energy over time
+    SymplecticEuler,
+    // also symplectic, but second-order: orbits stay stable for much longer
+    VelocityVerlet,
+    // very accurate per step, but not symplectic, so energy slowly drifts
+    RungeKutta4,
+}
+
+impl Default for Integrator {
+    fn default() -> Self {
+        Integrator::VelocityVerlet
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SimulationMethod {
+    // every particle pulls on every other particle: exact, but O(n²)
+    Direct,
+    // distant groups of particles are lumped together: O(n log n). smaller
+    // values of theta are more accurate (0.0 is the same as Direct).
+    BarnesHut { theta: f32 },
+}
+
+impl Default for SimulationMethod {
+    fn default() -> Self {
+        SimulationMethod::Direct
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Precision {
+    F32,
+    // positions are accumulated in f64 and only rounded to f32 for drawing,
+    // so tiny steps aren't lost to rounding far from the origin. velocities
+    // and forces are still f32.
+    F64,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Precision::F32
+    }
+}
+
+pub struct Simulation {
+    integrator: Integrator,
+    method: SimulationMethod,
+    precision: Precision,
+    // only used with Precision::F64
+    precise_positions: Vec<[f64; 2]>,
+    gravity: f32,
+    softening: f32,
+    paused: bool,
+    pending_steps: u32,
+    last_update: Option<Instant>,
+    fixed_timestep: Option<Duration>,
+    // with a fixed timestep, time that didn't add up to a whole step yet,
+    // and where the particles were before the last step (to interpolate)
+    accumulator: f32,
+    previous_positions: Vec<[f32; 2]>,
+    // in f64 so it doesn't stop advancing after a few days of tiny steps
+    time: f64,
+    steps: u64,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulation {
+    const DEFAULT_GRAVITY: f32 = 0.01;
+    const DEFAULT_SOFTENING: f32 = 0.01;
+
+    pub fn new() -> Self {
+        Self {
+            integrator: Default::default(),
+            method: Default::default(),
+            precision: Default::default(),
+            precise_positions: Vec::new(),
+            gravity: Self::DEFAULT_GRAVITY,
+            softening: Self::DEFAULT_SOFTENING,
+            paused: false,
+            pending_steps: 0,
+            last_update: None,
+            fixed_timestep: None,
+            accumulator: 0.0,
+            previous_positions: Vec::new(),
+            time: 0.0,
+            steps: 0,
+        }
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn integrator(&self) -> Integrator {
+        self.integrator
+    }
+
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        self.integrator = integrator;
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn method(&self) -> SimulationMethod {
+        self.method
+    }
+
+    pub fn set_method(&mut self, method: SimulationMethod) {
+        self.method = method;
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    // NOTE: the simulation runs on the CPU and only the rounded positions are
+    // uploaded, so F64 doesn't need the GPU to support doubles
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.precision = precision;
+        if precision == Precision::F32 {
+            self.precise_positions = Vec::new();
+        }
+    }
+
+    pub fn gravity(&self) -> f32 {
+        self.gravity
+    }
+
+    // the gravitational constant, in screen units (the window spans -1 to 1)
+    // for particles of mass 1. around 0.001 to 0.1 keeps things on screen;
+    // negative values push particles apart. like the other settings, this
+    // just takes effect from the next step, so it's fine to change every frame.
+    pub fn set_gravity(&mut self, gravity: f32) {
+        self.gravity = gravity;
+    }
+
+    pub fn softening(&self) -> f32 {
+        self.softening
+    }
+
+    // without softening, two particles that get very close to each other
+    // are flung apart at nearly infinite speed. it's a distance in screen
+    // units, so anything past about 0.1 smears out the whole simulation.
+    pub fn set_softening(&mut self, softening: f32) {
+        assert!(softening >= 0.0);
+        self.softening = softening;
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        // don't count the time spent paused as one giant frame
+        self.last_update = None;
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn fixed_timestep(&self) -> Option<Duration> {
+        self.fixed_timestep
+    }
+
+    // always integrate in steps of exactly this long, however long frames
+    // take: time is saved up until there's enough for a whole step, and the
+    // particles are drawn part of the way between their last two positions
+    // (see interpolated_positions). None goes back to one (sub)step per frame.
+    pub fn set_fixed_timestep(&mut self, timestep: Option<Duration>) {
+        assert!(timestep != Some(Duration::from_secs(0)));
+        self.fixed_timestep = timestep;
+        self.reset_interpolation();
+    }
+
+    // forget where the particles were, e.g. because they were all replaced
+    pub fn reset_interpolation(&mut self) {
+        self.accumulator = 0.0;
+        self.previous_positions = Vec::new();
+    }
+
+    // back to time 0, as if nothing had been simulated yet. the settings
+    // (gravity, pausing, etc.) stay as they are
+    pub fn restart(&mut self) {
+        self.precise_positions = Vec::new();
+        self.pending_steps = 0;
+        self.last_update = None;
+        self.reset_interpolation();
+        self.time = 0.0;
+        self.steps = 0;
+    }
+
+    // with a fixed timestep, where the particles should be drawn right now:
+    // as far from their previous positions towards their current ones as the
+    // saved up time is towards the next step. None if they should be drawn
+    // where they are (or if they changed since the last step).
+    pub fn interpolated_positions(&self, particles: &[Particle]) -> Option<Vec<[f32; 2]>> {
+        let timestep = self.fixed_timestep?.as_secs_f32();
+        if self.previous_positions.len() != particles.len() {
+            return None;
+        }
+
+        let t = (self.accumulator / timestep).min(1.0);
+        Some(
+            self.previous_positions
+                .iter()
+                .zip(particles)
+                .map(|(&previous, particle)| {
+                    add(previous, scale(sub(particle.position, previous), t))
+                })
+                .collect(),
+        )
+    }
+
+    // how much simulated time has passed, in seconds
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    // how many integration (sub)steps have run
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // advance by `steps` fixed-length steps, even while paused
+    pub fn step(&mut self, steps: u32) {
+        self.pending_steps = self.pending_steps.saturating_add(steps);
+    }
+
+    // how much time the particles should be advanced by this frame, if any
+    pub fn advance(&mut self) -> Option<f32> {
+        let now = Instant::now();
+
+        let elapsed = if self.paused {
+            if self.pending_steps == 0 {
+                return None;
+            }
+
+            STEP_DURATION * mem::replace(&mut self.pending_steps, 0)
+        } else {
+            let elapsed = self
+                .last_update
+                .map(|last| now - last)
+                .unwrap_or_default()
+                .min(MAX_FRAME_DURATION);
+            self.last_update = Some(now);
+            elapsed + STEP_DURATION * mem::replace(&mut self.pending_steps, 0)
+        };
+
+        Some(elapsed.as_secs_f32())
+    }
+}
+
+impl Simulation {
+    pub fn integrate(&mut self, particles: &mut [Particle], dt: f32) {
+        if self.precision == Precision::F64 {
+            self.sync_precise_positions(particles);
+        }
+
+        match self.fixed_timestep {
+            None => self.integrate_substeps(particles, dt),
+            Some(timestep) => {
+                let timestep = timestep.as_secs_f32();
+                self.accumulator += dt;
+                while self.accumulator >= timestep {
+                    self.accumulator -= timestep;
+                    self.previous_positions.clear();
+                    self.previous_positions
+                        .extend(particles.iter().map(|p| p.position));
+                    self.integrate_substeps(particles, timestep);
+                }
+            }
+        }
+    }
+
+    fn integrate_substeps(&mut self, particles: &mut [Particle], dt: f32) {
+        let substeps = (dt / MAX_SUBSTEP).ceil().max(1.0);
+        let dt = dt / substeps;
+
+        for _ in 0..substeps as u32 {
+            self.integrate_step(particles, dt);
+        }
+    }
+
+    // a single step of exactly dt, however big it is
+    fn integrate_step(&mut self, particles: &mut [Particle], dt: f32) {
+        let displacements = match self.integrator {
+            Integrator::SymplecticEuler => self.symplectic_euler(particles, dt),
+            Integrator::VelocityVerlet => self.velocity_verlet(particles, dt),
+            Integrator::RungeKutta4 => self.runge_kutta_4(particles, dt),
+        };
+
+        self.displace(particles, &displacements);
+
+        self.time += f64::from(dt);
+        self.steps += 1;
+    }
+
+    // particles may have been added, removed, or moved since the last frame,
+    // so any f32 position that isn't what the f64 one rounds to is taken as
+    // the new truth
+    fn sync_precise_positions(&mut self, particles: &[Particle]) {
+        self.precise_positions.resize(particles.len(), [0.0; 2]);
+
+        for (precise, particle) in self.precise_positions.iter_mut().zip(particles) {
+            if to_f32(*precise) != particle.position {
+                *precise = to_f64(particle.position);
+            }
+        }
+    }
+
+    fn displace(&mut self, particles: &mut [Particle], displacements: &[[f32; 2]]) {
+        match self.precision {
+            Precision::F32 => {
+                for (particle, &d) in particles.iter_mut().zip(displacements) {
+                    particle.position = add(particle.position, d);
+                }
+            }
+            Precision::F64 => {
+                let precise = self.precise_positions.iter_mut();
+                for ((particle, &d), precise) in
+                    particles.iter_mut().zip(displacements).zip(precise)
+                {
+                    let d = to_f64(d);
+                    *precise = [precise[0] + d[0], precise[1] + d[1]];
+                    particle.position = to_f32(*precise);
+                }
+            }
+        }
+    }
+
+    // each integrator updates the velocities itself, but only returns how far
+    // each particle should move, so displace() can decide how to add it up
+
+    fn symplectic_euler(&self, particles: &mut [Particle], dt: f32) -> Vec<[f32; 2]> {
+        let accelerations = self.accelerations(particles, &positions(particles));
+
+        particles
+            .iter_mut()
+            .zip(accelerations)
+            .map(|(particle, a)| {
+                particle.velocity = add(particle.velocity, scale(a, dt));
+                scale(particle.velocity, dt)
+            })
+            .collect()
+    }
+
+    fn velocity_verlet(&self, particles: &mut [Particle], dt: f32) -> Vec<[f32; 2]> {
+        let before = self.accelerations(particles, &positions(particles));
+
+        let displacements = particles
+            .iter()
+            .zip(&before)
+            .map(|(particle, a)| scale(add(particle.velocity, scale(*a, dt / 2.0)), dt))
+            .collect::<Vec<_>>();
+
+        let moved = particles
+            .iter()
+            .zip(&displacements)
+            .map(|(particle, &d)| add(particle.position, d))
+            .collect::<Vec<_>>();
+        let after = self.accelerations(particles, &moved);
+
+        for ((particle, a0), a1) in particles.iter_mut().zip(before).zip(after) {
+            particle.velocity = add(particle.velocity, scale(add(a0, a1), dt / 2.0));
+        }
+
+        displacements
+    }
+
+    fn runge_kutta_4(&self, particles: &mut [Particle], dt: f32) -> Vec<[f32; 2]> {
+        let x0 = positions(particles);
+        let v0 = particles.iter().map(|p| p.velocity).collect::<Vec<_>>();
+
+        // each stage is the (velocity, acceleration) at x0 + dx * h
+        let stage = |dx: &[[f32; 2]], dv: &[[f32; 2]], h: f32| {
+            let x = x0
+                .iter()
+                .zip(dx)
+                .map(|(&x, &dx)| add(x, scale(dx, h)))
+                .collect::<Vec<_>>();
+            let v = v0
+                .iter()
+                .zip(dv)
+                .map(|(&v, &dv)| add(v, scale(dv, h)))
+                .collect::<Vec<_>>();
+            let a = self.accelerations(particles, &x);
+            (v, a)
+        };
+
+        let zero = vec![[0.0; 2]; particles.len()];
+        let (k1x, k1v) = stage(&zero, &zero, 0.0);
+        let (k2x, k2v) = stage(&k1x, &k1v, dt / 2.0);
+        let (k3x, k3v) = stage(&k2x, &k2v, dt / 2.0);
+        let (k4x, k4v) = stage(&k3x, &k3v, dt);
+
+        let weighted = |k1: [f32; 2], k2: [f32; 2], k3: [f32; 2], k4: [f32; 2]| {
+            scale(add(add(k1, scale(add(k2, k3), 2.0)), k4), dt / 6.0)
+        };
+
+        particles
+            .iter_mut()
+            .enumerate()
+            .map(|(i, particle)| {
+                particle.velocity = add(v0[i], weighted(k1v[i], k2v[i], k3v[i], k4v[i]));
+                weighted(k1x[i], k2x[i], k3x[i], k4x[i])
+            })
+            .collect()
+    }
+
+    // gravitational acceleration on each particle, if they were at `positions`
+    fn accelerations(&self, particles: &[Particle], positions: &[[f32; 2]]) -> Vec<[f32; 2]> {
+        match self.method {
+            SimulationMethod::Direct => positions
+                .iter()
+                .enumerate()
+                .map(|(i, &x)| {
+                    positions
+                        .iter()
+                        .zip(particles)
+                        .enumerate()
+                        .filter(|&(j, _)| j != i)
+                        .fold([0.0; 2], |a, (_, (&other, particle))| {
+                            add(a, self.pull(x, other, particle.mass))
+                        })
+                })
+                .collect(),
+            SimulationMethod::BarnesHut { theta } => {
+                let masses = particles.iter().map(|p| p.mass).collect::<Vec<_>>();
+                let tree = QuadTree::new(positions, &masses);
+
+                positions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &x)| {
+                        tree.acceleration(i, x, theta, |other, mass| self.pull(x, other, mass))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // the acceleration at x caused by a body of the given mass at other
+    fn pull(&self, x: [f32; 2], other: [f32; 2], mass: f32) -> [f32; 2] {
+        let r = sub(other, x);
+        // i.e. the force falls off with 1 / (r² + ε²)
+        let r2 = dot(r, r) + self.softening * self.softening;
+        if r2 == 0.0 {
+            // only possible with no softening at all
+            [0.0; 2]
+        } else {
+            scale(r, self.gravity * mass / (r2 * r2.sqrt()))
+        }
+    }
+}
+
+// one step of the plainest possible simulation (direct summation, f32, no
+// substeps), with no state carried over between calls. this is the reference
+// anything faster (e.g. Barnes-Hut, or a compute shader) should agree with,
+// and it doesn't need a GPU, so it also works headless.
+#[cfg(any(test, feature = "engine-api"))]
+pub fn step_cpu(
+    particles: &mut [Particle],
+    gravity: f32,
+    softening: f32,
+    dt: f32,
+    integrator: Integrator,
+) {
+    let mut simulation = Simulation::new();
+    simulation.set_gravity(gravity);
+    simulation.set_softening(softening);
+    simulation.set_integrator(integrator);
+
+    simulation.integrate_step(particles, dt);
+}
+
+fn positions(particles: &[Particle]) -> Vec<[f32; 2]> {
+    particles.iter().map(|p| p.position).collect()
+}
+
+fn to_f64(a: [f32; 2]) -> [f64; 2] {
+    [a[0].into(), a[1].into()]
+}
+
+fn to_f32(a: [f64; 2]) -> [f32; 2] {
+    [a[0] as f32, a[1] as f32]
+}
+
+fn add(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+fn sub(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn scale(a: [f32; 2], s: f32) -> [f32; 2] {
+    [a[0] * s, a[1] * s]
+}
+
+fn dot(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Pcg32;
+    use std::f32::consts::PI;
+
+    // two bodies of mass 1, a distance 1 apart, circling their center of
+    // mass (with gravity 1, this takes about 4.4 seconds per orbit)
+    fn circular_orbit() -> Vec<Particle> {
+        let speed = 0.5f32.sqrt();
+        vec![
+            Particle {
+                position: [-0.5, 0.0],
+                velocity: [0.0, -speed],
+                mass: 1.0,
+            },
+            Particle {
+                position: [0.5, 0.0],
+                velocity: [0.0, speed],
+                mass: 1.0,
+            },
+        ]
+    }
+
+    // how far the bodies' separation strays from 1 over about 20 orbits
+    fn orbit_drift(integrator: Integrator) -> f32 {
+        let mut particles = circular_orbit();
+        let mut drift = 0.0f32;
+
+        for _ in 0..10_000 {
+            step_cpu(&mut particles, 1.0, 0.0, 0.01, integrator);
+            let r = sub(particles[1].position, particles[0].position);
+            drift = drift.max((dot(r, r).sqrt() - 1.0).abs());
+        }
+
+        drift
+    }
+
+    #[test]
+    fn verlet_keeps_orbits_circular() {
+        let verlet = orbit_drift(Integrator::VelocityVerlet);
+        let euler = orbit_drift(Integrator::SymplecticEuler);
+
+        assert!(verlet < 0.001, "{}", verlet);
+        assert!(verlet < euler, "{} vs. {}", verlet, euler);
+    }
+
+    #[test]
+    fn particles_in_the_same_place_dont_blow_up() {
+        for &softening in &[Simulation::DEFAULT_SOFTENING, 0.0] {
+            let mut particles = vec![Particle::default(); 2];
+            step_cpu(&mut particles, 1.0, softening, 0.01, Integrator::default());
+
+            for particle in &particles {
+                assert!(particle.velocity.iter().all(|v| v.is_finite()));
+                assert!(particle.position.iter().all(|x| x.is_finite()));
+            }
+        }
+    }
+
+    // a blob of particles, denser in the middle like a galaxy
+    fn cloud(count: usize) -> Vec<Particle> {
+        let mut rng = Pcg32::new(1);
+        (0..count)
+            .map(|_| Particle {
+                position: [rng.next_gaussian() * 0.3, rng.next_gaussian() * 0.3],
+                mass: 0.5 + rng.next_f32(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    // how far off Barnes-Hut's accelerations are, relative to Direct's
+    fn barnes_hut_error(particles: &[Particle], theta: f32) -> f32 {
+        let mut simulation = Simulation::new();
+        let direct = simulation.accelerations(particles, &positions(particles));
+        simulation.set_method(SimulationMethod::BarnesHut { theta });
+        let approximate = simulation.accelerations(particles, &positions(particles));
+
+        let length = |a: [f32; 2]| dot(a, a).sqrt();
+        let error: f32 = direct
+            .iter()
+            .zip(&approximate)
+            .map(|(&a, &b)| length(sub(a, b)))
+            .sum();
+        error / direct.iter().map(|&a| length(a)).sum::<f32>()
+    }
+
+    #[test]
+    fn barnes_hut_is_close_to_direct() {
+        let particles = cloud(200);
+
+        let error = barnes_hut_error(&particles, 0.5);
+        assert!(error < 0.01, "{}", error);
+        // and lumping particles together more coarsely is less accurate
+        assert!(barnes_hut_error(&particles, 1.0) > error);
+    }
+
+    #[test]
+    fn barnes_hut_never_lumps_a_particle_in_with_itself() {
+        // the heavy particle is right next to the pair's center of mass, so
+        // with a big enough theta, it would be pulled toward (nearly) itself
+        let particles = [
+            Particle::default(),
+            Particle {
+                position: [1.0, 0.0],
+                mass: 1000.0,
+                ..Default::default()
+            },
+        ];
+
+        let error = barnes_hut_error(&particles, 2.0);
+        assert!(error < 1e-6, "{}", error);
+    }
+
+    #[test]
+    fn barnes_hut_with_no_lumping_matches_step_cpu() {
+        let mut reference = cloud(50);
+        step_cpu(&mut reference, 0.01, 0.01, 0.01, Integrator::VelocityVerlet);
+
+        let mut particles = cloud(50);
+        let mut simulation = Simulation::new();
+        simulation.set_method(SimulationMethod::BarnesHut { theta: 0.0 });
+        simulation.integrate_step(&mut particles, 0.01);
+
+        // (the forces are only added up in a different order)
+        let close = |a: [f32; 2], b: [f32; 2]| {
+            let difference = sub(a, b);
+            dot(difference, difference).sqrt() <= 1e-5 * dot(b, b).sqrt()
+        };
+        for (a, b) in particles.iter().zip(&reference) {
+            assert!(close(a.velocity, b.velocity), "{:?} vs. {:?}", a, b);
+            assert!(close(a.position, b.position), "{:?} vs. {:?}", a, b);
+        }
+    }
+
+    fn momentum(particles: &[Particle]) -> [f32; 2] {
+        particles.iter().fold([0.0; 2], |p, particle| {
+            add(p, scale(particle.velocity, particle.mass))
+        })
+    }
+
+    // kinetic plus (softened) potential, with gravity 1
+    fn energy(particles: &[Particle], softening: f32) -> f32 {
+        let mut energy = 0.0;
+        for (i, a) in particles.iter().enumerate() {
+            energy += 0.5 * a.mass * dot(a.velocity, a.velocity);
+            for b in &particles[i + 1..] {
+                let r = sub(a.position, b.position);
+                energy -= a.mass * b.mass / (dot(r, r) + softening * softening).sqrt();
+            }
+        }
+        energy
+    }
+
+    #[test]
+    fn three_bodies_in_a_triangle_orbit_as_one() {
+        // Lagrange's solution: three equal masses at the corners of an
+        // equilateral triangle (with sides of 1) spin around its center
+        // without changing its shape
+        let radius = 1.0 / 3.0f32.sqrt();
+        let speed = radius * 3.0f32.sqrt();
+        let mut particles: Vec<_> = (0..3)
+            .map(|i| {
+                let angle = i as f32 * 2.0 * PI / 3.0;
+                let (sin, cos) = angle.sin_cos();
+                Particle {
+                    position: [radius * cos, radius * sin],
+                    velocity: [-speed * sin, speed * cos],
+                    mass: 1.0,
+                }
+            })
+            .collect();
+        let start = particles.clone();
+        let energy_before = energy(&particles, 0.0);
+
+        // once around
+        let period = 2.0 * PI / 3.0f32.sqrt();
+        let steps = 1000;
+        for _ in 0..steps {
+            step_cpu(
+                &mut particles,
+                1.0,
+                0.0,
+                period / steps as f32,
+                Integrator::VelocityVerlet,
+            );
+        }
+
+        for (particle, start) in particles.iter().zip(&start) {
+            let offset = sub(particle.position, start.position);
+            assert!(dot(offset, offset).sqrt() < 0.01, "{:?}", particle);
+        }
+
+        let p = momentum(&particles);
+        assert!(dot(p, p).sqrt() < 1e-5, "{:?}", p);
+        let drift = (energy(&particles, 0.0) / energy_before - 1.0).abs();
+        assert!(drift < 1e-4, "{}", drift);
+    }
+}
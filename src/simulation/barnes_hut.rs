@@ -0,0 +1,175 @@
+use std::ops::Range;
+
+use super::{add, dot, scale, sub};
+
+// coincident particles can't be separated by subdividing, so give up at some
+// point and just treat whatever is left as one big leaf
+const MAX_DEPTH: u32 = 32;
+
+enum NodeKind {
+    // a range of QuadTree::order, i.e. the particles in this cell
+    Leaf(Range<usize>),
+    Branch(Vec<usize>),
+}
+
+struct Node {
+    mass: f32,
+    center_of_mass: [f32; 2],
+    // the cell's corner with the lowest coordinates
+    min: [f32; 2],
+    size: f32,
+    kind: NodeKind,
+}
+
+impl Node {
+    fn contains(&self, x: [f32; 2]) -> bool {
+        (0..2).all(|axis| x[axis] >= self.min[axis] && x[axis] <= self.min[axis] + self.size)
+    }
+}
+
+pub struct QuadTree<'a> {
+    positions: &'a [[f32; 2]],
+    masses: &'a [f32],
+    // particle indices, sorted so every node's particles are contiguous
+    order: Vec<usize>,
+    nodes: Vec<Node>,
+}
+
+impl<'a> QuadTree<'a> {
+    pub fn new(positions: &'a [[f32; 2]], masses: &'a [f32]) -> Self {
+        let mut tree = Self {
+            positions,
+            masses,
+            order: (0..positions.len()).collect(),
+            nodes: Vec::new(),
+        };
+
+        if !positions.is_empty() {
+            let (min, max) =
+                positions
+                    .iter()
+                    .fold((positions[0], positions[0]), |(min, max), p| {
+                        (
+                            [min[0].min(p[0]), min[1].min(p[1])],
+                            [max[0].max(p[0]), max[1].max(p[1])],
+                        )
+                    });
+
+            // the root has to be square, and can't be infinitely small
+            let size = (max[0] - min[0])
+                .max(max[1] - min[1])
+                .max(std::f32::EPSILON);
+            tree.build(0..positions.len(), min, size, 0);
+        }
+
+        tree
+    }
+
+    fn build(&mut self, range: Range<usize>, min: [f32; 2], size: f32, depth: u32) -> usize {
+        let positions = self.positions;
+        let masses = self.masses;
+
+        let (mass, weighted) =
+            self.order[range.clone()]
+                .iter()
+                .fold((0.0, [0.0; 2]), |(mass, weighted), &i| {
+                    (
+                        mass + masses[i],
+                        add(weighted, scale(positions[i], masses[i])),
+                    )
+                });
+
+        let center_of_mass = if mass > 0.0 {
+            scale(weighted, 1.0 / mass)
+        } else {
+            add(min, [size / 2.0; 2])
+        };
+
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            mass,
+            center_of_mass,
+            min,
+            size,
+            kind: NodeKind::Leaf(range.clone()),
+        });
+
+        if range.len() > 1 && depth < MAX_DEPTH {
+            let half = size / 2.0;
+            let mid = add(min, [half; 2]);
+
+            self.order[range.clone()].sort_unstable_by_key(|&i| quadrant(positions[i], mid));
+
+            let mut children = Vec::with_capacity(4);
+            let mut start = range.start;
+            for q in 0..4 {
+                let end = start
+                    + self.order[start..range.end]
+                        .iter()
+                        .take_while(|&&i| quadrant(positions[i], mid) == q)
+                        .count();
+
+                if end > start {
+                    let child_min = [
+                        if q & 1 != 0 { mid[0] } else { min[0] },
+                        if q & 2 != 0 { mid[1] } else { min[1] },
+                    ];
+                    children.push(self.build(start..end, child_min, half, depth + 1));
+                }
+
+                start = end;
+            }
+
+            self.nodes[index].kind = NodeKind::Branch(children);
+        }
+
+        index
+    }
+
+    // the acceleration on particle i (at x), where pull(position, mass) is the
+    // acceleration caused by a single (possibly aggregate) body. cells that
+    // look smaller than theta from x are treated as a single body, except for
+    // the ones x is in: those include particle i itself, which mustn't pull
+    // on itself (their center of mass can be further away than the cell is
+    // wide, so theta alone doesn't rule them out).
+    pub fn acceleration(
+        &self,
+        i: usize,
+        x: [f32; 2],
+        theta: f32,
+        pull: impl Fn([f32; 2], f32) -> [f32; 2],
+    ) -> [f32; 2] {
+        let mut accel = [0.0; 2];
+        let mut stack = if self.nodes.is_empty() {
+            Vec::new()
+        } else {
+            vec![0]
+        };
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+
+            match &node.kind {
+                NodeKind::Leaf(range) => {
+                    for &j in self.order[range.clone()].iter().filter(|&&j| j != i) {
+                        accel = add(accel, pull(self.positions[j], self.masses[j]));
+                    }
+                }
+                NodeKind::Branch(children) => {
+                    let r = sub(node.center_of_mass, x);
+                    if !node.contains(x) && node.size * node.size < theta * theta * dot(r, r) {
+                        accel = add(accel, pull(node.center_of_mass, node.mass));
+                    } else {
+                        stack.extend(children);
+                    }
+                }
+            }
+        }
+
+        accel
+    }
+}
+
+fn quadrant(position: [f32; 2], mid: [f32; 2]) -> u8 {
+    (position[0] >= mid[0]) as u8 | ((position[1] >= mid[1]) as u8) << 1
+}
@@ -1,8 +1,16 @@
+use lazy_static::lazy_static;
+use log::{Level, LevelFilter, Log, Metadata, Record};
 use std::{
-    panic,
-    sync::atomic::{AtomicBool, Ordering},
+    any::{Any, TypeId},
+    env,
+    io::{self, Write},
+    panic::{self, PanicInfo},
+    sync::{Mutex, MutexGuard},
+    thread,
 };
+#[cfg(feature = "graphics")]
 use vulkano::swapchain::Capabilities;
+#[cfg(feature = "graphics")]
 use winit::dpi::PhysicalSize;
 
 pub fn clamp<T: PartialOrd>(num: T, min: T, max: T) -> T {
@@ -63,25 +71,146 @@ pub fn prefer_fn<'a, T: 'a>(
     }
 }
 
-static SETUP_HOOK: AtomicBool = AtomicBool::new(false);
+type PanicHook = Box<dyn Fn(&PanicInfo) + Send + Sync + 'static>;
+
+// panic payload types which shouldn't be reported by the panic hook, along
+// with the hook that was installed before any of them were registered
+struct PanicHookRegistry {
+    original: Option<PanicHook>,
+    sentinels: Vec<(TypeId, fn(&(dyn Any + Send)) -> bool)>,
+}
+
+lazy_static! {
+    static ref PANIC_HOOKS: Mutex<PanicHookRegistry> = Mutex::new(PanicHookRegistry {
+        original: None,
+        sentinels: Vec::new(),
+    });
+}
+
+fn lock_panic_hooks() -> MutexGuard<'static, PanicHookRegistry> {
+    // the registry is never left in an inconsistent state, so even if someone
+    // panicked while holding the lock it's still safe to use
+    PANIC_HOOKS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn is_sentinel<T: Any>(payload: &(dyn Any + Send)) -> bool {
+    payload.is::<T>()
+}
+
+fn chained_panic_hook(panic_info: &PanicInfo) {
+    let registry = lock_panic_hooks();
+
+    if !registry
+        .sentinels
+        .iter()
+        .any(|(_, is_sentinel)| is_sentinel(panic_info.payload()))
+    {
+        if let Some(original) = &registry.original {
+            original(panic_info);
+        }
+    }
+}
+
+// keeps panics with a payload of type T quiet for as long as it's alive. the
+// original hook is put back once every PanicHookGuard has been dropped.
+#[must_use]
+pub struct PanicHookGuard(TypeId);
+
+impl PanicHookGuard {
+    pub fn new<T: Any>() -> Self {
+        let mut registry = lock_panic_hooks();
+
+        if registry.sentinels.is_empty() {
+            registry.original = Some(panic::take_hook());
+            panic::set_hook(Box::new(chained_panic_hook));
+        }
+
+        registry
+            .sentinels
+            .push((TypeId::of::<T>(), is_sentinel::<T>));
+
+        PanicHookGuard(TypeId::of::<T>())
+    }
+}
+
+impl Drop for PanicHookGuard {
+    fn drop(&mut self) {
+        let mut registry = lock_panic_hooks();
+
+        if let Some(index) = registry.sentinels.iter().position(|(t, _)| *t == self.0) {
+            registry.sentinels.swap_remove(index);
+        }
+
+        // set_hook() panics if called while panicking, in which case leave
+        // our hook in place; it will still forward to the original
+        if registry.sentinels.is_empty() && !thread::panicking() {
+            if let Some(original) = registry.original.take() {
+                panic::set_hook(original);
+            }
+        }
+    }
+}
 
 pub struct IntentionalPanic;
 
 impl IntentionalPanic {
-    pub fn setup_hook() {
-        if !SETUP_HOOK.load(Ordering::Acquire) {
-            let original_hook = panic::take_hook();
-            panic::set_hook(Box::new(move |panic_info| {
-                if panic_info.payload().downcast_ref::<Self>().is_none() {
-                    original_hook(panic_info);
-                }
-            }));
-
-            SETUP_HOOK.store(true, Ordering::Release);
+    pub fn setup_hook() -> PanicHookGuard {
+        PanicHookGuard::new::<Self>()
+    }
+}
+
+// everything at this level or more important gets printed unless the
+// PLANETS_LOG environment variable (e.g. PLANETS_LOG=trace) says otherwise
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+const LOG_LEVEL_ENV_VAR: &str = "PLANETS_LOG";
+
+// prints log records to stderr, which is where warnings always went
+struct StderrLogger {
+    level: LevelFilter,
+}
+
+impl StderrLogger {
+    fn write_to(&self, out: &mut dyn Write, record: &Record) -> io::Result<()> {
+        if !self.enabled(record.metadata()) {
+            return Ok(());
         }
+
+        let level = match record.level() {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        writeln!(out, "{}: {}", level, record.args())
     }
 }
 
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        let _ = self.write_to(&mut io::stderr(), record);
+    }
+
+    fn flush(&self) {}
+}
+
+// does nothing if a logger was already set up
+pub fn init_logger() {
+    let level = env::var(LOG_LEVEL_ENV_VAR)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_LOG_LEVEL);
+
+    if log::set_boxed_logger(Box::new(StderrLogger { level })).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+#[cfg(feature = "graphics")]
 pub fn clamp_window_size(dims: PhysicalSize, caps: &Capabilities) -> PhysicalSize {
     let Capabilities {
         min_image_extent: min,
@@ -96,16 +225,19 @@ pub fn clamp_window_size(dims: PhysicalSize, caps: &Capabilities) -> PhysicalSiz
         .into()
 }
 
+#[cfg(feature = "graphics")]
 pub trait ToExtents<T> {
     fn to_extents(self) -> [T; 2];
 }
 
+#[cfg(feature = "graphics")]
 impl<T> ToExtents<T> for [T; 2] {
     fn to_extents(self) -> [T; 2] {
         self
     }
 }
 
+#[cfg(feature = "graphics")]
 impl ToExtents<u32> for PhysicalSize {
     fn to_extents(self) -> [u32; 2] {
         let x: (u32, u32) = self.into();
@@ -113,6 +245,7 @@ impl ToExtents<u32> for PhysicalSize {
     }
 }
 
+#[cfg(feature = "graphics")]
 impl ToExtents<f64> for PhysicalSize {
     fn to_extents(self) -> [f64; 2] {
         let x: (f64, f64) = self.into();
@@ -120,9 +253,92 @@ impl ToExtents<f64> for PhysicalSize {
     }
 }
 
+#[cfg(feature = "graphics")]
 impl ToExtents<f32> for PhysicalSize {
     fn to_extents(self) -> [f32; 2] {
         let x: (f64, f64) = self.into();
         [x.0 as f32, x.1 as f32]
     }
 }
+
+// a tiny PCG32 (see pcg-random.org), so seeded simulations come out the same
+// no matter which version of which RNG crate happens to be in Cargo.lock
+pub struct Pcg32 {
+    state: u64,
+    increment: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+    const DEFAULT_STREAM: u64 = 0xda3e_39cb_94b9_5bdb;
+
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            increment: (Self::DEFAULT_STREAM << 1) | 1,
+        };
+
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(self.increment);
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    // uniformly distributed in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    // normally distributed with mean 0 and standard deviation 1 (Box-Muller)
+    pub fn next_gaussian(&mut self) -> f32 {
+        let u1 = 1.0 - self.next_f32(); // (0, 1], so ln() can't blow up
+        let u2 = self.next_f32();
+
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logged(level: Level) -> String {
+        let logger = StderrLogger {
+            level: DEFAULT_LOG_LEVEL,
+        };
+        let mut out = Vec::new();
+        logger
+            .write_to(
+                &mut out,
+                &Record::builder()
+                    .level(level)
+                    .target("planets::window")
+                    .args(format_args!("{:?}", "Focused(true)"))
+                    .build(),
+            )
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn traced_events_are_quiet_by_default() {
+        assert_eq!(logged(Level::Trace), "");
+        assert_eq!(logged(Level::Debug), "");
+    }
+
+    #[test]
+    fn warnings_are_printed_by_default() {
+        assert_eq!(logged(Level::Warn), "warning: \"Focused(true)\"\n");
+    }
+}
@@ -3,54 +3,167 @@ use vulkano::{instance::Instance, swapchain::Surface};
 use vulkano_win::VkSurfaceBuild;
 use winit::{
     self,
-    dpi::PhysicalSize,
+    dpi::{LogicalSize, PhysicalSize},
     event::{
         DeviceEvent, ElementState,
         Event::{self, EventsCleared, NewEvents, UserEvent},
-        KeyboardInput, WindowEvent,
+        KeyboardInput, StartCause, WindowEvent,
     },
     event_loop::{ControlFlow, EventLoop, EventLoopProxy, EventLoopWindowTarget},
     window::{Window as WinitWindow, WindowBuilder},
 };
 
 use std::{
+    mem,
     num::NonZeroU32,
     panic,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        mpsc, Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, Condvar, Mutex,
     },
     thread,
+    time::{Duration, Instant},
 };
 
 mod input;
 
-pub use input::InputID;
-use input::KeyState;
+#[cfg(feature = "engine-api")]
+pub use input::{AxisBinding, DeadzoneMode, InputContext, KeyEvent};
+pub use input::{InputID, InputSet, Trigger};
+use input::{KeyEvents, KeyState};
 
-use crate::{get_app_info, util::IntentionalPanic, DEFAULT_WINDOW_SIZE};
+use crate::{
+    engine::EngineConfig,
+    util::{IntentionalPanic, PanicHookGuard},
+};
+
+// how the event loop should wait for new events. waiting saves power when
+// nothing on screen is changing; polling keeps animations going as fast as
+// possible; waiting with a deadline is somewhere in between (a frame limiter)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EventLoopPolicy {
+    Wait,
+    Poll,
+    WaitUntil(Duration),
+}
+
+impl Default for EventLoopPolicy {
+    fn default() -> Self {
+        EventLoopPolicy::Wait
+    }
+}
+
+// sent from Window handles to the event loop, since some platforms only let
+// the event loop's thread change the window
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum WindowCommand {
+    Close,
+    SetVisible(bool),
+    // wakes the event loop up to draw a frame (see Window::draw_on_event_loop)
+    Draw,
+}
+
+// with RenderThread::Main, the app thread asks the event loop for frames
+// instead of drawing them itself
+#[derive(Default)]
+struct FrameRequests {
+    requested: bool,
+    drawn: u64,
+}
 
 pub struct WindowEvents {
-    dpi_factor: AtomicCell<f64>,
+    policy: AtomicCell<EventLoopPolicy>,
+    deadline: AtomicCell<Option<Instant>>,
+    idle_nanos: AtomicU64,
+    // what winit says, which is what its logical sizes are in. only the DPI
+    // factor the app sees is clamped; the window's physical size is what it
+    // is, so converting to it has to use the same factor winit does
+    window_dpi_factor: AtomicCell<f64>,
+    dpi_factor_range: Option<(f64, f64)>,
+    scale_changed: AtomicCell<Option<f64>>,
     resize_to: AtomicCell<Option<(NonZeroU32, NonZeroU32)>>,
+    // stored as f64 bits because AtomicCell::compare_exchange() requires Eq
+    mouse_motion: AtomicCell<(u64, u64)>,
     key_state: KeyState,
+    key_events: Mutex<KeyEvents>,
+    frames: Mutex<FrameRequests>,
+    frame_drawn: Condvar,
     closed: AtomicBool,
+    _panic_hook: PanicHookGuard,
 }
 
 impl WindowEvents {
-    fn new() -> Self {
-        IntentionalPanic::setup_hook();
-
+    fn new(config: &EngineConfig, window_dpi_factor: f64) -> Self {
         Self {
-            dpi_factor: AtomicCell::new(1.0),
+            policy: AtomicCell::new(config.event_loop_policy.unwrap_or_default()),
+            deadline: AtomicCell::new(None),
+            idle_nanos: AtomicU64::new(0),
+            window_dpi_factor: AtomicCell::new(window_dpi_factor),
+            dpi_factor_range: config.dpi_factor_range,
+            scale_changed: AtomicCell::new(None),
             resize_to: AtomicCell::new(None),
+            mouse_motion: AtomicCell::new((0, 0)),
             key_state: KeyState::new(),
+            key_events: Mutex::new(KeyEvents::new()),
+            frames: Mutex::new(FrameRequests::default()),
+            frame_drawn: Condvar::new(),
             closed: AtomicBool::new(false),
+            _panic_hook: IntentionalPanic::setup_hook(),
         }
     }
 
+    pub fn event_loop_policy(&self) -> EventLoopPolicy {
+        self.policy.load()
+    }
+
+    // takes effect once the event loop wakes up next
+    #[cfg(feature = "engine-api")]
+    pub fn set_event_loop_policy(&self, policy: EventLoopPolicy) {
+        self.policy.store(policy);
+        self.deadline.store(None);
+    }
+
+    // how long the event loop spent waiting for events since the last call to
+    // this function. lots of idle time means there's room for higher quality.
+    pub fn take_idle_time(&self) -> Duration {
+        Duration::from_nanos(self.idle_nanos.swap(0, Ordering::AcqRel))
+    }
+
+    fn control_flow(&self) -> ControlFlow {
+        match self.policy.load() {
+            EventLoopPolicy::Wait => ControlFlow::Wait,
+            EventLoopPolicy::Poll => ControlFlow::Poll,
+            EventLoopPolicy::WaitUntil(interval) => {
+                // keep the same deadline until it passes, otherwise every
+                // event would push it further into the future
+                let now = Instant::now();
+                let deadline = match self.deadline.load() {
+                    Some(deadline) if deadline > now => deadline,
+                    _ => now + interval,
+                };
+                self.deadline.store(Some(deadline));
+
+                ControlFlow::WaitUntil(deadline)
+            }
+        }
+    }
+
+    // kept within EngineConfig::dpi_factor_range, if set
+    #[cfg(feature = "engine-api")]
     pub fn dpi_factor(&self) -> f64 {
-        self.dpi_factor.load()
+        self.clamp_dpi_factor(self.window_dpi_factor.load())
+    }
+
+    fn clamp_dpi_factor(&self, dpi_factor: f64) -> f64 {
+        match self.dpi_factor_range {
+            Some((min, max)) => dpi_factor.max(min).min(max),
+            None => dpi_factor,
+        }
+    }
+
+    // the new DPI factor, if it has changed since the last call to this function
+    pub fn take_scale_change(&self) -> Option<f64> {
+        self.scale_changed.swap(None)
     }
 
     pub fn resize_to(&self) -> Option<PhysicalSize> {
@@ -59,17 +172,82 @@ impl WindowEvents {
             .map(|s| (s.0.get(), s.1.get()).into())
     }
 
+    // raw, unaccelerated mouse movement since the last call to this function
+    #[cfg(feature = "engine-api")]
+    pub fn take_mouse_motion(&self) -> (f64, f64) {
+        let (x, y) = self.mouse_motion.swap((0, 0));
+        (f64::from_bits(x), f64::from_bits(y))
+    }
+
+    fn add_mouse_motion(&self, (dx, dy): (f64, f64)) {
+        let mut current = self.mouse_motion.load();
+        loop {
+            let new = (
+                (f64::from_bits(current.0) + dx).to_bits(),
+                (f64::from_bits(current.1) + dy).to_bits(),
+            );
+
+            match self.mouse_motion.compare_exchange(current, new) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
     pub fn key_state(&self) -> &KeyState {
         &self.key_state
     }
 
+    // every key press and release since the last call to this function,
+    // oldest first. unlike key_state(), this sees keys that aren't bound to
+    // anything, and presses that were let go of again before the next update
+    #[cfg(feature = "engine-api")]
+    pub fn take_key_events(&self) -> Vec<KeyEvent> {
+        self.key_events.lock().unwrap().take()
+    }
+
     pub fn closed(&self) -> bool {
         self.closed.load(Ordering::Acquire)
     }
 
-    fn callback(&self, event: Event<()>, _wt: &EventLoopWindowTarget<()>, cf: &mut ControlFlow) {
+    // returns how many frames had been drawn before this one
+    fn request_frame(&self) -> u64 {
+        let mut frames = self.frames.lock().unwrap();
+        frames.requested = true;
+        frames.drawn
+    }
+
+    // blocks until the event loop has drawn another frame since `drawn`, or
+    // the window has closed (and so won't draw any more)
+    fn wait_for_frame(&self, drawn: u64) {
+        let mut frames = self.frames.lock().unwrap();
+        while frames.drawn == drawn && !self.closed() {
+            frames = self.frame_drawn.wait(frames).unwrap();
+        }
+    }
+
+    fn take_frame_request(&self) -> bool {
+        let mut frames = self.frames.lock().unwrap();
+        mem::replace(&mut frames.requested, false)
+    }
+
+    fn finish_frame(&self) {
+        self.frames.lock().unwrap().drawn += 1;
+        self.frame_drawn.notify_all();
+    }
+
+    fn callback(
+        &self,
+        window: &WinitWindow,
+        event: Event<WindowCommand>,
+        _wt: &EventLoopWindowTarget<WindowCommand>,
+        cf: &mut ControlFlow,
+    ) {
         match event {
-            UserEvent(())
+            UserEvent(WindowCommand::SetVisible(visible)) => window.set_visible(visible),
+            // the frame itself is drawn once the events are cleared
+            UserEvent(WindowCommand::Draw) => {}
+            UserEvent(WindowCommand::Close)
             | Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
@@ -81,16 +259,35 @@ impl WindowEvents {
             Event::WindowEvent {
                 event: WindowEvent::HiDpiFactorChanged(dpi_factor),
                 ..
-            } => self.dpi_factor.store(dpi_factor),
+            } => {
+                let old_dpi_factor = self.window_dpi_factor.swap(dpi_factor);
+                let clamped = self.clamp_dpi_factor(dpi_factor);
+
+                // winit keeps the window's logical size (in its own, unclamped
+                // units) the same across the change. like in build(), resize
+                // it so its size in clamped units stays the same instead
+                let scale =
+                    old_dpi_factor / self.clamp_dpi_factor(old_dpi_factor) * clamped / dpi_factor;
+                if scale != 1.0 {
+                    let size = window.inner_size();
+                    window
+                        .set_inner_size(LogicalSize::new(size.width * scale, size.height * scale));
+                }
+
+                self.scale_changed.store(Some(clamped));
+            }
             Event::WindowEvent {
                 event: WindowEvent::Resized(size),
                 ..
             } => {
-                let physical: (u32, u32) = size.to_physical(self.dpi_factor.load()).into();
-                self.resize_to.store(Some((
-                    NonZeroU32::new(physical.0).unwrap(),
-                    NonZeroU32::new(physical.1).unwrap(),
-                )));
+                let physical: (u32, u32) = size.to_physical(self.window_dpi_factor.load()).into();
+                // some platforms resize to 0x0 on minimize. there's nothing
+                // to draw at that size, so keep the last real one instead
+                if let (Some(width), Some(height)) =
+                    (NonZeroU32::new(physical.0), NonZeroU32::new(physical.1))
+                {
+                    self.resize_to.store(Some((width, height)));
+                }
             }
             Event::WindowEvent {
                 event:
@@ -102,10 +299,25 @@ impl WindowEvents {
                         ..
                     },
                 ..
-            } => match state {
-                ElementState::Pressed => self.key_state.set(InputID::Key(scancode).into(), true),
-                ElementState::Released => self.key_state.set(InputID::Key(scancode).into(), false),
-            },
+            } => {
+                let pressed = state == ElementState::Pressed;
+                let event = self.key_events.lock().unwrap().record(scancode, pressed);
+                // a repeat would only set a bit that's already set
+                if !event.was_repeat {
+                    self.key_state.set(InputID::Key(scancode).into(), pressed);
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Focused(false),
+                ..
+            } => {
+                // otherwise their bindings would stay down until pressed and
+                // let go of again
+                let released = self.key_events.lock().unwrap().forget_held();
+                for scancode in released {
+                    self.key_state.set(InputID::Key(scancode).into(), false);
+                }
+            }
             Event::DeviceEvent {
                 device_id,
                 event: DeviceEvent::Button { button, state },
@@ -113,51 +325,134 @@ impl WindowEvents {
                 ElementState::Pressed => self.key_state.set(InputID::Button(button).into(), true),
                 ElementState::Released => self.key_state.set(InputID::Button(button).into(), false),
             },
+            Event::DeviceEvent {
+                event: DeviceEvent::Motion { axis, value },
+                ..
+            } => self
+                .key_state
+                .set_axis(InputID::Axis(axis).into(), value as f32),
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => self.add_mouse_motion(delta),
             EventsCleared => {}
+            NewEvents(StartCause::WaitCancelled { start, .. })
+            | NewEvents(StartCause::ResumeTimeReached { start, .. }) => {
+                let idle = Instant::now().saturating_duration_since(start);
+                self.idle_nanos
+                    .fetch_add(idle.as_nanos() as u64, Ordering::AcqRel);
+            }
             NewEvents(_) => {}
-            //e => { dbg!(e); }
-            _ => {}
+            // every event the window doesn't otherwise handle
+            // (PLANETS_LOG=trace to see them)
+            e => log::trace!("{:?}", e),
         }
 
         if self.closed() {
+            // (taking the lock first, so a wait_for_frame() that hasn't
+            // started waiting yet can't miss this)
+            drop(self.frames.lock().unwrap());
+            self.frame_drawn.notify_all();
             panic!(IntentionalPanic);
         }
 
-        *cf = ControlFlow::Wait;
+        *cf = self.control_flow();
     }
 }
 
 pub struct WindowThread {
     events: Arc<WindowEvents>,
-    event_loop: EventLoop<()>,
+    surface: Arc<Surface<WinitWindow>>,
+    event_loop: EventLoop<WindowCommand>,
+}
+
+// which thread the renderer should live on. the event loop always runs on the
+// thread that called WindowThread::with*(), which should be the main thread:
+//
+//   platform | event loop  | rendering
+//   ---------+-------------+-----------------------------------
+//   Windows  | main thread | any thread
+//   Linux    | main thread | any thread
+//   macOS    | main thread | main thread (AppKit is picky)
+//   iOS      | main thread | main thread
+//
+// where rendering can happen anywhere, it's nicer to keep it off the event
+// loop so slow frames don't delay input handling (and vice versa).
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum RenderThread {
+    Main,
+    Background,
+}
+
+impl Default for RenderThread {
+    fn default() -> Self {
+        if cfg!(any(target_os = "macos", target_os = "ios")) {
+            RenderThread::Main
+        } else {
+            RenderThread::Background
+        }
+    }
 }
 
 impl WindowThread {
     // some platforms such as iOS have a restriction where only the main thread can manipulate or
     // query the window, which is why this function would be needed instead of Window::spawn().
     // this function could potentially never return if panic=abort; i.e. if catch_unwind won't work
-    pub fn with<F: FnOnce(Window) + Send + 'static>(instance: Arc<Instance>, f: F) {
+    pub fn with<F: FnOnce(Window) + Send + 'static>(
+        instance: Arc<Instance>,
+        config: &EngineConfig,
+        f: F,
+    ) {
         let (sender, receiver) = mpsc::sync_channel(1);
+        let config = config.clone();
 
         thread::spawn(move || f(receiver.recv().unwrap()));
 
-        if let Err(e) = panic::catch_unwind(move || {
-            let (window, controller) = Self::new(instance);
+        Self::catch_intentional_panic(move || {
+            let (window, controller) = Self::new(instance, &config);
             sender.send(controller).unwrap();
 
             window.run();
-        }) {
+        });
+    }
+
+    // like with(), but render() is called on this thread (i.e. the event loop's thread) with a
+    // window handle that lives forever, and the closure it returns is called whenever the event
+    // loop runs out of events to process, if f() asked for a frame since the last one (see
+    // Window::draw_on_event_loop). f() still runs on a background thread.
+    pub fn with_render<F, R, D>(instance: Arc<Instance>, config: &EngineConfig, render: R, f: F)
+    where
+        F: FnOnce(Window) + Send + 'static,
+        R: FnOnce(&'static Window) -> D,
+        D: FnMut() + 'static,
+    {
+        let config = config.clone();
+
+        Self::catch_intentional_panic(move || {
+            let (window, controller) = Self::new(instance, &config);
+
+            // the event loop never returns, so there's no point in ever dropping this
+            let render_window: &'static Window = Box::leak(Box::new(controller.share()));
+            thread::spawn(move || f(controller));
+
+            window.run_with(render(render_window));
+        });
+    }
+
+    fn catch_intentional_panic<F: FnOnce()>(f: F) {
+        if let Err(e) = panic::catch_unwind(panic::AssertUnwindSafe(f)) {
             if e.downcast_ref::<IntentionalPanic>().is_none() {
                 panic!(e);
             }
         }
     }
 
-    pub fn spawn(instance: Arc<Instance>) -> Window {
+    pub fn spawn(instance: Arc<Instance>, config: &EngineConfig) -> Window {
         let (sender, receiver) = mpsc::sync_channel(1);
+        let config = config.clone();
 
         thread::spawn(move || {
-            let (window, controller) = Self::new(instance);
+            let (window, controller) = Self::new(instance, &config);
 
             sender.send(controller).unwrap();
 
@@ -167,22 +462,23 @@ impl WindowThread {
         receiver.recv().unwrap()
     }
 
-    fn new(instance: Arc<Instance>) -> (Self, Window) {
-        let event_loop = EventLoop::new();
-        let closed = event_loop.create_proxy();
+    fn new(instance: Arc<Instance>, config: &EngineConfig) -> (Self, Window) {
+        let event_loop = EventLoop::with_user_event();
+        let commands = event_loop.create_proxy();
 
-        let surface = Self::build(&event_loop, instance.clone());
+        let surface = Self::build(&event_loop, instance.clone(), config);
 
-        let events = Arc::new(WindowEvents::new());
+        let events = Arc::new(WindowEvents::new(config, surface.window().hidpi_factor()));
 
         let window = Self {
             events: events.clone(),
+            surface: surface.clone(),
             event_loop,
         };
 
         let controller = Window {
             surface,
-            closed,
+            commands,
             events,
             instance,
         };
@@ -190,30 +486,76 @@ impl WindowThread {
         (window, controller)
     }
 
-    fn build(event_loop: &EventLoop<()>, instance: Arc<Instance>) -> Arc<Surface<WinitWindow>> {
-        let mut window = WindowBuilder::new();
+    fn build(
+        event_loop: &EventLoop<WindowCommand>,
+        instance: Arc<Instance>,
+        config: &EngineConfig,
+    ) -> Arc<Surface<WinitWindow>> {
+        let mut window = WindowBuilder::new()
+            .with_visible(config.window_visible)
+            .with_decorations(config.window_decorations);
 
-        if let Some(size) = DEFAULT_WINDOW_SIZE {
+        if let Some(size) = config.window_size {
             window = window.with_inner_size(size);
         }
 
-        if let Some(name) = get_app_info().application_name {
-            window = window.with_title(name);
+        if let Some(title) = config.window_title.as_ref().or(config.app_name.as_ref()) {
+            window = window.with_title(title.clone());
         }
 
-        window.build_vk_surface(event_loop, instance).unwrap()
+        let surface = window.build_vk_surface(event_loop, instance).unwrap();
+
+        // the window was made window_size * (winit's factor) pixels big, which
+        // is exactly what's wrong when that factor is. resize it to what the
+        // clamped factor would've made it instead
+        if let (Some(size), Some((min, max))) = (config.window_size, config.dpi_factor_range) {
+            let window = surface.window();
+            let dpi_factor = window.hidpi_factor();
+            let clamped = dpi_factor.max(min).min(max);
+
+            if clamped != dpi_factor {
+                let scale = clamped / dpi_factor;
+                window.set_inner_size(LogicalSize::new(size.width * scale, size.height * scale));
+            }
+        }
+
+        surface
     }
 
     fn run(self) -> ! {
-        let Self { event_loop, events } = self;
+        let Self {
+            event_loop,
+            events,
+            surface,
+        } = self;
+
+        event_loop.run(move |ev, wt, cf| events.callback(surface.window(), ev, wt, cf));
+    }
+
+    fn run_with<D: FnMut() + 'static>(self, mut draw: D) -> ! {
+        let Self {
+            event_loop,
+            events,
+            surface,
+        } = self;
+
+        event_loop.run(move |ev, wt, cf| {
+            let idle = matches!(ev, EventsCleared);
 
-        event_loop.run(move |ev, wt, cf| events.callback(ev, wt, cf));
+            events.callback(surface.window(), ev, wt, cf);
+
+            if idle && events.take_frame_request() {
+                events.key_state.update();
+                draw();
+                events.finish_frame();
+            }
+        });
     }
 }
 
 pub struct Window {
     surface: Arc<Surface<WinitWindow>>,
-    closed: EventLoopProxy<()>,
+    commands: EventLoopProxy<WindowCommand>,
     events: Arc<WindowEvents>,
     instance: Arc<Instance>,
 }
@@ -241,17 +583,95 @@ impl Window {
         } else {
             self.window()
                 .inner_size()
-                .to_physical(self.events.dpi_factor())
+                .to_physical(self.events.window_dpi_factor.load())
         }
     }
 
+    // the refresh rate (in Hz) of the monitor the window is on, if known.
+    // winit only lists the modes a monitor supports, not which one is active,
+    // so this is the fastest one at the monitor's current resolution.
+    pub fn refresh_rate(&self) -> Option<u32> {
+        let monitor = self.window().current_monitor();
+        let size = monitor.size();
+
+        monitor
+            .video_modes()
+            .filter(|mode| mode.size() == size)
+            .map(|mode| u32::from(mode.refresh_rate()))
+            .filter(|&rate| rate > 0)
+            .max()
+    }
+
+    pub fn key_state(&self) -> &KeyState {
+        &self.events.key_state
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn take_key_events(&self) -> Vec<KeyEvent> {
+        self.events.take_key_events()
+    }
+
+    // for windows created hidden (see EngineConfig::window_visible). does
+    // nothing if the window is already visible.
+    pub fn show(&self) {
+        let _ = self.commands.send_event(WindowCommand::SetVisible(true));
+    }
+
+    // starts a new frame as far as input goes: pressed() and released() are
+    // only true until the next call. the engine calls this once per frame
+    // (see EngineBuilder::run), right before drawing it.
     pub fn update(&self) {
         self.events.key_state.update();
     }
+
+    // for RenderThread::Main: has the event loop draw a frame (and update()
+    // before it), and waits for that to finish
+    pub fn draw_on_event_loop(&self) {
+        let drawn = self.events.request_frame();
+        // (only after asking, or the event loop might wake up too early)
+        let _ = self.commands.send_event(WindowCommand::Draw);
+        self.events.wait_for_frame(drawn);
+    }
+
+    // NOTE: dropping *any* handle closes the window, so be careful with these
+    fn share(&self) -> Self {
+        Self {
+            surface: self.surface.clone(),
+            commands: self.commands.clone(),
+            events: self.events.clone(),
+            instance: self.instance.clone(),
+        }
+    }
 }
 
 impl Drop for Window {
     fn drop(&mut self) {
-        let _ = self.closed.send_event(());
+        let _ = self.commands.send_event(WindowCommand::Close);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_drawn_once_per_request() {
+        let events = Arc::new(WindowEvents::new(&EngineConfig::default(), 1.0));
+        assert!(!events.take_frame_request());
+
+        let drawn = events.request_frame();
+        let app = {
+            let events = events.clone();
+            thread::spawn(move || events.wait_for_frame(drawn))
+        };
+
+        assert!(events.take_frame_request());
+        assert!(!events.take_frame_request());
+        events.finish_frame();
+        app.join().unwrap();
+
+        // a frame that was already drawn doesn't count for the next request
+        let drawn = events.request_frame();
+        assert_eq!(drawn, 1);
     }
 }
@@ -8,9 +8,10 @@
 use arr_macro::arr;
 use crossbeam_utils::atomic::AtomicCell;
 use hashed::Hashed32;
-use winit::event::{ButtonId, DeviceEvent, DeviceId, ScanCode};
+use winit::event::{AxisId, ButtonId, DeviceEvent, DeviceId, ScanCode};
 
 use std::{
+    collections::{HashSet, VecDeque},
     convert::{TryFrom, TryInto},
     sync::{
         atomic::{AtomicU64, Ordering},
@@ -18,11 +19,13 @@ use std::{
     },
 };
 
-// TODO: all of this only handles binary inputs
+// NOTE: analog inputs (axes) share the binding slots with binary inputs; the
+// only difference is which half of KeyState their values are stored in.
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum InputID {
     None,
+    Axis(AxisId),
     Button(ButtonId),
     Key(ScanCode),
 }
@@ -38,6 +41,7 @@ impl TryFrom<DeviceEvent> for InputID {
 
     fn try_from(evt: DeviceEvent) -> Result<Self, Self::Error> {
         match evt {
+            DeviceEvent::Motion { axis, .. } => Ok(Self::Axis(axis)),
             DeviceEvent::Button { button, .. } => Ok(Self::Button(button)),
             DeviceEvent::Key(kb_input) => Ok(Self::Key(kb_input.scancode)),
             _ => Err(()),
@@ -51,6 +55,13 @@ pub struct Input {
     device: Hashed32<Option<DeviceId>>,
 }
 
+impl Input {
+    #[cfg(feature = "engine-api")]
+    pub fn input_id(&self) -> InputID {
+        self.input_id
+    }
+}
+
 impl PartialEq for Input {
     fn eq(&self, other: &Self) -> bool {
         let any_device = Default::default();
@@ -71,6 +82,38 @@ impl Into<Input> for InputID {
     }
 }
 
+// bindings in a context only respond while that context is active, so e.g. the
+// menu's "confirm" key doesn't also do something in-game. bindings in the
+// global context always respond.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InputContext(pub u8);
+
+impl InputContext {
+    pub const GLOBAL: Self = InputContext(0);
+}
+
+impl Default for InputContext {
+    fn default() -> Self {
+        Self::GLOBAL
+    }
+}
+
+// which part of a button press an action happens on. most actions should
+// happen on Press so they feel responsive; Release is for things that can be
+// cancelled by sliding off (like UI buttons).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    Press,
+    Hold,
+    Release,
+}
+
+impl Default for Trigger {
+    fn default() -> Self {
+        Trigger::Press
+    }
+}
+
 pub struct InputBinding<'a> {
     state: &'a KeyState,
     input: Input,
@@ -79,7 +122,11 @@ pub struct InputBinding<'a> {
 
 impl<'a> InputBinding<'a> {
     pub fn new(state: &'a KeyState, input: Input) -> Self {
-        let index = state.add(input);
+        Self::new_in(state, InputContext::GLOBAL, input)
+    }
+
+    pub fn new_in(state: &'a KeyState, context: InputContext, input: Input) -> Self {
+        let index = state.add(input, context);
 
         Self {
             state,
@@ -88,18 +135,36 @@ impl<'a> InputBinding<'a> {
         }
     }
 
+    // true only on the update() where the input went down
     pub fn pressed(&self) -> bool {
         self.state.pressed(self.index)
     }
 
+    // true on every update() while the input is held
     pub fn down(&self) -> bool {
         self.state.down(self.index)
     }
 
+    // true only on the update() where the input came back up, so e.g. holding
+    // a key does nothing until it's let go
     pub fn released(&self) -> bool {
         self.state.released(self.index)
     }
 
+    pub fn triggered(&self, trigger: Trigger) -> bool {
+        match trigger {
+            Trigger::Press => self.pressed(),
+            Trigger::Hold => self.down(),
+            Trigger::Release => self.released(),
+        }
+    }
+
+    // the raw value of an analog input, or 0.0 if it hasn't moved (yet)
+    #[cfg(any(test, feature = "engine-api"))]
+    pub fn value(&self) -> f32 {
+        self.state.value(self.index)
+    }
+
     pub fn name() -> String {
         unimplemented!()
     }
@@ -111,8 +176,239 @@ impl<'a> Drop for InputBinding<'a> {
     }
 }
 
+// several inputs acting as one (e.g. both A and the left arrow key meaning
+// "move left"). it's down while any of them is, pressed when the first one
+// goes down, and released only once the last one comes back up.
+pub struct InputSet<'a> {
+    state: &'a KeyState,
+    members: Vec<InputBinding<'a>>,
+    mask: u64,
+}
+
+impl<'a> InputSet<'a> {
+    pub fn new<I: IntoIterator<Item = Input>>(state: &'a KeyState, inputs: I) -> Self {
+        Self::new_in(state, InputContext::GLOBAL, inputs)
+    }
+
+    pub fn new_in<I>(state: &'a KeyState, context: InputContext, inputs: I) -> Self
+    where
+        I: IntoIterator<Item = Input>,
+    {
+        let members: Vec<_> = inputs
+            .into_iter()
+            .map(|input| InputBinding::new_in(state, context, input))
+            .collect();
+
+        let mask = members.iter().fold(0u64, |mask, member| {
+            mask | 1u64.wrapping_shl(member.index.try_into().unwrap())
+        });
+
+        Self {
+            state,
+            members,
+            mask,
+        }
+    }
+
+    pub fn pressed(&self) -> bool {
+        self.state.pressed_any(self.mask)
+    }
+
+    pub fn down(&self) -> bool {
+        self.state.down_any(self.mask)
+    }
+
+    pub fn released(&self) -> bool {
+        self.state.released_any(self.mask)
+    }
+
+    pub fn triggered(&self, trigger: Trigger) -> bool {
+        match trigger {
+            Trigger::Press => self.pressed(),
+            Trigger::Hold => self.down(),
+            Trigger::Release => self.released(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+// a key going down or coming back up, in the order the window saw them (see
+// WindowEvents::take_key_events), e.g. for text entry. holding a key down
+// makes most platforms send more presses for it; those have was_repeat set,
+// so "press once" actions can skip them and text entry can keep them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub scancode: ScanCode,
+    pub pressed: bool,
+    pub was_repeat: bool,
+}
+
+// winit (as of 0.20) doesn't say which presses are auto-repeats, so this
+// remembers which keys are down and calls any press of one of them a repeat
+pub(super) struct KeyEvents {
+    held: HashSet<ScanCode>,
+    // the oldest events are dropped once this is full, so nobody has to read
+    // them if they don't care
+    queue: VecDeque<KeyEvent>,
+}
+
+impl KeyEvents {
+    const MAX_QUEUED: usize = 256;
+
+    pub fn new() -> Self {
+        Self {
+            held: HashSet::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, scancode: ScanCode, pressed: bool) -> KeyEvent {
+        let was_repeat = if pressed {
+            !self.held.insert(scancode)
+        } else {
+            self.held.remove(&scancode);
+            false
+        };
+
+        let event = KeyEvent {
+            scancode,
+            pressed,
+            was_repeat,
+        };
+        if self.queue.len() == Self::MAX_QUEUED {
+            self.queue.pop_front();
+        }
+        self.queue.push_back(event);
+
+        event
+    }
+
+    // keys let go of while the window wasn't focused never send a release,
+    // so they'd otherwise count as held (and their next press as a repeat).
+    // queues a release for each of them, and returns which they were.
+    pub fn forget_held(&mut self) -> Vec<ScanCode> {
+        let held: Vec<_> = self.held.iter().copied().collect();
+        for &scancode in &held {
+            self.record(scancode, false);
+        }
+        held
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn take(&mut self) -> Vec<KeyEvent> {
+        self.queue.drain(..).collect()
+    }
+}
+
+#[cfg(any(test, feature = "engine-api"))]
+#[derive(Copy, Clone, PartialEq)]
+pub enum DeadzoneMode {
+    // each axis is zeroed independently, which snaps sticks to the axes
+    Axial,
+    // the stick's distance from center is what matters, which keeps
+    // diagonal movement smooth
+    Radial,
+}
+
+// one analog input (e.g. a trigger) or a pair of them (e.g. a stick)
+#[cfg(any(test, feature = "engine-api"))]
+pub struct AxisBinding<'a> {
+    x: InputBinding<'a>,
+    y: Option<InputBinding<'a>>,
+    deadzone: f32,
+    mode: DeadzoneMode,
+}
+
+#[cfg(any(test, feature = "engine-api"))]
+impl<'a> AxisBinding<'a> {
+    const DEFAULT_DEADZONE: f32 = 0.1;
+    // anything bigger would leave (almost) nothing outside the deadzone
+    const MAX_DEADZONE: f32 = 0.99;
+
+    pub fn new(state: &'a KeyState, axis: Input) -> Self {
+        Self {
+            x: InputBinding::new(state, axis),
+            y: None,
+            deadzone: Self::DEFAULT_DEADZONE,
+            mode: DeadzoneMode::Axial,
+        }
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn stick(state: &'a KeyState, x: Input, y: Input) -> Self {
+        Self {
+            x: InputBinding::new(state, x),
+            y: Some(InputBinding::new(state, y)),
+            deadzone: Self::DEFAULT_DEADZONE,
+            mode: DeadzoneMode::Radial,
+        }
+    }
+
+    // out-of-range deadzones (including NaN) are clamped to [0.0, 0.99]
+    pub fn with_deadzone(mut self, deadzone: f32) -> Self {
+        self.deadzone = deadzone.max(0.0).min(Self::MAX_DEADZONE);
+        self
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn with_deadzone_mode(mut self, mode: DeadzoneMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    // (x, y) after applying the deadzone. single axes always have y = 0.0
+    pub fn value(&self) -> (f32, f32) {
+        let raw = (
+            self.x.value(),
+            self.y.as_ref().map(InputBinding::value).unwrap_or(0.0),
+        );
+
+        apply_deadzone(raw, self.deadzone, self.mode)
+    }
+}
+
+// values outside the deadzone are rescaled so they still start from 0.0 at its
+// edge; otherwise the stick would jump straight from 0.0 to the deadzone size
+#[cfg(any(test, feature = "engine-api"))]
+pub fn apply_deadzone((x, y): (f32, f32), deadzone: f32, mode: DeadzoneMode) -> (f32, f32) {
+    let rescale = |magnitude: f32| ((magnitude - deadzone) / (1.0 - deadzone)).min(1.0);
+
+    match mode {
+        DeadzoneMode::Axial => {
+            let axial = |v: f32| {
+                if v.abs() < deadzone {
+                    0.0
+                } else {
+                    rescale(v.abs()).copysign(v)
+                }
+            };
+
+            (axial(x), axial(y))
+        }
+        DeadzoneMode::Radial => {
+            let magnitude = x.hypot(y);
+            if magnitude < deadzone || magnitude == 0.0 {
+                (0.0, 0.0)
+            } else {
+                let scale = rescale(magnitude) / magnitude;
+                (x * scale, y * scale)
+            }
+        }
+    }
+}
+
 pub struct KeyState {
     state_map: [AtomicCell<Input>; 64],
+    contexts: [AtomicCell<InputContext>; 64],
+    active_context: AtomicCell<InputContext>,
+    analog: [AtomicCell<f32>; 64],
     old_state: AtomicU64,
     state: AtomicU64,
 }
@@ -123,6 +419,9 @@ impl KeyState {
             // TODO: remove arr_macro once Default is generic over array lengths >= 32
             //state_map: [AtomicCell::new(Default::default()); 64],
             state_map: arr![AtomicCell::new(Default::default()); 64],
+            contexts: arr![AtomicCell::new(Default::default()); 64],
+            active_context: AtomicCell::new(Default::default()),
+            analog: arr![AtomicCell::new(0.0); 64],
             old_state: AtomicU64::new(0),
             state: AtomicU64::new(0),
         }
@@ -132,7 +431,49 @@ impl KeyState {
         AtomicCell::new(Arc::new(InputBinding::new(&self, input)))
     }
 
-    fn add(&self, input: Input) -> usize {
+    #[cfg(feature = "engine-api")]
+    pub fn bind_in(&self, context: InputContext, input: Input) -> AtomicCell<Arc<InputBinding>> {
+        AtomicCell::new(Arc::new(InputBinding::new_in(&self, context, input)))
+    }
+
+    // each input takes up a slot of its own, just like binding it separately
+    pub fn bind_any<I: IntoIterator<Item = Input>>(&self, inputs: I) -> InputSet {
+        InputSet::new(&self, inputs)
+    }
+
+    // every input bound right now, along with the slot it's in and the
+    // context it belongs to (e.g. for listing the controls on screen). an
+    // input bound more than once shows up once per binding.
+    #[cfg(feature = "engine-api")]
+    pub fn bindings(&self) -> impl Iterator<Item = (usize, Input, InputContext)> + '_ {
+        let empty_slot = Default::default();
+
+        self.state_map
+            .iter()
+            .zip(self.contexts.iter())
+            .enumerate()
+            .map(|(index, (input, context))| (index, input.load(), context.load()))
+            .filter(move |&(_, input, _)| input != empty_slot)
+    }
+
+    #[cfg(feature = "engine-api")]
+    pub fn context(&self) -> InputContext {
+        self.active_context.load()
+    }
+
+    // inputs keep being tracked in inactive contexts, so a key held down
+    // while switching contexts still counts as down afterwards
+    #[cfg(feature = "engine-api")]
+    pub fn set_context(&self, context: InputContext) {
+        self.active_context.store(context);
+    }
+
+    fn active(&self, index: usize) -> bool {
+        let context = self.contexts[index].load();
+        context == InputContext::GLOBAL || context == self.active_context.load()
+    }
+
+    fn add(&self, input: Input, context: InputContext) -> usize {
         let empty_slot = Default::default();
 
         let (new_index, slot) = self
@@ -142,6 +483,7 @@ impl KeyState {
             .find(|(_, x)| x.load() == empty_slot)
             .unwrap();
 
+        self.contexts[new_index].store(context);
         slot.store(input);
 
         new_index
@@ -151,20 +493,59 @@ impl KeyState {
         let pointer = 1u64.wrapping_shl(index.try_into().unwrap());
         self.state.fetch_and(!pointer, Ordering::Release);
         self.old_state.fetch_and(!pointer, Ordering::Release);
+        self.analog[index].store(0.0);
+        self.contexts[index].store(Default::default());
 
         self.state_map[index].store(Default::default());
     }
 
     fn pressed(&self, index: usize) -> bool {
-        !Self::get(&self.old_state, index) && Self::get(&self.state, index)
+        self.active(index) && !Self::get(&self.old_state, index) && Self::get(&self.state, index)
     }
 
     fn down(&self, index: usize) -> bool {
-        Self::get(&self.state, index)
+        self.active(index) && Self::get(&self.state, index)
     }
 
     fn released(&self, index: usize) -> bool {
-        Self::get(&self.old_state, index) && !Self::get(&self.state, index)
+        self.active(index) && Self::get(&self.old_state, index) && !Self::get(&self.state, index)
+    }
+
+    // the same as the above, but for a set of slots at once (see InputSet)
+
+    fn active_mask(&self, mask: u64) -> u64 {
+        (0..64)
+            .map(|i| 1u64.wrapping_shl(i))
+            .filter(|&pointer| mask & pointer != 0)
+            .filter(|&pointer| self.active(pointer.trailing_zeros() as usize))
+            .fold(0, |active, pointer| active | pointer)
+    }
+
+    fn pressed_any(&self, mask: u64) -> bool {
+        let mask = self.active_mask(mask);
+        !Self::any(&self.old_state, mask) && Self::any(&self.state, mask)
+    }
+
+    fn down_any(&self, mask: u64) -> bool {
+        Self::any(&self.state, self.active_mask(mask))
+    }
+
+    fn released_any(&self, mask: u64) -> bool {
+        let mask = self.active_mask(mask);
+        Self::any(&self.old_state, mask) && !Self::any(&self.state, mask)
+    }
+
+    fn any(state: &AtomicU64, mask: u64) -> bool {
+        state.load(Ordering::Acquire) & mask != 0
+    }
+
+    #[cfg(any(test, feature = "engine-api"))]
+    fn value(&self, index: usize) -> f32 {
+        if self.active(index) {
+            self.analog[index].load()
+        } else {
+            0.0
+        }
     }
 
     fn get(state: &AtomicU64, index: usize) -> bool {
@@ -175,25 +556,153 @@ impl KeyState {
     }
 
     pub fn set(&self, input: Input, pressed: bool) {
-        if let Some(index) = self
+        // the same input may be bound more than once (e.g. to different
+        // actions), so every matching binding has to be updated
+        let pointer = self
             .state_map
             .iter()
             .enumerate()
-            .find(|(_, x)| x.load() == input)
-            .map(|(i, _)| i)
-        {
-            let pointer = 1u64.wrapping_shl(index.try_into().unwrap());
-
-            if pressed {
-                self.state.fetch_or(pointer, Ordering::Release);
-            } else {
-                self.state.fetch_and(!pointer, Ordering::Release);
-            }
+            .filter(|(_, x)| x.load() == input)
+            .fold(0u64, |pointer, (i, _)| {
+                pointer | 1u64.wrapping_shl(i.try_into().unwrap())
+            });
+
+        if pressed {
+            self.state.fetch_or(pointer, Ordering::Release);
+        } else {
+            self.state.fetch_and(!pointer, Ordering::Release);
         }
     }
 
+    pub fn set_axis(&self, input: Input, value: f32) {
+        self.state_map
+            .iter()
+            .zip(self.analog.iter())
+            .filter(|(x, _)| x.load() == input)
+            .for_each(|(_, slot)| slot.store(value));
+    }
+
     pub fn update(&self) {
         let state = self.state.load(Ordering::Acquire);
         self.old_state.store(state, Ordering::Release);
     }
+
+    // which bindings are down, one bit per slot (there are only 64 slots, so
+    // this is all of them). analog values aren't included.
+    #[cfg(feature = "engine-api")]
+    pub fn snapshot(&self) -> u64 {
+        self.state.load(Ordering::Acquire)
+    }
+
+    // replaces the state of every binding, e.g. to play back input recorded
+    // with snapshot(). like events from the window, this should come after
+    // update() so pressed() and released() see the change.
+    #[cfg(feature = "engine-api")]
+    pub fn apply_snapshot(&self, snapshot: u64) {
+        self.state.store(snapshot, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(scancode: ScanCode) -> Input {
+        InputID::Key(scancode).into()
+    }
+
+    #[test]
+    fn inputs_bound_twice_update_both_bindings() {
+        let state = KeyState::new();
+        let jump = InputBinding::new(&state, key(57));
+        let confirm = InputBinding::new(&state, key(57));
+
+        state.update();
+        state.set(key(57), true);
+        assert!(jump.pressed() && confirm.pressed());
+
+        state.update();
+        assert!(jump.down() && confirm.down());
+        assert!(!jump.pressed() && !confirm.pressed());
+
+        state.set(key(57), false);
+        assert!(jump.released() && confirm.released());
+    }
+
+    #[test]
+    fn unbinding_one_leaves_the_other() {
+        let state = KeyState::new();
+        let jump = InputBinding::new(&state, key(57));
+        let confirm = InputBinding::new(&state, key(57));
+        drop(confirm);
+
+        state.update();
+        state.set(key(57), true);
+        assert!(jump.pressed());
+    }
+
+    fn assert_close((x, y): (f32, f32), (expected_x, expected_y): (f32, f32)) {
+        assert!(
+            (x - expected_x).abs() < 1e-6 && (y - expected_y).abs() < 1e-6,
+            "({}, {}) != ({}, {})",
+            x,
+            y,
+            expected_x,
+            expected_y
+        );
+    }
+
+    #[test]
+    fn axial_deadzone() {
+        let axial = |x, y| apply_deadzone((x, y), 0.1, DeadzoneMode::Axial);
+
+        assert_close(axial(0.05, -0.09), (0.0, 0.0));
+        // each axis on its own, so one can be zeroed while the other isn't
+        assert_close(axial(0.55, 0.05), (0.5, 0.0));
+        assert_close(axial(-0.55, -1.0), (-0.5, -1.0));
+        // the edge of the deadzone is where the rescaled range starts
+        assert_close(axial(0.1, -0.1), (0.0, 0.0));
+        assert_close(axial(1.0, 0.0), (1.0, 0.0));
+    }
+
+    #[test]
+    fn radial_deadzone() {
+        let radial = |x, y| apply_deadzone((x, y), 0.1, DeadzoneMode::Radial);
+
+        assert_close(radial(0.0, 0.0), (0.0, 0.0));
+        assert_close(radial(0.06, -0.06), (0.0, 0.0));
+        // the direction is kept, only the distance from center is rescaled
+        assert_close(radial(0.3, -0.4), (0.6 * 0.4 / 0.9, -0.8 * 0.4 / 0.9));
+        assert_close(radial(-0.6, 0.8), (-0.6, 0.8));
+        // past the edge of the stick's range is still full tilt
+        assert_close(radial(1.0, 1.0), (0.5f32.sqrt(), 0.5f32.sqrt()));
+    }
+
+    #[test]
+    fn radial_deadzones_combine_both_axes() {
+        // neither axis is past the deadzone on its own, but together they are
+        let (x, y) = (0.08, 0.08);
+        assert_close(apply_deadzone((x, y), 0.1, DeadzoneMode::Axial), (0.0, 0.0));
+
+        let (x, y) = apply_deadzone((x, y), 0.1, DeadzoneMode::Radial);
+        assert!(x > 0.0 && y > 0.0);
+        assert!((x - y).abs() < 1e-6);
+    }
+
+    #[test]
+    fn out_of_range_deadzones_are_clamped() {
+        let state = KeyState::new();
+        let axis = InputID::Axis(0).into();
+
+        let trigger = AxisBinding::new(&state, axis).with_deadzone(-0.5);
+        state.set_axis(axis, 0.05);
+        assert_close(trigger.value(), (0.05, 0.0));
+
+        // a deadzone of 1.0 or more would divide by zero (or flip the sign)
+        for &deadzone in &[1.0, 1.5, std::f32::NAN] {
+            let trigger = AxisBinding::new(&state, axis).with_deadzone(deadzone);
+            state.set_axis(axis, 1.0);
+            assert_close(trigger.value(), (1.0, 0.0));
+        }
+    }
 }